@@ -67,6 +67,27 @@ impl Aabb {
         bbox
     }
 
+    /// The overlapping region of both boxes, or [`Aabb::EMPTY`] if they are disjoint.
+    pub fn intersection(box0: Aabb, box1: Aabb) -> Self {
+        let x = Range::intersect(box0.x, box1.x);
+        let y = Range::intersect(box0.y, box1.y);
+        let z = Range::intersect(box0.z, box1.z);
+        if x.size() < 0.0 || y.size() < 0.0 || z.size() < 0.0 {
+            Aabb::EMPTY
+        } else {
+            Aabb { x, y, z }
+        }
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        if dx < 0.0 || dy < 0.0 || dz < 0.0 {
+            0.0
+        } else {
+            2.0 * (dx * dy + dy * dz + dz * dx)
+        }
+    }
+
     pub fn interval_at(&self, axis: Axis) -> Range {
         match axis {
             Axis::X => self.x,
@@ -0,0 +1,469 @@
+//! Keyframed node transforms from glTF animations, evaluated at the time of a ray for motion
+//! blur. Times are in seconds, as in glTF.
+
+use std::f32::consts::FRAC_PI_2;
+use std::ops::{Add, Mul};
+
+use glam::{Affine3A, Quat};
+
+use crate::aabb::Aabb;
+use crate::range::Range;
+use crate::vec3::{Point3, Vec3};
+
+/// Number of poses sampled between two keyframes when bounding motion.
+const POSES_PER_KEYFRAME: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Cubic Hermite splines, with an in-tangent and out-tangent stored around every value.
+    CubicSpline,
+}
+
+/// A value that can be interpolated between keyframes.
+pub trait Animatable: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    fn interpolate(self, other: Self, t: f32) -> Self;
+
+    /// Brings a value blended from several keyframes back to a valid one.
+    fn normalized(self) -> Self {
+        self
+    }
+}
+
+impl Animatable for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Animatable for Quat {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.slerp(other, t)
+    }
+
+    fn normalized(self) -> Self {
+        self.normalize()
+    }
+}
+
+/// The values of one animated property at increasing keyframe times.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    interpolation: Interpolation,
+    times: Vec<f32>,
+    values: Vec<T>,
+}
+
+impl<T: Animatable> Track<T> {
+    /// Returns `None` unless there is a value, or three for cubic splines, for every time.
+    pub fn new(interpolation: Interpolation, times: Vec<f32>, values: Vec<T>) -> Option<Self> {
+        let per_key = match interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        (!times.is_empty() && values.len() == times.len() * per_key).then_some(Track {
+            interpolation,
+            times,
+            values,
+        })
+    }
+
+    fn key(&self, i: usize) -> T {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.values[3 * i + 1],
+            _ => self.values[i],
+        }
+    }
+
+    /// The value at `time`, holding the first and last keyframes outside of the track. A NaN
+    /// time gives the first keyframe.
+    pub fn at(&self, time: f32) -> T {
+        let last = self.times.len() - 1;
+        if time <= self.times[0] || time.is_nan() {
+            return self.key(0);
+        }
+        if time >= self.times[last] {
+            return self.key(last);
+        }
+
+        let i = self.times.partition_point(|&t| t <= time) - 1;
+        match self.interpolation {
+            Interpolation::Step => self.key(i),
+            Interpolation::Linear => {
+                let s = (time - self.times[i]) / (self.times[i + 1] - self.times[i]);
+                self.key(i).interpolate(self.key(i + 1), s)
+            }
+            Interpolation::CubicSpline => self.hermite(i, time).0.normalized(),
+        }
+    }
+
+    /// The spline from keyframe `i` to the next at `time`, before normalization, together with
+    /// its derivative with respect to time.
+    fn hermite(&self, i: usize, time: f32) -> (T, T) {
+        let dt = self.times[i + 1] - self.times[i];
+        let s = (time - self.times[i]) / dt;
+        let (v0, b0) = (self.values[3 * i + 1], self.values[3 * i + 2]);
+        let (a1, v1) = (self.values[3 * i + 3], self.values[3 * i + 4]);
+        let (s2, s3) = (s * s, s * s * s);
+        let value = v0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+            + b0 * (dt * (s3 - 2.0 * s2 + s))
+            + v1 * (3.0 * s2 - 2.0 * s3)
+            + a1 * (dt * (s3 - s2));
+        let derivative = (v0 * (6.0 * s2 - 6.0 * s)
+            + b0 * (dt * (3.0 * s2 - 4.0 * s + 1.0))
+            + v1 * (6.0 * s - 6.0 * s2)
+            + a1 * (dt * (3.0 * s2 - 2.0 * s)))
+            * (1.0 / dt);
+        (value, derivative)
+    }
+
+    /// Control points of the cubic Bézier curve a spline follows from `t0` to `t1`, which must
+    /// not be more than one keyframe apart. The curve stays within their convex hull. `None` for
+    /// other interpolations, and outside of the track where the value holds.
+    fn bezier(&self, t0: f32, t1: f32) -> Option<[T; 4]> {
+        let last = self.times.len() - 1;
+        let mid = 0.5 * (t0 + t1);
+        if self.interpolation != Interpolation::CubicSpline
+            || mid <= self.times[0]
+            || mid >= self.times[last]
+            || mid.is_nan()
+        {
+            return None;
+        }
+
+        let i = self.times.partition_point(|&t| t <= mid) - 1;
+        let (p0, d0) = self.hermite(i, t0);
+        let (p3, d3) = self.hermite(i, t1);
+        let h = (t1 - t0) / 3.0;
+        Some([p0, p0 + d0 * h, p3 + d3 * -h, p3])
+    }
+
+    fn time_range(&self) -> Range {
+        Range::new(self.times[0], self.times[self.times.len() - 1])
+    }
+}
+
+/// The transform of a node whose translation, rotation or scale is animated, falling back to
+/// the node's own for properties without a track.
+#[derive(Debug, Clone)]
+pub struct AnimatedTransform {
+    scale: Vec3,
+    rotation: Quat,
+    translation: Vec3,
+    pub scale_track: Option<Track<Vec3>>,
+    pub rotation_track: Option<Track<Quat>>,
+    pub translation_track: Option<Track<Vec3>>,
+}
+
+impl AnimatedTransform {
+    pub fn new(scale: Vec3, rotation: Quat, translation: Vec3) -> Self {
+        AnimatedTransform {
+            scale,
+            rotation,
+            translation,
+            scale_track: None,
+            rotation_track: None,
+            translation_track: None,
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.scale_track.is_some()
+            || self.rotation_track.is_some()
+            || self.translation_track.is_some()
+    }
+
+    fn decomposed_at(&self, time: f32) -> (Vec3, Quat, Vec3) {
+        (
+            self.scale_track.as_ref().map_or(self.scale, |t| t.at(time)),
+            self.rotation_track
+                .as_ref()
+                .map_or(self.rotation, |t| t.at(time)),
+            self.translation_track
+                .as_ref()
+                .map_or(self.translation, |t| t.at(time)),
+        )
+    }
+
+    pub fn at(&self, time: f32) -> Affine3A {
+        let (scale, rotation, translation) = self.decomposed_at(time);
+        Affine3A::from_scale_rotation_translation(scale.into(), rotation, translation.into())
+    }
+
+    /// The times from the first to the last keyframe of any track.
+    pub fn time_range(&self) -> Range {
+        [
+            self.scale_track.as_ref().map(Track::time_range),
+            self.rotation_track.as_ref().map(Track::time_range),
+            self.translation_track.as_ref().map(Track::time_range),
+        ]
+        .into_iter()
+        .flatten()
+        .fold(Range::EMPTY, Range::from_ranges)
+    }
+
+    /// Poses sampled over `shutter`, at its ends, every keyframe and evenly in between, for
+    /// bounding the motion of points with [`MotionBounds`].
+    pub fn motion_bounds(&self, shutter: Range) -> MotionBounds {
+        let mut times = vec![shutter.min, shutter.max];
+        for track_times in [
+            self.scale_track.as_ref().map(|t| &t.times),
+            self.rotation_track.as_ref().map(|t| &t.times),
+            self.translation_track.as_ref().map(|t| &t.times),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            times.extend(track_times.iter().filter(|&&t| shutter.surrounds(t)));
+        }
+        times.sort_by(f32::total_cmp);
+        times.dedup();
+
+        let mut samples = vec![times[0]];
+        for pair in times.windows(2) {
+            samples.extend(
+                (1..=POSES_PER_KEYFRAME)
+                    .map(|i| pair[0] + (pair[1] - pair[0]) * i as f32 / POSES_PER_KEYFRAME as f32),
+            );
+        }
+
+        let poses: Vec<_> = samples.iter().map(|&t| self.decomposed_at(t)).collect();
+        let (mut spread, mut overshoot) = (0.0f32, 0.0f32);
+        for (times, pair) in samples.windows(2).zip(poses.windows(2)) {
+            let (t0, t1) = (times[0], times[1]);
+            let scale = pair[0].0.abs().max(pair[1].0.abs()).max_element();
+
+            // a point rotating by θ between two poses strays at most r (1 - cos θ/2) from the
+            // chord. A rotation spline can turn further, but stays in the cone its control points
+            // span around the first pose, so a point strays at most 2 r sin φ from it for a cone
+            // of half-angle φ, and twice that from the chord.
+            let angle = pair[0].1.angle_between(pair[1].1);
+            let mut rotation = 2.0 * (1.0 - (angle / 2.0).cos());
+            if let Some(c) = self.rotation_track.as_ref().and_then(|t| t.bezier(t0, t1)) {
+                let cone = c[1..]
+                    .iter()
+                    .map(|q| (q.dot(c[0]) / (q.length() * c[0].length())).clamp(-1.0, 1.0))
+                    .fold(1.0, f32::min)
+                    .acos();
+                rotation = rotation.max(if cone < FRAC_PI_2 {
+                    4.0 * cone.sin()
+                } else {
+                    4.0
+                });
+            }
+
+            let scale_overshoot = self
+                .scale_track
+                .as_ref()
+                .and_then(|t| t.bezier(t0, t1))
+                .map_or(0.0, |c| chord_distance(&c));
+            let translation_overshoot = self
+                .translation_track
+                .as_ref()
+                .and_then(|t| t.bezier(t0, t1))
+                .map_or(0.0, |c| chord_distance(&c));
+
+            spread = spread.max((scale + scale_overshoot) * rotation + scale_overshoot);
+            overshoot = overshoot.max(translation_overshoot);
+        }
+
+        MotionBounds {
+            poses: poses
+                .into_iter()
+                .map(|(s, r, t)| Affine3A::from_scale_rotation_translation(s.into(), r, t.into()))
+                .collect(),
+            spread,
+            overshoot,
+        }
+    }
+}
+
+/// How far a cubic Bézier curve can stray from the chord between its end points, which is at
+/// most as far as its inner control points.
+fn chord_distance(c: &[Vec3; 4]) -> f32 {
+    let chord = c[3] - c[0];
+    let distance = |p: Vec3| {
+        let t = if chord.length_squared() > 0.0 {
+            ((p - c[0]).dot(chord) / chord.length_squared()).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        (p - (c[0] + chord * t)).length()
+    };
+    distance(c[1]).max(distance(c[2]))
+}
+
+/// Poses of an [`AnimatedTransform`] over some time, from which the space swept by moving
+/// points can be bounded. The bounds are conservative: between two poses, rotation is accounted
+/// for by the sagitta of its arc, and cubic splines by the hull of their Bézier control points.
+#[derive(Debug, Clone)]
+pub struct MotionBounds {
+    poses: Vec<Affine3A>,
+    /// Distance a point at unit distance from the origin can stray from a straight line
+    /// between two poses by rotating or by the overshoot of a scale spline.
+    spread: f32,
+    /// Distance a translation spline can overshoot the straight line between two poses.
+    overshoot: f32,
+}
+
+impl MotionBounds {
+    /// The box enclosing `points` in object space while they move.
+    pub fn enclose(&self, points: &[Point3]) -> Aabb {
+        let mut bbox = Aabb::EMPTY;
+        for pose in &self.poses {
+            for &p in points {
+                let p = pose.transform_point3a(p);
+                bbox = Aabb::from_boxes(bbox, Aabb::from_points(p, p));
+            }
+        }
+
+        let radius = points.iter().map(|p| p.length()).fold(0.0, f32::max);
+        let padding = self.spread * radius + self.overshoot;
+        Aabb::new(
+            bbox.x.expand(padding),
+            bbox.y.expand(padding),
+            bbox.z.expand(padding),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use glam::Quat;
+
+    use super::{AnimatedTransform, Interpolation, Track};
+    use crate::range::Range;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_animated_transform() {
+        let mut transform = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        transform.translation_track = Track::new(
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)],
+        );
+        transform.rotation_track = Track::new(
+            Interpolation::Step,
+            vec![0.0, 0.5],
+            vec![Quat::IDENTITY, Quat::from_rotation_z(PI)],
+        );
+        let range = transform.time_range();
+        assert_eq!((range.min, range.max), (0.0, 1.0));
+
+        let p = Point3::new(1.0, 0.0, 0.0);
+        let at = |t| transform.at(t).transform_point3a(p);
+        assert!((at(0.25) - Point3::new(1.5, 0.0, 0.0)).length() < 1e-5);
+        assert!((at(0.75) - Point3::new(0.5, 0.0, 0.0)).length() < 1e-5);
+        assert!((at(2.0) - Point3::new(1.0, 0.0, 0.0)).length() < 1e-5);
+        // a NaN time holds the first keyframe rather than indexing before it
+        assert!((at(f32::NAN) - p).length() < 1e-5);
+
+        // the bounds cover every pose in between, not just the keyframes
+        let bbox = transform.motion_bounds(Range::new(0.0, 1.0)).enclose(&[p]);
+        for i in 0..=100 {
+            let q = at(i as f32 / 100.0);
+            assert!(bbox.x.contains(q.x) && bbox.y.contains(q.y), "{q} outside");
+        }
+
+        // a quarter turn bulges out of the box around its ends
+        let mut spin = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        spin.rotation_track = Track::new(
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![Quat::IDENTITY, Quat::from_rotation_z(PI / 2.0)],
+        );
+        let bbox = spin.motion_bounds(Range::new(0.0, 1.0)).enclose(&[p]);
+        for i in 0..=100 {
+            let q = spin.at(i as f32 / 100.0).transform_point3a(p);
+            assert!(bbox.x.contains(q.x) && bbox.y.contains(q.y), "{q} outside");
+        }
+    }
+
+    #[test]
+    fn test_cubic_spline() {
+        // keys at 1 and 3 seconds, stored as in-tangent, value, out-tangent
+        let track = Track::new(
+            Interpolation::CubicSpline,
+            vec![1.0, 3.0],
+            vec![
+                Vec3::ZERO,
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(2.0, 1.0, 0.0),
+                Vec3::new(-1.0, 0.0, 0.0),
+                Vec3::new(3.0, 0.0, 0.0),
+                Vec3::ZERO,
+            ],
+        )
+        .unwrap();
+        assert_eq!(track.at(0.0), Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(track.at(4.0), Vec3::new(3.0, 0.0, 0.0));
+
+        // halfway, the Hermite basis weighs the values by 1/2 and the tangents, scaled by the
+        // 2 seconds between the keys, by 1/8 and -1/8
+        let mid = 0.5 * Vec3::new(1.0, 0.0, 0.0)
+            + 0.5 * Vec3::new(3.0, 0.0, 0.0)
+            + 0.125 * 2.0 * Vec3::new(2.0, 1.0, 0.0)
+            - 0.125 * 2.0 * Vec3::new(-1.0, 0.0, 0.0);
+        assert!((track.at(2.0) - mid).length() < 1e-6);
+
+        // at a quarter: h00 = 27/32, h10 = 9/64, h01 = 5/32, h11 = -3/64
+        let quarter = Vec3::new(
+            27.0 / 32.0 + 2.0 * 2.0 * 9.0 / 64.0 + 3.0 * 5.0 / 32.0 + 2.0 * 3.0 / 64.0,
+            2.0 * 9.0 / 64.0,
+            0.0,
+        );
+        assert!((track.at(1.5) - quarter).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_cubic_overshoot_is_bounded() {
+        // steep tangents make both splines swing far past their keyframes
+        let tangent = Vec3::new(0.0, 20.0, 0.0);
+        let mut shift = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        shift.translation_track = Track::new(
+            Interpolation::CubicSpline,
+            vec![0.0, 1.0],
+            vec![
+                Vec3::ZERO,
+                Vec3::ZERO,
+                tangent,
+                tangent,
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::ZERO,
+            ],
+        );
+        let spin = Quat::from_xyzw(0.0, 0.0, 8.0, 0.0);
+        let mut turn = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        turn.rotation_track = Track::new(
+            Interpolation::CubicSpline,
+            vec![0.0, 1.0],
+            vec![
+                Quat::IDENTITY * 0.0,
+                Quat::IDENTITY,
+                spin,
+                spin,
+                Quat::IDENTITY,
+                Quat::IDENTITY * 0.0,
+            ],
+        );
+
+        let p = Point3::new(1.0, 0.0, 0.0);
+        for transform in [shift, turn] {
+            for shutter in [Range::new(0.0, 1.0), Range::new(0.2, 0.7)] {
+                let bbox = transform.motion_bounds(shutter).enclose(&[p]);
+                for i in 0..=100 {
+                    let t = shutter.min + (shutter.max - shutter.min) * i as f32 / 100.0;
+                    let q = transform.at(t).transform_point3a(p);
+                    assert!(
+                        bbox.x.contains(q.x) && bbox.y.contains(q.y) && bbox.z.contains(q.z),
+                        "{q} at {t} outside"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::time::Instant;
 
 use ordered_float::OrderedFloat;
 use rayon::prelude::*;
-use tracing::info;
+use tracing::{info, trace};
 
 use crate::aabb::Aabb;
 use crate::object::{get_id, HitRecord, Hittable, Object};
@@ -61,20 +61,20 @@ impl FlatBvhNode {
         }
     }
 
-    pub fn is_valid(&self, nodes: &[FlatBvhNode], count: usize) -> bool {
+    pub fn is_valid(&self, nodes: &[FlatBvhNode]) -> bool {
         match self {
             FlatBvhNode::Leaf { .. } => true,
             FlatBvhNode::Interior { left, right, .. } => {
                 let left_valid = left
                     .and_then(|idx| nodes.get(idx))
-                    .map(|n| n.is_valid(nodes, count + 1))
+                    .map(|n| n.is_valid(nodes))
                     .unwrap_or(false);
-                info!("left {:?} valid: {}", left, left_valid);
+                trace!("left {:?} valid: {}", left, left_valid);
                 let right_valid = right
                     .and_then(|idx| nodes.get(idx))
-                    .map(|n| n.is_valid(nodes, count + 1))
+                    .map(|n| n.is_valid(nodes))
                     .unwrap_or(false);
-                info!("right {:?} valid: {}", right, right_valid);
+                trace!("right {:?} valid: {}", right, right_valid);
 
                 left_valid && right_valid
             }
@@ -82,12 +82,25 @@ impl FlatBvhNode {
     }
 
     pub fn hit(&self, ray: &Ray, hit_range: Range, nodes: &[FlatBvhNode]) -> Option<HitRecord> {
+        self.hit_observed(ray, hit_range, nodes, &mut ())
+    }
+
+    /// Same as [`FlatBvhNode::hit`], reporting the work done along the way to `observer`.
+    pub fn hit_observed(
+        &self,
+        ray: &Ray,
+        hit_range: Range,
+        nodes: &[FlatBvhNode],
+        observer: &mut impl TraversalObserver,
+    ) -> Option<HitRecord> {
+        observer.node_visited();
         match self {
             FlatBvhNode::Interior { left, right, bbox } => {
                 if !bbox.hit(ray, hit_range) {
                     return None;
                 }
-                let hit_left = left.and_then(|idx| nodes[idx].hit(ray, hit_range, nodes));
+                let hit_left =
+                    left.and_then(|idx| nodes[idx].hit_observed(ray, hit_range, nodes, observer));
                 let range = Range::new(
                     hit_range.min,
                     hit_left
@@ -95,7 +108,8 @@ impl FlatBvhNode {
                         .map(|h| h.distance)
                         .unwrap_or(hit_range.max),
                 );
-                let hit_right = right.and_then(|idx| nodes[idx].hit(ray, range, nodes));
+                let hit_right =
+                    right.and_then(|idx| nodes[idx].hit_observed(ray, range, nodes, observer));
 
                 hit_right.or(hit_left)
             }
@@ -105,12 +119,21 @@ impl FlatBvhNode {
                     return None;
                 }
 
+                observer.primitives_tested(object.len());
                 object.hit(ray, hit_range)
             }
         }
     }
 }
 
+/// Hooks called while traversing a [`FlatBvhTree`]; the unit type ignores them.
+pub trait TraversalObserver {
+    fn node_visited(&mut self) {}
+    fn primitives_tested(&mut self, _count: usize) {}
+}
+
+impl TraversalObserver for () {}
+
 #[derive(Debug)]
 pub struct FlatBvhTree {
     nodes: Vec<FlatBvhNode>,
@@ -127,7 +150,7 @@ fn flatten_tree(node: BvhNode, node_list: &mut Vec<FlatBvhNode>, offset: &mut us
             node_list[node_offset] = FlatBvhNode::Interior {
                 left: None,
                 right: None,
-                bbox: bbox,
+                bbox,
             };
             let left_index = flatten_tree(*left, node_list, offset);
             let right_index = flatten_tree(*right, node_list, offset);
@@ -138,12 +161,12 @@ fn flatten_tree(node: BvhNode, node_list: &mut Vec<FlatBvhNode>, offset: &mut us
             // info!("leaf: node offset: {node_offset}");
             node_list[node_offset] = FlatBvhNode::Leaf {
                 object: *object,
-                bbox: bbox,
+                bbox,
             };
         }
     }
 
-    *offset
+    node_offset
 }
 
 impl FlatBvhTree {
@@ -159,31 +182,68 @@ impl FlatBvhTree {
             });
         }
         flatten_tree(root, &mut nodes, &mut index);
+        nodes.truncate(index);
+
+        Self { nodes }
+    }
 
+    pub fn from_nodes(nodes: Vec<FlatBvhNode>) -> Self {
         Self { nodes }
     }
 
+    pub fn nodes(&self) -> &[FlatBvhNode] {
+        &self.nodes
+    }
+
     pub fn is_valid(&self) -> bool {
         let root_node_is_interior = self.nodes.len() > 1 && self.nodes[0].is_interior();
-        info!("root node is interior: {root_node_is_interior}");
-        root_node_is_interior && self.nodes[0].is_valid(&self.nodes, 0)
+        trace!("root node is interior: {root_node_is_interior}");
+        root_node_is_interior && self.nodes[0].is_valid(&self.nodes)
     }
 
     pub fn len(&self) -> usize {
         self.nodes.len()
     }
+
+    pub fn hit_observed(
+        &self,
+        ray: &Ray,
+        hit_range: Range,
+        observer: &mut impl TraversalObserver,
+    ) -> Option<HitRecord> {
+        self.nodes[0].hit_observed(ray, hit_range, &self.nodes, observer)
+    }
+
+    /// Recomputes the bounds of every node from `leaf_bounds`, for objects that moved, while
+    /// keeping the structure of the tree. Children always come after their parent, so a single
+    /// backwards pass updates them before it.
+    pub fn refit(&mut self, mut leaf_bounds: impl FnMut(&mut Object) -> Aabb) {
+        for i in (0..self.nodes.len()).rev() {
+            let bounds = match &mut self.nodes[i] {
+                FlatBvhNode::Leaf { object, .. } => leaf_bounds(object),
+                &mut FlatBvhNode::Interior { left, right, .. } => {
+                    let child =
+                        |c: Option<usize>| c.map_or(Aabb::EMPTY, |c| self.nodes[c].bounding_box());
+                    Aabb::from_boxes(child(left), child(right))
+                }
+            };
+            match &mut self.nodes[i] {
+                FlatBvhNode::Leaf { bbox, .. } | FlatBvhNode::Interior { bbox, .. } => {
+                    *bbox = bounds
+                }
+            }
+        }
+    }
 }
 
 impl Hittable for FlatBvhTree {
     fn hit(&self, ray: &Ray, hit_range: Range) -> Option<HitRecord> {
-        let root = &self.nodes[0];
-
-        root.hit(ray, hit_range, &self.nodes)
+        self.hit_observed(ray, hit_range, &mut ())
     }
 
     fn bounding_box(&self) -> Aabb {
         self.nodes
-            .get(0)
+            .first()
             .map(|node| match node {
                 FlatBvhNode::Leaf { bbox, .. } => *bbox,
                 FlatBvhNode::Interior { bbox, .. } => *bbox,
@@ -290,6 +350,23 @@ impl BvhNode {
             BvhNode::Leaf { .. } => 1,
         }
     }
+
+    /// Recomputes the bounds of every node from `leaf_bounds`, keeping the structure of the
+    /// tree, and returns the new bounds of this node.
+    pub fn refit(&mut self, leaf_bounds: &mut impl FnMut(&mut Object) -> Aabb) -> Aabb {
+        match self {
+            BvhNode::Interior {
+                left, right, bbox, ..
+            } => {
+                *bbox = Aabb::from_boxes(left.refit(leaf_bounds), right.refit(leaf_bounds));
+                *bbox
+            }
+            BvhNode::Leaf { object, bbox, .. } => {
+                *bbox = leaf_bounds(object);
+                *bbox
+            }
+        }
+    }
 }
 
 impl Hittable for BvhNode {
@@ -334,6 +411,9 @@ mod tests {
     use tracing_test::traced_test;
 
     use super::{BvhNode, FlatBvhTree};
+    use crate::aabb::Aabb;
+    use crate::object::Hittable;
+    use crate::range::Range;
     use crate::{scene, Result};
 
     #[test]
@@ -347,4 +427,26 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_refit_moves_bounds() -> Result<()> {
+        let scene = scene::load_from_gltf("./assets/cornell.gltf")?;
+        let mut tree = FlatBvhTree::from_tree(BvhNode::from_object(scene.root_object));
+        let before = tree.bounding_box();
+
+        tree.refit(|object| {
+            let bbox = object.bounding_box();
+            Aabb::new(
+                Range::new(bbox.x.min + 1.0, bbox.x.max + 1.0),
+                bbox.y,
+                bbox.z,
+            )
+        });
+        let after = tree.bounding_box();
+        assert!((after.x.min - before.x.min - 1.0).abs() < 1e-4);
+        assert!((after.x.max - before.x.max - 1.0).abs() < 1e-4);
+        assert_eq!(after.y.max, before.y.max);
+
+        Ok(())
+    }
 }
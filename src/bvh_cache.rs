@@ -0,0 +1,308 @@
+//! On-disk cache for flattened BVHs.
+//!
+//! Layout (all values little endian):
+//!
+//! | field        | type                   |
+//! |--------------|------------------------|
+//! | magic        | `[u8; 8]` (`RTBVHC\0\0`) |
+//! | version      | `u32`                  |
+//! | mesh count   | `u32`                  |
+//! | scene hash   | `u64`                  |
+//! | shutter      | `[f32; 2]`             |
+//! | face counts  | `u32` per mesh         |
+//! | node count   | `u32`                  |
+//! | nodes        | [`NODE_SIZE`] bytes each |
+//!
+//! Every node is `kind: u32, a: u32, b: u32, bbox: [f32; 6]` (min/max for x, y, z). Interior
+//! nodes store their child indices in `a` and `b`, leaves store the mesh index and face index
+//! of their triangle. Leaves for moving meshes only store the mesh index, and are taken from
+//! the scene, whose meshes were bounded over the shutter the cache was written for.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{bail, eyre};
+use memmap2::Mmap;
+
+use crate::aabb::Aabb;
+use crate::bvh::{FlatBvhNode, FlatBvhTree};
+use crate::object::{Hittable, Object};
+use crate::range::Range;
+use crate::scene::SceneDescription;
+use crate::Result;
+
+const MAGIC: &[u8; 8] = b"RTBVHC\0\0";
+const VERSION: u32 = 2;
+const NODE_SIZE: usize = 3 * 4 + 6 * 4;
+const NO_CHILD: u32 = u32::MAX;
+
+const KIND_INTERIOR: u32 = 0;
+const KIND_LEAF: u32 = 1;
+const KIND_MOVING_MESH: u32 = 2;
+
+/// FNV-1a hash over the glTF document and all of its buffers.
+pub fn scene_hash(document: &[u8], buffers: &[gltf::buffer::Data]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut feed = |bytes: &[u8]| {
+        for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    };
+
+    feed(document);
+    for buffer in buffers {
+        feed(&(buffer.len() as u64).to_le_bytes());
+        feed(buffer);
+    }
+
+    hash
+}
+
+pub fn save(path: impl AsRef<Path>, tree: &FlatBvhTree, scene: &SceneDescription) -> Result<()> {
+    // write next to the cache and move it into place, so that readers never see a partial file
+    let path = path.as_ref();
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp);
+
+    let result = write(&temp, tree, scene).and_then(|()| Ok(std::fs::rename(&temp, path)?));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write(path: &Path, tree: &FlatBvhTree, scene: &SceneDescription) -> Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&(scene.meshes.len() as u32).to_le_bytes())?;
+    out.write_all(&scene.source_hash.to_le_bytes())?;
+    out.write_all(&scene.shutter.min.to_le_bytes())?;
+    out.write_all(&scene.shutter.max.to_le_bytes())?;
+    for mesh in &scene.meshes {
+        out.write_all(&(mesh.face_count() as u32).to_le_bytes())?;
+    }
+
+    out.write_all(&(tree.len() as u32).to_le_bytes())?;
+    for node in tree.nodes() {
+        let (kind, a, b) = match node {
+            FlatBvhNode::Interior { left, right, .. } => (
+                KIND_INTERIOR,
+                left.map(|i| i as u32).unwrap_or(NO_CHILD),
+                right.map(|i| i as u32).unwrap_or(NO_CHILD),
+            ),
+            FlatBvhNode::Leaf {
+                object: Object::TriangleRef(triangle),
+                ..
+            } => (KIND_LEAF, triangle.mesh_index(), triangle.face_index()),
+            FlatBvhNode::Leaf {
+                object: Object::MovingMesh(mesh),
+                ..
+            } => (KIND_MOVING_MESH, mesh.mesh_index(), 0),
+            FlatBvhNode::Leaf { object, .. } => {
+                bail!("cannot cache BVH leaf of type {}", object.name())
+            }
+        };
+        let bbox = node.bounding_box();
+
+        for value in [kind, a, b] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for value in [
+            bbox.x.min, bbox.x.max, bbox.y.min, bbox.y.max, bbox.z.min, bbox.z.max,
+        ] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+    }
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Loads the cached BVH for `scene`. Returns `Ok(None)` if there is no cache file, it was
+/// written for a different scene, shutter or format version, or its nodes don't form a tree.
+pub fn load(path: impl AsRef<Path>, scene: &SceneDescription) -> Result<Option<FlatBvhTree>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    // SAFETY: the cache is only written by `save`, and we validate every read against the
+    // length of the mapping.
+    let data = unsafe { Mmap::map(&file)? };
+    let mut reader = Reader {
+        data: &data,
+        offset: 0,
+    };
+
+    if reader.bytes(MAGIC.len())? != MAGIC || reader.u32()? != VERSION {
+        return Ok(None);
+    }
+    let mesh_count = reader.u32()? as usize;
+    if reader.u64()? != scene.source_hash || mesh_count != scene.meshes.len() {
+        return Ok(None);
+    }
+    if reader.f32()? != scene.shutter.min || reader.f32()? != scene.shutter.max {
+        return Ok(None);
+    }
+    for mesh in &scene.meshes {
+        if reader.u32()? as usize != mesh.face_count() {
+            return Ok(None);
+        }
+    }
+
+    let moving_meshes: Vec<_> = match &scene.root_object {
+        Object::World(world) => world
+            .objects
+            .iter()
+            .filter_map(|object| match object {
+                Object::MovingMesh(mesh) => Some(mesh),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    let node_count = reader.u32()? as usize;
+    if reader.remaining() < node_count * NODE_SIZE {
+        bail!("BVH cache is truncated: expected {node_count} nodes");
+    }
+    let mut nodes = Vec::with_capacity(node_count);
+    for _ in 0..node_count {
+        let kind = reader.u32()?;
+        let a = reader.u32()?;
+        let b = reader.u32()?;
+        let bbox = Aabb {
+            x: Range::new(reader.f32()?, reader.f32()?),
+            y: Range::new(reader.f32()?, reader.f32()?),
+            z: Range::new(reader.f32()?, reader.f32()?),
+        };
+
+        let node = match kind {
+            KIND_INTERIOR => {
+                let child = |i: u32| {
+                    if i == NO_CHILD || i as usize >= node_count {
+                        None
+                    } else {
+                        Some(i as usize)
+                    }
+                };
+                FlatBvhNode::Interior {
+                    left: child(a),
+                    right: child(b),
+                    bbox,
+                }
+            }
+            KIND_LEAF => {
+                let mesh = scene
+                    .meshes
+                    .get(a as usize)
+                    .ok_or_else(|| eyre!("cached BVH references unknown mesh {a}"))?;
+                if b as usize >= mesh.face_count() {
+                    bail!("cached BVH references unknown face {b} of mesh {a}");
+                }
+                FlatBvhNode::Leaf {
+                    object: Object::TriangleRef(mesh.face(b)),
+                    bbox,
+                }
+            }
+            KIND_MOVING_MESH => {
+                let mesh = moving_meshes
+                    .iter()
+                    .find(|mesh| mesh.mesh_index() == a)
+                    .ok_or_else(|| eyre!("cached BVH references unknown moving mesh {a}"))?;
+                FlatBvhNode::Leaf {
+                    object: Object::MovingMesh((*mesh).clone()),
+                    bbox,
+                }
+            }
+            _ => bail!("unknown BVH node kind {kind}"),
+        };
+        nodes.push(node);
+    }
+
+    let tree = FlatBvhTree::from_nodes(nodes);
+    Ok(tree.is_valid().then_some(tree))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| eyre!("BVH cache is truncated at offset {}", self.offset))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NODE_SIZE, NO_CHILD};
+    use crate::range::Range;
+    use crate::{bvh_cache, scene, Result};
+
+    #[test]
+    fn test_cache_round_trip() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("raytracer-bvhcache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("cornell.bvhcache");
+
+        let built = scene::load_from_gltf("./assets/cornell.gltf")?.build_bvh_cached(&path);
+        let scene = scene::load_from_gltf("./assets/cornell.gltf")?;
+        let cached = bvh_cache::load(&path, &scene)?.expect("cache must be fresh");
+        assert!(cached.is_valid());
+        assert_eq!(cached.len(), built.root_object.len());
+
+        let mut other_shutter = scene::load_from_gltf("./assets/cornell.gltf")?;
+        other_shutter.set_shutter(Range::new(0.0, 0.5));
+        assert!(bvh_cache::load(&path, &other_shutter)?.is_none());
+
+        // a root without children no longer makes a tree
+        let mut data = std::fs::read(&path)?;
+        let root = data.len() - cached.len() * NODE_SIZE;
+        data[root + 4..root + 8].copy_from_slice(&NO_CHILD.to_le_bytes());
+        let corrupt = dir.join("corrupt.bvhcache");
+        std::fs::write(&corrupt, data)?;
+        assert!(bvh_cache::load(&corrupt, &scene)?.is_none());
+
+        let stale = scene::SceneDescription {
+            source_hash: scene.source_hash ^ 1,
+            ..scene
+        };
+        assert!(bvh_cache::load(&path, &stale)?.is_none());
+
+        // the cache was moved into place rather than leaving its temporary file behind
+        for entry in std::fs::read_dir(&dir)? {
+            assert_ne!(entry?.path().extension(), Some("tmp".as_ref()));
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
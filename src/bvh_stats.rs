@@ -0,0 +1,239 @@
+//! Quality metrics for flattened BVHs, used by the `bvh-stats` command to compare builders.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Add;
+
+use rayon::prelude::*;
+
+use crate::aabb::Aabb;
+use crate::bvh::{FlatBvhNode, FlatBvhTree, TraversalObserver};
+use crate::camera::Camera;
+use crate::object::{HitRecord, Hittable};
+use crate::range::Range;
+use crate::ray::Ray;
+
+/// Relative cost of a node traversal step in the SAH estimate.
+const TRAVERSAL_COST: f32 = 1.0;
+/// Relative cost of a single primitive intersection test in the SAH estimate.
+const INTERSECTION_COST: f32 = 1.0;
+
+#[derive(Debug, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub interior_count: usize,
+    pub leaf_count: usize,
+    /// Number of nodes at each depth, the root being at depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// Number of leaves by the number of primitives they contain.
+    pub leaf_sizes: BTreeMap<usize, usize>,
+    pub sah_cost: f32,
+    /// Surface area of the overlap between sibling boxes relative to their parent, averaged over
+    /// all interior nodes.
+    pub mean_overlap: f32,
+    pub max_overlap: f32,
+}
+
+impl BvhStats {
+    pub fn collect(tree: &FlatBvhTree) -> Self {
+        let mut stats = BvhStats::default();
+        let nodes = tree.nodes();
+        let Some(root) = nodes.first() else {
+            return stats;
+        };
+        let root_area = root.bounding_box().surface_area();
+
+        let mut overlap_sum = 0.0;
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &nodes[index];
+            let area_ratio = if root_area > 0.0 {
+                node.bounding_box().surface_area() / root_area
+            } else {
+                1.0
+            };
+
+            stats.node_count += 1;
+            if stats.depth_histogram.len() <= depth {
+                stats.depth_histogram.resize(depth + 1, 0);
+            }
+            stats.depth_histogram[depth] += 1;
+
+            match node {
+                FlatBvhNode::Interior { left, right, bbox } => {
+                    stats.interior_count += 1;
+                    stats.sah_cost += area_ratio * TRAVERSAL_COST;
+
+                    if let (Some(l), Some(r)) = (left, right) {
+                        let overlap =
+                            Aabb::intersection(nodes[*l].bounding_box(), nodes[*r].bounding_box());
+                        let parent_area = bbox.surface_area();
+                        if parent_area > 0.0 {
+                            let ratio = overlap.surface_area() / parent_area;
+                            overlap_sum += ratio;
+                            stats.max_overlap = stats.max_overlap.max(ratio);
+                        }
+                    }
+
+                    stack.extend(left.iter().chain(right.iter()).map(|i| (*i, depth + 1)));
+                }
+                FlatBvhNode::Leaf { object, .. } => {
+                    let size = object.len();
+                    stats.leaf_count += 1;
+                    stats.sah_cost += area_ratio * INTERSECTION_COST * size as f32;
+                    *stats.leaf_sizes.entry(size).or_default() += 1;
+                }
+            }
+        }
+
+        if stats.interior_count > 0 {
+            stats.mean_overlap = overlap_sum / stats.interior_count as f32;
+        }
+
+        stats
+    }
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "nodes: {} ({} interior, {} leaves)",
+            self.node_count, self.interior_count, self.leaf_count
+        )?;
+        writeln!(
+            f,
+            "max depth: {}",
+            self.depth_histogram.len().saturating_sub(1)
+        )?;
+        writeln!(f, "depth histogram:")?;
+        for (depth, count) in self.depth_histogram.iter().enumerate() {
+            writeln!(f, "  {depth:>4}: {count}")?;
+        }
+        writeln!(f, "leaf sizes:")?;
+        for (size, count) in &self.leaf_sizes {
+            writeln!(f, "  {size:>4}: {count}")?;
+        }
+        writeln!(f, "SAH cost: {:.3}", self.sah_cost)?;
+        write!(
+            f,
+            "sibling overlap: mean {:.2}%, max {:.2}%",
+            self.mean_overlap * 100.0,
+            self.max_overlap * 100.0
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraversalStats {
+    pub rays: u64,
+    pub hits: u64,
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+impl TraversalStats {
+    /// Casts one primary ray per sampled pixel, spreading `ray_count` samples evenly over the
+    /// image.
+    pub fn collect(
+        tree: &FlatBvhTree,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        ray_count: u32,
+    ) -> Self {
+        let pixel_count = width as u64 * height as u64;
+        let range = Range::new(camera.z_near, camera.z_far);
+
+        (0..ray_count as u64)
+            .into_par_iter()
+            .map(|k| {
+                let index = k * pixel_count / ray_count as u64;
+                let Some(ray) =
+                    camera.get_ray((index % width as u64) as u32, (index / width as u64) as u32)
+                else {
+                    return TraversalStats::default();
+                };
+                let mut stats = TraversalStats {
+                    rays: 1,
+                    ..Default::default()
+                };
+                if tree.hit_observed(&ray, range, &mut stats).is_some() {
+                    stats.hits += 1;
+                }
+                stats
+            })
+            .reduce(TraversalStats::default, |a, b| a + b)
+    }
+}
+
+impl Add for TraversalStats {
+    type Output = TraversalStats;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        TraversalStats {
+            rays: self.rays + rhs.rays,
+            hits: self.hits + rhs.hits,
+            nodes_visited: self.nodes_visited + rhs.nodes_visited,
+            primitives_tested: self.primitives_tested + rhs.primitives_tested,
+        }
+    }
+}
+
+impl fmt::Display for TraversalStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rays = self.rays.max(1) as f64;
+        writeln!(f, "primary rays: {} ({} hits)", self.rays, self.hits)?;
+        writeln!(
+            f,
+            "nodes visited per ray: {:.2}",
+            self.nodes_visited as f64 / rays
+        )?;
+        write!(
+            f,
+            "primitives tested per ray: {:.2}",
+            self.primitives_tested as f64 / rays
+        )
+    }
+}
+
+impl TraversalObserver for TraversalStats {
+    fn node_visited(&mut self) {
+        self.nodes_visited += 1;
+    }
+
+    fn primitives_tested(&mut self, count: usize) {
+        self.primitives_tested += count as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BvhStats, TraversalStats};
+    use crate::bvh::BvhType;
+    use crate::camera::Camera;
+    use crate::object::Object;
+    use crate::{scene, Result};
+
+    #[test]
+    fn test_cornell_stats() -> Result<()> {
+        let scene = scene::load_from_gltf("./assets/cornell.gltf")?.build_bvh(BvhType::Flat);
+        let Object::FlatBvhTree(tree) = &scene.root_object else {
+            panic!("expected a flat BVH");
+        };
+
+        let stats = BvhStats::collect(tree);
+        assert_eq!(stats.node_count, tree.len());
+        assert_eq!(stats.leaf_count, stats.interior_count + 1);
+        assert_eq!(stats.depth_histogram[0], 1);
+        assert!(stats.sah_cost > 0.0);
+
+        let camera = Camera::new(scene.camera(0), 64, 64);
+        let traversal = TraversalStats::collect(tree, &camera, 64, 64, 256);
+        assert_eq!(traversal.rays, 256);
+        assert!(traversal.hits > 0);
+        assert!(traversal.nodes_visited >= traversal.rays);
+
+        Ok(())
+    }
+}
@@ -1,94 +1,724 @@
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use enum_dispatch::enum_dispatch;
+use glam::{Affine3A, Vec2};
+
+use crate::animation::AnimatedTransform;
+use crate::math;
+use crate::medium::Medium;
 use crate::random::random;
-use crate::ray::Ray;
-use crate::scene::CameraSettings;
+use crate::range::Range;
+use crate::ray::{Ray, RayDifferentials};
+use crate::sample;
+use crate::scene::{CameraProjection, CameraSettings};
 use crate::vec3::{self, Point3, Vec3};
 
 pub struct Camera {
-    center: Point3,
-    pixel_00_loc: Point3,
-    pixel_delta_u: Vec3,
-    pixel_delta_v: Vec3,
-    defocus_disk_u: Vec3,
-    defocus_disk_v: Vec3,
-    defocus_angle: f32,
+    projection: Projection,
+    /// Times in seconds at which the shutter opens and closes.
+    shutter: Range,
+    /// The transform of a moving camera, with the transform from world space into the camera
+    /// space of its resting position, in which the projection generates rays.
+    motion: Option<(Arc<AnimatedTransform>, Affine3A)>,
 
     pub z_near: f32,
     pub z_far: f32,
+    /// The medium surrounding the camera, which camera rays start out in.
+    pub medium: Option<Arc<Medium>>,
 }
 
 impl Camera {
     pub fn new(settings: CameraSettings, width: u32, height: u32) -> Self {
-        let v_up = Vec3::Y;
+        let projection = match settings.projection {
+            CameraProjection::Perspective { y_fov } => {
+                Perspective::new(&settings, y_fov, width, height).into()
+            }
+            CameraProjection::Orthographic { x_mag, y_mag } => {
+                Orthographic::new(&settings, x_mag, y_mag, width, height).into()
+            }
+            CameraProjection::Equirectangular { stereo_ipd } => {
+                Equirectangular::new(&settings, stereo_ipd, width, height).into()
+            }
+            CameraProjection::Cubemap => Cubemap::new(&settings, width, height).into(),
+            CameraProjection::Fisheye { fov, mapping } => {
+                Fisheye::new(&settings, fov, mapping, width, height).into()
+            }
+        };
 
-        // gltf FoV is already in radians
-        let theta = settings.y_fov;
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * settings.focus_dist;
-        let viewport_width = viewport_height * (width as f32 / height as f32);
+        let rest = Frame::new(&settings).to_affine().inverse();
+        let motion = settings.motion.map(|transform| (transform, rest));
+
+        Camera {
+            projection,
+            shutter: settings.shutter,
+            motion,
+            z_far: settings.z_far,
+            z_near: settings.z_near,
+            medium: settings.medium,
+        }
+    }
+
+    /// Construct a camera ray for a randomly sampled point around the pixel location `(i, j)`,
+    /// or `None` for pixels outside of the area the projection covers.
+    pub fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let time = math::lerp(random(), self.shutter.min, self.shutter.max);
+        let ray = self.projection.get_ray(i, j)?.with_time(time);
+        Some(match &self.motion {
+            Some((transform, rest)) => {
+                let frame = Frame::from_transform(transform.at(time));
+                ray.transformed(frame.to_affine() * *rest)
+            }
+            None => ray,
+        })
+    }
+}
+
+#[enum_dispatch]
+pub trait GenerateRay {
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray>;
+}
+
+#[enum_dispatch(GenerateRay)]
+pub enum Projection {
+    Perspective(Perspective),
+    Orthographic(Orthographic),
+    Equirectangular(Equirectangular),
+    Cubemap(Cubemap),
+    Fisheye(Fisheye),
+}
 
-        let look_from = settings.transform.transform_point3a(Point3::ZERO);
-        let look_at = settings.transform.transform_point3a(Point3::NEG_Z);
+/// The position and orientation of a camera, with `w` pointing backwards, away from the
+/// direction the camera looks in.
+#[derive(Debug)]
+struct Frame {
+    center: Point3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Frame {
+    fn new(settings: &CameraSettings) -> Self {
+        Frame::from_transform(settings.transform)
+    }
 
-        // Calculate the u,v,w unit basis vectors for the camera coordinate frame.
-        let w = (look_from - look_at).normalize();
-        let u = v_up.cross(w).normalize();
+    /// The frame of the camera's rotation, including roll, with the axes made orthonormal in
+    /// case the transform also scales or shears.
+    fn from_transform(transform: Affine3A) -> Self {
+        let w = transform.transform_vector3a(Vec3::Z).normalize();
+        let x = transform.transform_vector3a(Vec3::X);
+        let u = (x - w * x.dot(w)).normalize();
         let v = w.cross(u);
 
-        let camera_center = look_from;
+        Frame {
+            center: transform.transform_point3a(Point3::ZERO),
+            u,
+            v,
+            w,
+        }
+    }
+
+    /// The transform from camera space to world space.
+    fn to_affine(&self) -> Affine3A {
+        Affine3A::from_cols(self.u, self.v, self.w, self.center)
+    }
+
+    /// Transforms `direction` from camera space, looking down -z, to world space.
+    fn to_world(&self, direction: Vec3) -> Vec3 {
+        direction.x * self.u + direction.y * self.v + direction.z * self.w
+    }
+
+    /// A ray from `origin` along the camera space direction that `direction` returns for a
+    /// position in the image, with differentials towards the neighbouring pixels.
+    fn ray(
+        &self,
+        origin: impl Fn(f32, f32) -> Point3,
+        direction: impl Fn(f32, f32) -> Option<Vec3>,
+        x: f32,
+        y: f32,
+    ) -> Option<Ray> {
+        let ray = Ray::new(origin(x, y), self.to_world(direction(x, y)?));
+        let differential = |x, y| Some((origin(x, y), self.to_world(direction(x, y)?)));
+        match (differential(x + 1.0, y), differential(x, y + 1.0)) {
+            (Some((rx_origin, rx_direction)), Some((ry_origin, ry_direction))) => {
+                Some(ray.with_differentials(RayDifferentials {
+                    rx_origin,
+                    rx_direction,
+                    ry_origin,
+                    ry_direction,
+                }))
+            }
+            _ => Some(ray),
+        }
+    }
+}
+
+// Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
+fn sample_square() -> Vec3 {
+    Vec3::new(random() - 0.5, random() - 0.5, 0.0)
+}
+
+/// The range of pixels along an image axis of `size` pixels that a view covering the fraction
+/// `coverage` of it shows, centred between black bars.
+fn visible(coverage: f32, size: u32) -> (f32, f32) {
+    let margin = (1.0 - coverage) / 2.0 * size as f32;
+    (margin, size as f32 - margin)
+}
+
+/// Whether the centre of pixel `(i, j)` lies within the visible columns and rows.
+fn is_visible(visible_x: (f32, f32), visible_y: (f32, f32), i: u32, j: u32) -> bool {
+    let (x, y) = (i as f32 + 0.5, j as f32 + 0.5);
+    (visible_x.0..visible_x.1).contains(&x) && (visible_y.0..visible_y.1).contains(&y)
+}
+
+/// Width of a full frame sensor in millimetres, for lenses that don't give theirs.
+const FULL_FRAME_WIDTH: f32 = 36.0;
+
+/// Focus distance of lenses that don't give theirs, in scene units.
+const DEFAULT_FOCUS_DISTANCE: f32 = 10.0;
+
+/// A pinhole or thin lens camera, projecting the scene onto a viewport in front of it.
+pub struct Perspective {
+    center: Point3,
+    pixel_00_loc: Point3,
+    pixel_delta_u: Vec3,
+    pixel_delta_v: Vec3,
+    /// The axes of the aperture, scaled to its radius in scene units.
+    aperture_u: Vec3,
+    aperture_v: Vec3,
+    lens_radius: f32,
+    blades: u32,
+    blade_rotation: f32,
+    /// The columns and rows of the image the view covers, with black bars around them.
+    visible_x: (f32, f32),
+    visible_y: (f32, f32),
+}
+
+impl Perspective {
+    fn new(settings: &CameraSettings, y_fov: f32, width: u32, height: u32) -> Self {
+        let Frame { center, u, v, w } = Frame::new(settings);
+        let lens = settings.lens;
+        let image_aspect = width as f32 / height as f32;
+        let aspect_ratio = settings.aspect_ratio.unwrap_or(image_aspect);
+
+        // like Blender's automatic sensor fit, the sensor width spans the longer side
+        let sensor_width = lens.sensor_width.unwrap_or(FULL_FRAME_WIDTH);
+        let sensor_height = sensor_width / aspect_ratio.max(1.0);
+        // gltf FoV is already in radians
+        let (h, focal_length) = match lens.focal_length {
+            Some(focal_length) => (sensor_height / (2.0 * focal_length), focal_length),
+            None => {
+                let h = (y_fov / 2.0).tan();
+                (h, sensor_height / (2.0 * h))
+            }
+        };
+
+        let (h, coverage) = settings.aspect_fit.fit(h, aspect_ratio, image_aspect);
+
+        let focus_dist = lens.focus_distance.unwrap_or(DEFAULT_FOCUS_DISTANCE);
+        let viewport_height = 2.0 * h * focus_dist;
+        let viewport_width = viewport_height * image_aspect;
+
         let viewport_u = u * viewport_width;
         let viewport_v = -v * viewport_height;
 
         let pixel_delta_u = viewport_u / width as f32;
         let pixel_delta_v = viewport_v / height as f32;
 
-        let viewport_upper_left =
-            camera_center - (w * settings.focus_dist) - viewport_u / 2.0 - viewport_v / 2.0;
+        let viewport_upper_left = center - (w * focus_dist) - viewport_u / 2.0 - viewport_v / 2.0;
         let pixel_00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
 
-        let defocus_radius =
-            settings.focus_dist * (settings.defocus_angle / 2.0).to_radians().tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
+        // the aperture's diameter is the focal length over the f-number, in millimetres
+        let lens_radius = lens
+            .f_stop
+            .map_or(0.0, |f_stop| focal_length / f_stop / 2.0 / 1000.0);
 
-        Camera {
-            center: camera_center,
+        Perspective {
+            center,
             pixel_00_loc,
             pixel_delta_u,
             pixel_delta_v,
-            defocus_disk_u,
-            defocus_disk_v,
-            defocus_angle: settings.defocus_angle,
-            z_far: settings.z_far,
-            z_near: settings.z_near,
+            aperture_u: u * lens_radius,
+            aperture_v: v * lens_radius,
+            lens_radius,
+            blades: lens.blades.unwrap_or(0),
+            blade_rotation: lens.blade_rotation.unwrap_or(0.0),
+            visible_x: visible(coverage.x, width),
+            visible_y: visible(coverage.y, height),
         }
     }
 
-    /// Construct a camera ray originating from the origin and directed at randomly sampled
-    /// point around the pixel location `(i, j)`.
-    pub fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let offset = self.sample_square();
+    /// Returns a random point on the aperture, a polygon with a corner per blade or a disk.
+    fn aperture_sample(&self) -> Point3 {
+        let u = Vec2::new(random(), random());
+        let p = if self.blades >= 3 {
+            sample::sample_regular_polygon(u, self.blades, self.blade_rotation)
+        } else {
+            sample::sample_uniform_disk_concentric(u)
+        };
+        self.center + (self.aperture_u * p.x) + (self.aperture_v * p.y)
+    }
+}
+
+impl GenerateRay for Perspective {
+    /// Construct a camera ray originating from the lens and directed at a randomly sampled
+    /// point around the pixel location `(i, j)` on the plane in focus.
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        if !is_visible(self.visible_x, self.visible_y, i, j) {
+            return None;
+        }
+
+        let offset = sample_square();
         let pixel_sample = self.pixel_00_loc
             + (self.pixel_delta_u * (i as f32 + offset.x))
             + (self.pixel_delta_v * (j as f32 + offset.y));
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
+        let ray_origin = if self.lens_radius <= 0.0 {
             self.center
         } else {
-            self.defocus_disk_sample()
+            self.aperture_sample()
         };
         let ray_direction = pixel_sample - ray_origin;
 
-        Ray::new(ray_origin, ray_direction)
+        Some(
+            Ray::new(ray_origin, ray_direction).with_differentials(RayDifferentials {
+                rx_origin: ray_origin,
+                rx_direction: pixel_sample + self.pixel_delta_u - ray_origin,
+                ry_origin: ray_origin,
+                ry_direction: pixel_sample + self.pixel_delta_v - ray_origin,
+            }),
+        )
+    }
+}
+
+/// A camera casting parallel rays from a rectangle around its position, for elevations and
+/// plans without perspective. There is no lens, so everything is in focus.
+pub struct Orthographic {
+    pixel_00_loc: Point3,
+    pixel_delta_u: Vec3,
+    pixel_delta_v: Vec3,
+    direction: Vec3,
+    /// The columns and rows of the image the view covers, with black bars around them.
+    visible_x: (f32, f32),
+    visible_y: (f32, f32),
+}
+
+impl Orthographic {
+    /// The view volume spans `x_mag` and `y_mag` to either side of the camera, fitted into the
+    /// image like the view of a perspective camera.
+    fn new(settings: &CameraSettings, x_mag: f32, y_mag: f32, width: u32, height: u32) -> Self {
+        let Frame { center, u, v, w } = Frame::new(settings);
+
+        let image_aspect = width as f32 / height as f32;
+        let (half_height, coverage) = settings.aspect_fit.fit(y_mag, x_mag / y_mag, image_aspect);
+        let half_width = half_height * image_aspect;
+
+        let viewport_u = u * 2.0 * half_width;
+        let viewport_v = -v * 2.0 * half_height;
+        let pixel_delta_u = viewport_u / width as f32;
+        let pixel_delta_v = viewport_v / height as f32;
+        let viewport_upper_left = center - viewport_u / 2.0 - viewport_v / 2.0;
+
+        Orthographic {
+            pixel_00_loc: viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5,
+            pixel_delta_u,
+            pixel_delta_v,
+            direction: -w,
+            visible_x: visible(coverage.x, width),
+            visible_y: visible(coverage.y, height),
+        }
+    }
+}
+
+impl GenerateRay for Orthographic {
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        if !is_visible(self.visible_x, self.visible_y, i, j) {
+            return None;
+        }
+
+        let offset = sample_square();
+        let origin = self.pixel_00_loc
+            + (self.pixel_delta_u * (i as f32 + offset.x))
+            + (self.pixel_delta_v * (j as f32 + offset.y));
+
+        Some(
+            Ray::new(origin, self.direction).with_differentials(RayDifferentials {
+                rx_origin: origin + self.pixel_delta_u,
+                rx_direction: self.direction,
+                ry_origin: origin + self.pixel_delta_v,
+                ry_direction: self.direction,
+            }),
+        )
+    }
+}
+
+/// A 360° panorama mapping longitude to x and latitude to y, with the camera's view direction
+/// in the centre. In stereo, the image holds the left eye's panorama above the right eye's,
+/// rendered as omni-directional stereo with the eyes `ipd` apart.
+pub struct Equirectangular {
+    frame: Frame,
+    width: f32,
+    height: f32,
+    stereo_ipd: Option<f32>,
+}
+
+impl Equirectangular {
+    fn new(settings: &CameraSettings, stereo_ipd: Option<f32>, width: u32, height: u32) -> Self {
+        Equirectangular {
+            frame: Frame::new(settings),
+            width: width as f32,
+            height: height as f32,
+            stereo_ipd,
+        }
+    }
+}
+
+impl GenerateRay for Equirectangular {
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let offset = sample_square();
+        let (x, y) = (i as f32 + 0.5 + offset.x, j as f32 + 0.5 + offset.y);
+
+        // each eye gets half of the image in stereo, with the same eye for the whole ray
+        let (eye_height, eye, eye_top) = match self.stereo_ipd {
+            Some(ipd) if y < self.height / 2.0 => (self.height / 2.0, -ipd / 2.0, 0.0),
+            Some(ipd) => (self.height / 2.0, ipd / 2.0, self.height / 2.0),
+            None => (self.height, 0.0, 0.0),
+        };
+
+        let angles = |x: f32, y: f32| {
+            let phi = (x / self.width - 0.5) * TAU;
+            let latitude = (0.5 - (y - eye_top) / eye_height) * PI;
+            (phi, latitude)
+        };
+        let direction = |x, y| {
+            let (phi, latitude) = angles(x, y);
+            Some(Vec3::new(
+                phi.sin() * latitude.cos(),
+                latitude.sin(),
+                -phi.cos() * latitude.cos(),
+            ))
+        };
+        // the eyes sit on a circle, offset to the side of each viewing direction
+        let origin = |x, y| {
+            let (phi, _) = angles(x, y);
+            self.frame.center
+                + self
+                    .frame
+                    .to_world(Vec3::new(phi.cos(), 0.0, phi.sin()) * eye)
+        };
+
+        self.frame.ray(origin, direction, x, y)
     }
+}
 
-    /// Returns a random point in the camera defocus disk.
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = vec3::random::gen_unit_disk();
-        self.center + (self.defocus_disk_u * p.x) + (self.defocus_disk_v * p.y)
+/// Six 90° views along the axes of the camera, laid out in a 3×2 grid of square faces: +x, -x
+/// and +y in the top row, -y, +z and -z in the bottom row, each oriented like the faces of an
+/// OpenGL cube map.
+pub struct Cubemap {
+    frame: Frame,
+    face_size: f32,
+}
+
+impl Cubemap {
+    fn new(settings: &CameraSettings, width: u32, height: u32) -> Self {
+        Cubemap {
+            frame: Frame::new(settings),
+            face_size: (width as f32 / 3.0).min(height as f32 / 2.0),
+        }
     }
+}
+
+impl GenerateRay for Cubemap {
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let offset = sample_square();
+        let (x, y) = (i as f32 + 0.5 + offset.x, j as f32 + 0.5 + offset.y);
+        let (column, row) = ((x / self.face_size) as usize, (y / self.face_size) as usize);
+        if column >= 3 || row >= 2 {
+            return None;
+        }
+
+        // differentials stay on the face of the sampled pixel
+        let face = row * 3 + column;
+        let direction = |x: f32, y: f32| {
+            let a = 2.0 * (x / self.face_size - column as f32) - 1.0;
+            let b = 2.0 * (y / self.face_size - row as f32) - 1.0;
+            let direction = match face {
+                0 => Vec3::new(1.0, -b, -a),
+                1 => Vec3::new(-1.0, -b, a),
+                2 => Vec3::new(a, 1.0, b),
+                3 => Vec3::new(a, -1.0, -b),
+                4 => Vec3::new(a, -b, 1.0),
+                _ => Vec3::new(-a, -b, -1.0),
+            };
+            Some(direction.normalize())
+        };
+
+        self.frame.ray(|_, _| self.frame.center, direction, x, y)
+    }
+}
+
+/// How a view is fitted into an image of a different aspect ratio.
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum AspectFit {
+    /// Fill the image, cutting off the top and bottom or the sides of the view.
+    #[default]
+    Crop,
+    /// Show the whole view, with black bars above and below or to the sides.
+    Letterbox,
+}
+
+impl AspectFit {
+    /// Fits a view with `aspect_ratio`, extending `h` from its centre to its top edge, into an
+    /// image with `image_aspect`. Returns the extent to the top edge of the image and the
+    /// fractions of its width and height the view covers.
+    fn fit(self, h: f32, aspect_ratio: f32, image_aspect: f32) -> (f32, Vec2) {
+        let wider = image_aspect / aspect_ratio;
+        match self {
+            AspectFit::Crop if wider > 1.0 => (h / wider, Vec2::ONE),
+            AspectFit::Crop => (h, Vec2::ONE),
+            AspectFit::Letterbox if wider > 1.0 => (h, Vec2::new(1.0 / wider, 1.0)),
+            AspectFit::Letterbox => (h / wider, Vec2::new(1.0, wider)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    /// The distance from the centre of the image is proportional to the angle from the view
+    /// direction.
+    Equidistant,
+    /// Equal areas in the image cover equal solid angles.
+    Equisolid,
+}
+
+/// A fisheye lens projecting a field of view of `fov` radians onto a circle that fills the
+/// shorter side of the image. Pixels outside of the circle stay black.
+pub struct Fisheye {
+    frame: Frame,
+    fov: f32,
+    mapping: FisheyeMapping,
+    center: (f32, f32),
+    radius: f32,
+}
+
+impl Fisheye {
+    fn new(
+        settings: &CameraSettings,
+        fov: f32,
+        mapping: FisheyeMapping,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Fisheye {
+            frame: Frame::new(settings),
+            fov: fov.min(TAU),
+            mapping,
+            center: (width as f32 / 2.0, height as f32 / 2.0),
+            radius: width.min(height) as f32 / 2.0,
+        }
+    }
+}
+
+impl GenerateRay for Fisheye {
+    fn get_ray(&self, i: u32, j: u32) -> Option<Ray> {
+        let offset = sample_square();
+        let (x, y) = (i as f32 + 0.5 + offset.x, j as f32 + 0.5 + offset.y);
+
+        let direction = |x: f32, y: f32| {
+            let dx = (x - self.center.0) / self.radius;
+            let dy = (self.center.1 - y) / self.radius;
+            let r = (dx * dx + dy * dy).sqrt();
+            if r > 1.0 {
+                return None;
+            }
+
+            let theta = match self.mapping {
+                FisheyeMapping::Equidistant => r * self.fov / 2.0,
+                FisheyeMapping::Equisolid => {
+                    2.0 * (r * (self.fov / 4.0).sin()).clamp(-1.0, 1.0).asin()
+                }
+            };
+            let psi = dy.atan2(dx);
+            Some(Vec3::new(
+                theta.sin() * psi.cos(),
+                theta.sin() * psi.sin(),
+                -theta.cos(),
+            ))
+        };
+
+        self.frame.ray(|_, _| self.frame.center, direction, x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Affine3A;
+
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::{AspectFit, Camera, FisheyeMapping, GenerateRay, Perspective};
+    use crate::scene::{CameraProjection, CameraSettings, Lens};
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let settings = CameraSettings {
+            projection: CameraProjection::Orthographic {
+                x_mag: 2.0,
+                y_mag: 1.0,
+            },
+            transform: Affine3A::from_translation(glam::Vec3::new(0.0, 0.0, 5.0)),
+            ..CameraSettings::default()
+        };
+        // a square image crops the sides of the view volume
+        let camera = Camera::new(settings.clone(), 100, 100);
+
+        for (i, j) in [(0, 0), (99, 0), (50, 50), (0, 99)] {
+            let ray = camera.get_ray(i, j).unwrap();
+            assert_eq!(ray.direction, Vec3::NEG_Z);
+            assert!((ray.origin.z - 5.0).abs() < 1e-5);
+            assert!(ray.origin.x.abs() <= 1.0 && ray.origin.y.abs() <= 1.0);
+        }
+
+        // rays start anywhere inside the pixel, which is 0.02 units wide
+        let corner = camera.get_ray(0, 0).unwrap().origin;
+        assert!((corner - Point3::new(-1.0, 1.0, 5.0)).abs().max_element() <= 0.02);
+
+        // or shows all of it between black bars above and below
+        let letterbox = Camera::new(
+            CameraSettings {
+                aspect_fit: AspectFit::Letterbox,
+                ..settings
+            },
+            100,
+            100,
+        );
+        assert!(letterbox.get_ray(0, 24).is_none());
+        assert!(letterbox.get_ray(0, 75).is_none());
+        let corner = letterbox.get_ray(0, 25).unwrap().origin;
+        assert!((corner - Point3::new(-2.0, 1.0, 5.0)).abs().max_element() <= 0.04);
+    }
+
+    #[test]
+    fn test_panoramic_projections() {
+        let camera = |projection, width, height| {
+            let settings = CameraSettings {
+                projection,
+                ..CameraSettings::default()
+            };
+            Camera::new(settings, width, height)
+        };
+        let direction = |camera: &Camera, i, j| camera.get_ray(i, j).unwrap().direction.normalize();
+
+        // the centre of a panorama looks ahead, its left and right edges behind the camera
+        let panorama = camera(
+            CameraProjection::Equirectangular { stereo_ipd: None },
+            360,
+            180,
+        );
+        assert!(direction(&panorama, 180, 90).dot(Vec3::NEG_Z) > 0.99);
+        assert!(direction(&panorama, 0, 90).dot(Vec3::Z) > 0.99);
+        assert!(direction(&panorama, 180, 0).dot(Vec3::Y) > 0.99);
+
+        // in stereo both eyes look the same way from either side of the camera
+        let stereo = camera(
+            CameraProjection::Equirectangular {
+                stereo_ipd: Some(0.1),
+            },
+            360,
+            360,
+        );
+        let (left, right) = (
+            stereo.get_ray(180, 90).unwrap(),
+            stereo.get_ray(180, 270).unwrap(),
+        );
+        assert!(left.direction.normalize().dot(right.direction.normalize()) > 0.99);
+        assert!(left.origin.x < -0.04 && right.origin.x > 0.04);
+
+        // the fifth cube face looks back along +z
+        let cubemap = camera(CameraProjection::Cubemap, 300, 200);
+        assert!(direction(&cubemap, 150, 150).dot(Vec3::Z) > 0.99);
+        assert!(direction(&cubemap, 50, 50).dot(Vec3::X) > 0.99);
+
+        let fisheye = camera(
+            CameraProjection::Fisheye {
+                fov: std::f32::consts::PI,
+                mapping: FisheyeMapping::Equisolid,
+            },
+            200,
+            100,
+        );
+        assert!(direction(&fisheye, 100, 50).dot(Vec3::NEG_Z) > 0.99);
+        assert!(direction(&fisheye, 145, 50).dot(Vec3::X) > 0.95);
+        assert!(fisheye.get_ray(0, 0).is_none());
+    }
+
+    #[test]
+    fn test_thin_lens() {
+        let settings = CameraSettings {
+            lens: Lens {
+                focal_length: Some(50.0),
+                f_stop: Some(2.0),
+                focus_distance: Some(3.0),
+                blades: Some(3),
+                ..Lens::default()
+            },
+            ..CameraSettings::default()
+        };
+        let camera = Perspective::new(&settings, 1.0, 300, 200);
+
+        // 50 mm at f/2 is a 25 mm aperture, and a full frame sensor 24 mm high
+        assert!((camera.lens_radius - 0.0125).abs() < 1e-6);
+        let viewport_height = (camera.pixel_delta_v * 200.0).length();
+        assert!((viewport_height - 3.0 * 24.0 / 50.0).abs() < 1e-4);
+
+        // rays through the same pixel meet on the plane in focus, and leave the triangular
+        // aperture no further than half its radius opposite the first blade corner
+        let mut min_x = f32::INFINITY;
+        for _ in 0..1000 {
+            let ray = camera.get_ray(150, 100).unwrap();
+            let t = -3.0 / ray.direction.z;
+            let focus = ray.origin + ray.direction * t;
+            assert!(focus.truncate().length() < 0.02, "focused at {focus}");
+            assert!(ray.origin.length() <= 0.0125 + 1e-6);
+            min_x = min_x.min(ray.origin.x);
+        }
+        assert!((-0.00625 - 1e-6..-0.005).contains(&min_x), "{min_x}");
+    }
+
+    #[test]
+    fn test_frame_follows_rotation_and_aspect() {
+        let perspective = |transform, aspect_ratio, aspect_fit| {
+            let settings = CameraSettings {
+                projection: CameraProjection::Perspective { y_fov: FRAC_PI_2 },
+                aspect_ratio,
+                aspect_fit,
+                transform,
+                ..CameraSettings::default()
+            };
+            Camera::new(settings, 200, 100)
+        };
+        let slope = |camera: &Camera, i, j| {
+            let d = camera.get_ray(i, j).unwrap().direction;
+            d / -d.z
+        };
+
+        // looking straight down, and rolled a quarter turn so that up in the image is -x
+        let down = perspective(Affine3A::from_rotation_x(-FRAC_PI_2), None, AspectFit::Crop);
+        let d = down.get_ray(100, 50).unwrap().direction.normalize();
+        assert!(d.is_finite() && d.dot(Vec3::NEG_Y) > 0.99, "{d}");
+        let rolled = perspective(Affine3A::from_rotation_z(FRAC_PI_2), None, AspectFit::Crop);
+        assert!(slope(&rolled, 100, 0).x < -0.9);
 
-    // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
-    fn sample_square(&self) -> Vec3 {
-        Vec3::new(random() - 0.5, random() - 0.5, 0.0)
+        // a square view in a wide image loses its top and bottom, or gets bars to the sides
+        let crop = perspective(Affine3A::IDENTITY, Some(1.0), AspectFit::Crop);
+        assert!((slope(&crop, 100, 0).y - 0.5).abs() < 0.02);
+        assert!((slope(&crop, 199, 50).x - 1.0).abs() < 0.02);
+        let letterbox = perspective(Affine3A::IDENTITY, Some(1.0), AspectFit::Letterbox);
+        assert!((slope(&letterbox, 100, 0).y - 1.0).abs() < 0.03);
+        assert!(letterbox.get_ray(10, 50).is_none());
+        assert!((slope(&letterbox, 149, 50).x - 1.0).abs() < 0.03);
     }
 }
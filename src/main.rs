@@ -1,23 +1,35 @@
 #![allow(unused)]
 use std::path::PathBuf;
+use std::str::FromStr;
 
 use bvh::BvhType;
-use camera::Camera;
-use clap::Parser;
+use bvh_stats::{BvhStats, TraversalStats};
+use camera::{AspectFit, Camera, FisheyeMapping};
+use clap::error::ErrorKind;
+use clap::{value_parser, CommandFactory, Parser, Subcommand, ValueEnum};
+use color_eyre::eyre::eyre;
 use mimalloc::MiMalloc;
-use object::Hittable;
+use object::{Hittable, Object};
+use range::Range;
 use renderer::{ImageOutput, Renderer};
-use scene::RenderSettings;
+use scene::{CameraProjection, CameraSettings, CropWindow, Lens, RenderSettings, SceneDescription};
 use tev_client::TevClient;
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 use tracing_subscriber::fmt::format::FmtSpan;
 use vec3::Color;
 
 mod aabb;
+mod animation;
 mod bvh;
+mod bvh_cache;
+mod bvh_stats;
 mod camera;
 mod material;
 mod math;
+mod medium;
+mod microfacet;
+mod mipmap;
+mod noise;
 mod object;
 mod onb;
 mod random;
@@ -26,9 +38,11 @@ mod ray;
 mod renderer;
 mod sample;
 mod scene;
+mod spectrum;
 mod texture;
 mod util;
 mod vec3;
+mod volume;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -36,10 +50,19 @@ static GLOBAL: MiMalloc = MiMalloc;
 pub type Result<T> = color_eyre::Result<T>;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[clap(long)]
     pub bvh_disabled: bool,
 
+    /// Always rebuild the BVH instead of reading or writing the `.bvhcache` file next to the
+    /// scene.
+    #[clap(long)]
+    pub no_bvh_cache: bool,
+
     #[clap(long)]
     pub debug: bool,
 
@@ -58,12 +81,224 @@ pub struct Args {
     #[clap(short, long, default_value = "0")]
     pub camera: usize,
 
-    pub input: PathBuf,
+    /// Render with sampled wavelengths instead of RGB, upsampling colours to spectra.
+    #[clap(long)]
+    pub spectral: bool,
+
+    /// Replace the projection of the selected camera, keeping its position and orientation.
+    #[clap(long, value_enum)]
+    pub projection: Option<ProjectionArg>,
+
+    /// How to fit a camera whose aspect ratio differs from the image's into it.
+    #[clap(long, value_enum, default_value = "crop")]
+    pub aspect_fit: AspectFit,
+
+    /// Distance between the eyes of stereo panoramas, in scene units.
+    #[clap(long, default_value = "0.064")]
+    pub ipd: f32,
+
+    /// Field of view of fisheye projections, in degrees.
+    #[clap(long, default_value = "180")]
+    pub fisheye_fov: f32,
+
+    /// Focal length of the lens in millimetres, replacing the camera's field of view.
+    #[clap(long, value_parser = parse_positive)]
+    pub focal_length: Option<f32>,
+
+    /// F-number of the aperture, enabling depth of field.
+    #[clap(long, value_parser = parse_positive)]
+    pub f_stop: Option<f32>,
+
+    /// Width of the sensor in millimetres, 36 for full frame.
+    #[clap(long)]
+    pub sensor_width: Option<f32>,
+
+    /// Distance to the plane in focus, in scene units.
+    #[clap(long, conflicts_with = "autofocus")]
+    pub focus_distance: Option<f32>,
+
+    /// Focus on whatever is at the centre of the image.
+    #[clap(long)]
+    pub autofocus: bool,
+
+    /// Number of aperture blades shaping the bokeh, round if fewer than three.
+    #[clap(long)]
+    pub aperture_blades: Option<u32>,
+
+    /// Rotation of the aperture blades in degrees.
+    #[clap(long)]
+    pub aperture_rotation: Option<f32>,
+
+    /// Time in seconds at which the shutter opens, for motion blur of animated scenes. When
+    /// rendering frames, relative to the time of each frame.
+    #[clap(long, default_value = "0", value_parser = parse_finite)]
+    pub shutter_open: f32,
+
+    /// Time in seconds at which the shutter closes.
+    #[clap(long, default_value = "0", value_parser = parse_finite)]
+    pub shutter_close: f32,
+
+    /// Render the frames `start..end`, or `start..=end` including the last, of the scene's
+    /// animation to numbered EXR files instead of a single still.
+    #[clap(long)]
+    pub frames: Option<Frames>,
+
+    /// Frames per second of the animation.
+    #[clap(long, default_value = "24", value_parser = parse_positive)]
+    pub fps: f32,
+
+    /// Render only the pixels from `x0,y0` up to `x1,y1` of the full image.
+    #[clap(long)]
+    pub crop: Option<CropWindow>,
+
+    /// Log every bounce of the paths through pixel `x,y`, rendering only it unless a crop
+    /// window is given.
+    #[clap(long, value_parser = parse_pixel)]
+    pub debug_pixel: Option<(u32, u32)>,
+
+    #[clap(required = true)]
+    pub input: Option<PathBuf>,
 
     #[clap(default_value = "image.jpeg")]
     pub output: PathBuf,
 }
 
+impl Args {
+    /// The shutter interval for an image taken at `time`.
+    fn shutter(&self, time: f32) -> Range {
+        Range::new(
+            time + self.shutter_open,
+            time + self.shutter_close.max(self.shutter_open),
+        )
+    }
+}
+
+fn parse_finite(s: &str) -> std::result::Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("expected a finite number, got {s:?}")),
+    }
+}
+
+fn parse_positive(s: &str) -> std::result::Result<f32, String> {
+    match parse_finite(s) {
+        Ok(value) if value > 0.0 => Ok(value),
+        _ => Err(format!("expected a positive number, got {s:?}")),
+    }
+}
+
+fn parse_pixel(s: &str) -> std::result::Result<(u32, u32), String> {
+    let invalid = || format!("expected a pixel as x,y, got {s:?}");
+    let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+    Ok((
+        x.trim().parse().map_err(|_| invalid())?,
+        y.trim().parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// A range of animation frames, excluding `end`.
+#[derive(Debug, Clone, Copy)]
+pub struct Frames {
+    start: u32,
+    end: u32,
+}
+
+impl FromStr for Frames {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("expected frames as start..end or start..=end, got {s:?}");
+        let (start, end) = s.split_once("..").ok_or_else(invalid)?;
+        let (end, inclusive) = match end.strip_prefix('=') {
+            Some(end) => (end, true),
+            None => (end, false),
+        };
+        let start: u32 = start.trim().parse().map_err(|_| invalid())?;
+        let end: u32 = end.trim().parse().map_err(|_| invalid())?;
+        let end = end.checked_add(inclusive as u32).ok_or_else(invalid)?;
+        if end <= start {
+            return Err(format!("frames {s:?} are empty"));
+        }
+        Ok(Frames { start, end })
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ProjectionArg {
+    Perspective,
+    Equirectangular,
+    /// Equirectangular panoramas for the left and right eye, one above the other.
+    StereoEquirectangular,
+    Cubemap,
+    FisheyeEquidistant,
+    FisheyeEquisolid,
+}
+
+impl ProjectionArg {
+    /// The projection to render with, where perspective cameras take the field of view of
+    /// `current` if it has one.
+    fn to_projection(self, current: CameraProjection, args: &Args) -> CameraProjection {
+        let fisheye = |mapping| CameraProjection::Fisheye {
+            fov: args.fisheye_fov.to_radians(),
+            mapping,
+        };
+        match self {
+            ProjectionArg::Perspective => match current {
+                CameraProjection::Perspective { .. } => current,
+                _ => CameraSettings::default().projection,
+            },
+            ProjectionArg::Equirectangular => {
+                CameraProjection::Equirectangular { stereo_ipd: None }
+            }
+            ProjectionArg::StereoEquirectangular => CameraProjection::Equirectangular {
+                stereo_ipd: Some(args.ipd),
+            },
+            ProjectionArg::Cubemap => CameraProjection::Cubemap,
+            ProjectionArg::FisheyeEquidistant => fisheye(FisheyeMapping::Equidistant),
+            ProjectionArg::FisheyeEquisolid => fisheye(FisheyeMapping::Equisolid),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print quality statistics of the BVH built for a scene.
+    BvhStats(BvhStatsArgs),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BvhStatsArgs {
+    #[clap(short = 'W', long, default_value = "1280", value_parser = value_parser!(u32).range(1..))]
+    pub width: u32,
+
+    #[clap(short = 'H', long, default_value = "720", value_parser = value_parser!(u32).range(1..))]
+    pub height: u32,
+
+    #[clap(short, long, default_value = "0")]
+    pub camera: usize,
+
+    /// Number of primary rays to cast, spread evenly over the image.
+    #[clap(long, default_value = "100000", value_parser = value_parser!(u32).range(1..))]
+    pub rays: u32,
+
+    pub input: PathBuf,
+}
+
+fn bvh_stats(args: BvhStatsArgs) -> Result<()> {
+    let scene = scene::load_from_gltf(&args.input)?.build_bvh(BvhType::Flat);
+    let Object::FlatBvhTree(tree) = &scene.root_object else {
+        return Err(eyre!("scene {} has no BVH", args.input.display()));
+    };
+
+    println!("{}", BvhStats::collect(tree));
+
+    let camera = Camera::new(scene.camera(args.camera), args.width, args.height);
+    let traversal = TraversalStats::collect(tree, &camera, args.width, args.height, args.rays);
+    println!("{traversal}");
+
+    Ok(())
+}
+
 fn main() -> Result<()> {
     color_eyre::install()?;
 
@@ -77,6 +312,14 @@ fn main() -> Result<()> {
         })
         .init();
 
+    if let Some(Command::BvhStats(stats_args)) = args.command {
+        return bvh_stats(stats_args);
+    }
+    let input = args
+        .input
+        .clone()
+        .expect("input is required without a subcommand");
+
     let render_settings = RenderSettings {
         samples_per_pixel: args.samples_per_pixel,
         selected_camera: args.camera,
@@ -84,23 +327,101 @@ fn main() -> Result<()> {
         image_height: args.height,
         max_depth: args.max_depth,
         background_color: Color::ZERO,
+        spectral: args.spectral,
+        crop: args.crop,
+        debug_pixel: args.debug_pixel,
     };
+    if let Err(message) = render_settings.validate() {
+        Args::command()
+            .error(ErrorKind::ValueValidation, message)
+            .exit();
+    }
 
     let selected_camera = render_settings.selected_camera;
 
-    let scene = scene::load_from_gltf(&args.input)?.build_bvh(BvhType::Tree);
+    // the BVH is built around the poses of moving meshes while the first image's shutter is open
+    let shutter = args.shutter(args.frames.map_or(0.0, |f| f.start as f32 / args.fps));
+    let mut scene = scene::load_from_gltf(&input)?;
+    scene.set_shutter(shutter);
+    let scene = if args.no_bvh_cache {
+        scene.build_bvh(BvhType::Tree)
+    } else {
+        scene.build_bvh_cached(input.with_extension("bvhcache"))
+    };
     info!(
         "extents of the scene: {:#?}",
         scene.root_object.bounding_box()
     );
     info!("rendering with configuration {args:#?}");
 
+    let mut camera_settings = scene.camera(selected_camera);
+    if let Some(projection) = args.projection {
+        camera_settings.projection = projection.to_projection(camera_settings.projection, &args);
+    }
+    camera_settings.lens = camera_settings.lens.overridden_by(Lens {
+        focal_length: args.focal_length,
+        f_stop: args.f_stop,
+        sensor_width: args.sensor_width,
+        focus_distance: args.focus_distance,
+        blades: args.aperture_blades,
+        blade_rotation: args.aperture_rotation.map(f32::to_radians),
+    });
+    camera_settings.aspect_fit = args.aspect_fit;
+
+    if let Some(frames) = args.frames {
+        return render_frames(scene, camera_settings, render_settings, frames, &args);
+    }
+
     let mut output = ImageOutput::Viewer(TevClient::spawn_path_default()?);
     output.init(render_settings.image_width, render_settings.image_height)?;
 
-    let camera = Camera::new(scene.camera(selected_camera), args.width, args.height);
+    camera_settings.shutter = shutter;
+    if args.autofocus {
+        autofocus(&mut camera_settings, &scene);
+    }
+    let camera = Camera::new(camera_settings, args.width, args.height);
     let renderer = Renderer::new(camera, scene, render_settings);
 
     renderer.render_progressive(output, 16)?;
     Ok(())
 }
+
+/// Focuses the camera on whatever the scene shows at the centre of the image.
+fn autofocus(camera_settings: &mut CameraSettings, scene: &SceneDescription) {
+    match camera_settings.autofocus(&scene.root_object) {
+        Some(distance) => info!("focusing at {distance}"),
+        None => warn!("nothing to focus on at the centre of the image"),
+    }
+}
+
+/// Renders the animation at every frame of `frames`, refitting the BVH to the poses of each,
+/// to `out_####.exr` files next to the output path.
+fn render_frames(
+    mut scene: SceneDescription,
+    camera_settings: CameraSettings,
+    render_settings: RenderSettings,
+    frames: Frames,
+    args: &Args,
+) -> Result<()> {
+    for frame in frames.start..frames.end {
+        let shutter = args.shutter(frame as f32 / args.fps);
+        scene.set_shutter(shutter);
+        let mut frame_settings = CameraSettings {
+            shutter,
+            ..camera_settings.clone()
+        };
+        if args.autofocus {
+            autofocus(&mut frame_settings, &scene);
+        }
+        let camera = Camera::new(frame_settings, args.width, args.height);
+
+        let renderer = Renderer::new(camera, scene, render_settings.clone());
+        let image = renderer.render_hdr(16);
+        let path = args.output.with_file_name(format!("out_{frame:04}.exr"));
+        image.save(&path)?;
+        info!("wrote frame {frame} to {}", path.display());
+        scene = renderer.into_scene();
+    }
+
+    Ok(())
+}
@@ -1,32 +1,32 @@
 use std::f32::consts::FRAC_1_PI;
+use std::ops::{Add, Div, Mul, Sub};
 use std::sync::Arc;
 
 use enum_dispatch::enum_dispatch;
 
+use crate::medium::Medium;
+use crate::microfacet::{Charlie, TrowbridgeReitz};
 use crate::object::HitRecord;
+use crate::onb::Onb;
 use crate::random::{self, random};
 use crate::ray::Ray;
 use crate::sample::cosine_hemisphere_pdf;
-use crate::texture::{HasColorValue, SolidColor, Texture, TextureCoordinates};
+use crate::texture::{HasColorValue, ScalarTexture, SolidColor, Texture, TextureCoordinates};
 use crate::vec3::random::gen_unit_vector;
 use crate::vec3::{self, reflect, refract, Color, Point3, Vec3};
 use crate::{math, sample};
 
+#[derive(Debug)]
 pub struct ScatterResult {
     pub attenuation: Color,
     pub scattered: Ray,
     pub pdf: Option<f32>,
-}
-
-impl ScatterResult {
-    pub fn mix(self, other: ScatterResult, factor: f32) -> ScatterResult {
-        let scattered = random::choose(self.scattered, other.scattered, factor);
-        ScatterResult {
-            attenuation: self.attenuation * factor + other.attenuation * (1.0 - factor),
-            scattered,
-            pdf: None,
-        }
-    }
+    /// Whether the scattering depended on the wavelength of the ray, so that only its hero
+    /// wavelength can carry on.
+    pub dispersive: bool,
+    /// The medium filling the inside of the surface, for materials that bring their own such as
+    /// [`Subsurface`].
+    pub interior: Option<Arc<Medium>>,
 }
 
 #[enum_dispatch]
@@ -45,85 +45,716 @@ pub struct Lambertian {
 }
 
 impl Scatterable for Lambertian {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
-        let w_o = -ray.direction;
-        let u = vec3::random::gen_2d();
-        let mut w_i = sample::cosine_hemisphere(u);
-
-        if w_o.z < 0.0 {
-            w_i.z *= -1.0;
+    fn scatter(&self, _: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        // the normal faces the incoming ray, so the local hemisphere is the side it came from
+        let frame = Onb::build_from_w(hit.normal);
+        let w_i = sample::cosine_hemisphere(vec3::random::gen_2d());
+        if w_i.z == 0.0 {
+            // grazing samples have a zero pdf
+            return None;
         }
 
         let sample = self.texture.value_at(hit.tex_coords, hit.point);
         Some(ScatterResult {
-            scattered: Ray::new(hit.point, w_i),
+            scattered: hit.spawn_ray(frame.local_vec(w_i)),
             attenuation: sample * FRAC_1_PI,
             pdf: Some(cosine_hemisphere_pdf(math::abs_cos_theta(w_i))),
+            dispersive: false,
+            interior: None,
+        })
+    }
+}
+
+/// A diffuse surface that also scatters light diffusely to its other side, for translucent
+/// sheets like paper, leaves and lampshades.
+#[derive(Debug)]
+pub struct DiffuseTransmission {
+    pub reflectance: Arc<Texture>,
+    pub transmittance: Arc<Texture>,
+}
+
+impl Scatterable for DiffuseTransmission {
+    fn scatter(&self, _: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let reflectance = self.reflectance.value_at(hit.tex_coords, hit.point);
+        let transmittance = self.transmittance.value_at(hit.tex_coords, hit.point);
+        let (pr, pt) = (reflectance.max_element(), transmittance.max_element());
+        if pr + pt <= 0.0 {
+            return None;
+        }
+
+        let frame = Onb::build_from_w(hit.normal);
+        let mut w_i = sample::cosine_hemisphere(vec3::random::gen_2d());
+        if w_i.z == 0.0 {
+            return None;
+        }
+        let pdf = cosine_hemisphere_pdf(w_i.z);
+        let (f, probability) = if random() < pr / (pr + pt) {
+            (reflectance, pr / (pr + pt))
+        } else {
+            w_i.z = -w_i.z;
+            (transmittance, pt / (pr + pt))
+        };
+
+        Some(ScatterResult {
+            attenuation: f * FRAC_1_PI,
+            scattered: hit.spawn_ray(frame.local_vec(w_i)),
+            pdf: Some(pdf * probability),
+            dispersive: false,
+            interior: None,
         })
     }
 }
 
+/// The "Ray Tracing in One Weekend" metal: a mirror reflection perturbed by a random offset.
+/// Kept for existing scenes; [`Conductor`] is the physically based alternative.
 #[derive(Debug)]
 pub struct Metal {
     pub texture: Arc<Texture>,
-    pub fuzz: f32,
+    pub fuzz: ScalarTexture,
 }
 
 impl Scatterable for Metal {
     fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
         let reflected = reflect(ray.direction, hit.normal);
-        let reflected = reflected.normalize() + (gen_unit_vector() * self.fuzz);
+        let fuzz = self.fuzz.value_at(hit.tex_coords, hit.point);
+        let reflected = reflected.normalize() + (gen_unit_vector() * fuzz);
         Some(ScatterResult {
-            scattered: Ray::new(hit.point, reflected),
+            scattered: hit.spawn_ray(reflected),
             attenuation: self.texture.value_at(hit.tex_coords, hit.point),
             pdf: None,
+            dispersive: false,
+            interior: None,
         })
     }
 }
 
+/// Index of refraction of a dielectric, either fixed or varying with wavelength.
+#[derive(Debug, Clone)]
+pub enum Ior {
+    Constant(ScalarTexture),
+    /// `n(λ) = a + b / λ²`, with `λ` in micrometres.
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// `n²(λ) = 1 + Σ bᵢλ² / (λ² - cᵢ)`, with `λ` in micrometres.
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    /// Schott N-BK7 crown glass.
+    pub const BK7: Ior = Ior::Sellmeier {
+        b: [1.039_612, 0.231_792_34, 1.010_469_5],
+        c: [0.006_000_699, 0.020_017_914, 103.560_65],
+    };
+    pub const FUSED_SILICA: Ior = Ior::Sellmeier {
+        b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+        c: [0.004_679_148, 0.013_512_063, 97.934],
+    };
+    pub const DIAMOND: Ior = Ior::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    /// Wavelength used when the ray carries none, the helium d line.
+    const REFERENCE_WAVELENGTH: f32 = 587.6;
+
+    pub fn at(&self, wavelength: Option<f32>, coords: TextureCoordinates, p: Point3) -> f32 {
+        let lambda = wavelength.unwrap_or(Ior::REFERENCE_WAVELENGTH) * 1e-3;
+        let lambda2 = lambda * lambda;
+        match self {
+            Ior::Constant(ior) => ior.value_at(coords, p),
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f32 = b
+                    .iter()
+                    .zip(c)
+                    .map(|(b, c)| b * lambda2 / (lambda2 - c))
+                    .sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+impl From<f32> for Ior {
+    fn from(value: f32) -> Self {
+        Ior::Constant(value.into())
+    }
+}
+
+/// Beer-Lambert absorption inside a dielectric: light travelling `distance` through the medium
+/// is tinted by `color`.
+#[derive(Debug, Clone, Copy)]
+pub struct Absorption {
+    pub color: Color,
+    pub distance: f32,
+}
+
+impl Absorption {
+    pub fn transmittance(&self, distance: f32) -> Color {
+        // exp(-σa d) with σa = -ln(color) / self.distance
+        self.color.powf(distance / self.distance)
+    }
+}
+
+/// Complex index of refraction of a conductor, per RGB channel.
+#[derive(Debug, Clone, Copy)]
+pub struct ComplexIor {
+    pub eta: Color,
+    pub k: Color,
+}
+
+impl ComplexIor {
+    pub const GOLD: ComplexIor = ComplexIor::new([0.143, 0.374, 1.442], [3.983, 2.385, 1.603]);
+    pub const COPPER: ComplexIor = ComplexIor::new([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]);
+    pub const ALUMINIUM: ComplexIor = ComplexIor::new([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]);
+    pub const SILVER: ComplexIor = ComplexIor::new([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]);
+    pub const CHROME: ComplexIor = ComplexIor::new([3.180, 3.180, 2.010], [3.300, 3.330, 3.040]);
+
+    pub const fn new(eta: [f32; 3], k: [f32; 3]) -> Self {
+        ComplexIor {
+            eta: Vec3::from_array(eta),
+            k: Vec3::from_array(k),
+        }
+    }
+
+    pub fn fresnel(&self, cos_theta_i: f32) -> Color {
+        Vec3::new(
+            fresnel_complex(cos_theta_i, Complex::new(self.eta.x, self.k.x)),
+            fresnel_complex(cos_theta_i, Complex::new(self.eta.y, self.k.y)),
+            fresnel_complex(cos_theta_i, Complex::new(self.eta.z, self.k.z)),
+        )
+    }
+}
+
+/// How much light a [`Conductor`] reflects depending on the angle of incidence.
+#[derive(Debug)]
+pub enum Reflectance {
+    /// The Fresnel equations for a measured complex index of refraction.
+    Complex(ComplexIor),
+    /// Schlick's approximation from the colour at normal incidence, as in the glTF
+    /// metallic-roughness model.
+    Schlick(Arc<Texture>),
+}
+
+impl Reflectance {
+    fn at(&self, cos_theta_i: f32, hit: &HitRecord) -> Color {
+        match self {
+            Reflectance::Complex(ior) => ior.fresnel(cos_theta_i),
+            Reflectance::Schlick(f0) => {
+                let f0 = f0.value_at(hit.tex_coords, hit.point);
+                f0 + (Color::ONE - f0) * (1.0 - cos_theta_i).clamp(0.0, 1.0).powi(5)
+            }
+        }
+    }
+}
+
+impl From<ComplexIor> for Reflectance {
+    fn from(ior: ComplexIor) -> Self {
+        Reflectance::Complex(ior)
+    }
+}
+
+/// A metal reflecting according to its [`Reflectance`], either as a perfect mirror or, when
+/// rough, through a Trowbridge-Reitz microfacet distribution.
+#[derive(Debug)]
+pub struct Conductor {
+    pub reflectance: Reflectance,
+    pub roughness: ScalarTexture,
+}
+
+impl Scatterable for Conductor {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let frame = Onb::build_from_w(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let roughness = self.roughness.value_at(hit.tex_coords, hit.point);
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness.clamp(0.0, 1.0));
+        let distribution = TrowbridgeReitz::new(alpha, alpha);
+        if distribution.effectively_smooth() {
+            let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+            let cos_theta = math::abs_cos_theta(wi);
+            return Some(ScatterResult {
+                // the renderer applies the cosine term, which a mirror doesn't have
+                attenuation: self.reflectance.at(cos_theta, hit) / cos_theta,
+                scattered: hit.spawn_ray(frame.local_vec(wi)),
+                pdf: None,
+                dispersive: false,
+                interior: None,
+            });
+        }
+
+        let wm = distribution.sample_wm(wo, vec3::random::gen_2d());
+        let wi = reflect(-wo, wm);
+        if wo.z * wi.z <= 0.0 {
+            return None;
+        }
+
+        let cos_theta_o = math::abs_cos_theta(wo);
+        let cos_theta_i = math::abs_cos_theta(wi);
+        let cos_theta_m = wo.dot(wm).abs();
+        let pdf = distribution.pdf(wo, wm) / (4.0 * cos_theta_m);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = distribution.d(wm) * self.reflectance.at(cos_theta_m, hit) * distribution.g(wo, wi)
+            / (4.0 * cos_theta_i * cos_theta_o);
+
+        Some(ScatterResult {
+            attenuation: f,
+            scattered: hit.spawn_ray(frame.local_vec(wi)),
+            pdf: Some(pdf),
+            dispersive: false,
+            interior: None,
+        })
+    }
+}
+
+/// Samples a Trowbridge-Reitz reflection lobe without a Fresnel term, falling back to a mirror
+/// when the distribution is smooth. Directions are in the local frame of the surface.
+fn sample_glossy_reflection(
+    wo: Vec3,
+    distribution: &TrowbridgeReitz,
+) -> Option<(Vec3, Color, Option<f32>)> {
+    if distribution.effectively_smooth() {
+        let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+        return Some((wi, Vec3::splat(1.0 / wi.z), None));
+    }
+
+    let wm = distribution.sample_wm(wo, vec3::random::gen_2d());
+    let wi = reflect(-wo, wm);
+    if wi.z <= 0.0 {
+        return None;
+    }
+
+    let pdf = distribution.pdf(wo, wm) / (4.0 * wo.dot(wm).abs());
+    if pdf <= 0.0 {
+        return None;
+    }
+    let f = distribution.d(wm) * distribution.g(wo, wi) / (4.0 * wi.z * wo.z);
+    Some((wi, Vec3::splat(f), Some(pdf)))
+}
+
+/// A glossy dielectric layer reflecting part of the light by its Fresnel term and letting the
+/// rest through to the layers below. Used for clearcoats and for the specular reflection of
+/// non-metals.
+#[derive(Debug)]
+pub struct Coat {
+    pub weight: ScalarTexture,
+    pub tint: Arc<Texture>,
+    pub roughness: ScalarTexture,
+    pub ior: f32,
+}
+
+/// A retro-reflective sheen lobe for cloth, using the [`Charlie`] distribution.
+#[derive(Debug)]
+pub struct Sheen {
+    pub color: Arc<Texture>,
+    pub roughness: ScalarTexture,
+}
+
+/// A coat and a sheen layered over a base material, from top to bottom. Each layer is picked
+/// with the probability of it reflecting the incoming light and the remainder is passed on to
+/// the next one, so together the layers never reflect more light than arrives.
+#[derive(Debug)]
+pub struct Layered {
+    pub base: Arc<Material>,
+    pub coat: Option<Coat>,
+    pub sheen: Option<Sheen>,
+}
+
+impl Scatterable for Layered {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let frame = Onb::build_from_w(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        if wo.z <= 0.0 {
+            return self.base.scatter(ray, hit);
+        }
+
+        let (coords, point) = (hit.tex_coords, hit.point);
+        let mut weight = Vec3::ONE;
+
+        if let Some(coat) = &self.coat {
+            let reflectance = (coat.tint.value_at(coords, point)
+                * fresnel_dielectric(wo.z, coat.ior))
+            .min(Vec3::ONE)
+                * coat.weight.value_at(coords, point).clamp(0.0, 1.0);
+            let probability = reflectance.max_element();
+            if random() < probability {
+                let roughness = coat.roughness.value_at(coords, point).clamp(0.0, 1.0);
+                let alpha = TrowbridgeReitz::roughness_to_alpha(roughness);
+                let (wi, f, pdf) =
+                    sample_glossy_reflection(wo, &TrowbridgeReitz::new(alpha, alpha))?;
+                return Some(ScatterResult {
+                    attenuation: f * reflectance / probability,
+                    scattered: hit.spawn_ray(frame.local_vec(wi)),
+                    pdf,
+                    dispersive: false,
+                    interior: None,
+                });
+            }
+            weight *= (Vec3::ONE - reflectance) / (1.0 - probability);
+        }
+
+        if let Some(sheen) = &self.sheen {
+            let color = sheen.color.value_at(coords, point);
+            let roughness = sheen.roughness.value_at(coords, point).clamp(0.0, 1.0);
+            let charlie = Charlie::new(TrowbridgeReitz::roughness_to_alpha(roughness));
+            // the layers below are scaled by 1 - max(color) * albedo, which is exactly the
+            // probability of passing the sheen
+            let probability = (color.max_element() * charlie.albedo(wo.z)).clamp(0.0, 1.0);
+            if random() < probability {
+                let wi = sample::cosine_hemisphere(vec3::random::gen_2d());
+                if wi.z == 0.0 {
+                    return None;
+                }
+                return Some(ScatterResult {
+                    attenuation: weight * color * charlie.f(wo, wi) / probability,
+                    scattered: hit.spawn_ray(frame.local_vec(wi)),
+                    pdf: Some(cosine_hemisphere_pdf(wi.z)),
+                    dispersive: false,
+                    interior: None,
+                });
+            }
+        }
+
+        let mut result = self.base.scatter(ray, hit)?;
+        result.attenuation *= weight;
+        Some(result)
+    }
+
+    fn emit(&self, coords: TextureCoordinates, point: Point3) -> Color {
+        self.base.emit(coords, point)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Complex { re, im }
+    }
+
+    fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Complex {
+        let n = self.norm().sqrt();
+        if n == 0.0 {
+            return Complex::new(0.0, 0.0);
+        }
+
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0.0 {
+            Complex::new(t1, t2)
+        } else {
+            Complex::new(t2.abs(), t1.copysign(self.im))
+        }
+    }
+}
+
+impl From<f32> for Complex {
+    fn from(re: f32) -> Self {
+        Complex::new(re, 0.0)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+
+    fn div(self, rhs: Complex) -> Complex {
+        let scale = 1.0 / rhs.norm();
+        Complex::new(
+            (self.re * rhs.re + self.im * rhs.im) * scale,
+            (self.im * rhs.re - self.re * rhs.im) * scale,
+        )
+    }
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction `eta`.
+fn fresnel_complex(cos_theta_i: f32, eta: Complex) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let cos_i = Complex::from(cos_theta_i);
+    let sin2_theta_t = Complex::from(1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    let cos_t = (Complex::from(1.0) - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel.norm() + r_perpendicular.norm()) / 2.0
+}
+
+/// A glass-like interface that reflects or refracts according to the Fresnel equations, either
+/// perfectly smooth or through a Trowbridge-Reitz microfacet distribution when rough.
 #[derive(Debug)]
 pub struct Dielectric {
-    pub refraction_index: f32,
+    pub ior: Ior,
+    pub roughness: ScalarTexture,
+    pub absorption: Option<Absorption>,
+    /// Colour filtering light refracted into the surface, as glTF's base colour does for
+    /// transmissive materials.
+    pub tint: Option<Arc<Texture>>,
 }
 
 impl Scatterable for Dielectric {
     fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
-        let ri = if hit.front_facing {
-            1.0 / self.refraction_index
-        } else {
-            self.refraction_index
-        };
+        let eta = self.ior.at(ray.wavelength, hit.tex_coords, hit.point);
+        // relative index of refraction across the surface in the direction of the ray
+        let etap = if hit.front_facing { eta } else { 1.0 / eta };
 
-        let unit_direction = ray.direction.normalize();
-        let cos_theta = (-unit_direction).dot(hit.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let frame = Onb::build_from_w(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
 
-        let cannot_refract = ri * sin_theta > 1.0;
-        let direction = if cannot_refract || reflectance(cos_theta, ri) > random() {
-            reflect(unit_direction, hit.normal)
+        let roughness = self.roughness.value_at(hit.tex_coords, hit.point);
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness.clamp(0.0, 1.0));
+        let distribution = TrowbridgeReitz::new(alpha, alpha);
+        let (wi, mut attenuation, pdf) = if eta == 1.0 || distribution.effectively_smooth() {
+            sample_specular_dielectric(wo, etap)?
         } else {
-            refract(unit_direction, hit.normal, ri)
+            sample_rough_dielectric(wo, etap, &distribution)?
         };
 
+        // tint once on the way in, so light passing through both sides is filtered only once
+        if wi.z < 0.0 && hit.front_facing {
+            if let Some(tint) = &self.tint {
+                attenuation *= tint.value_at(hit.tex_coords, hit.point);
+            }
+        }
+
+        // the path from the entry point to here ran through the medium
+        if let Some(absorption) = self.absorption.filter(|_| !hit.front_facing) {
+            attenuation *= absorption.transmittance(hit.distance * ray.direction.length());
+        }
+
         Some(ScatterResult {
-            attenuation: Vec3::new(1.0, 1.0, 1.0),
-            scattered: Ray::new(hit.point, direction),
-            pdf: None,
+            attenuation,
+            scattered: hit.spawn_ray(frame.local_vec(wi)),
+            pdf,
+            dispersive: self.ior.is_dispersive(),
+            interior: None,
+        })
+    }
+}
+
+/// Translucent materials like skin, wax and marble, where light refracts into a closed mesh
+/// and takes a random walk through the homogeneous medium inside before leaving it somewhere
+/// else. The medium is derived from the multiple scattering `albedo` the surface should
+/// appear to have and the mean free path of light inside, in scene units per colour channel.
+#[derive(Debug)]
+pub struct Subsurface {
+    pub albedo: Arc<Texture>,
+    pub mean_free_path: Color,
+    /// Anisotropy of the scattering inside, see [`HenyeyGreenstein`](crate::medium::HenyeyGreenstein).
+    pub g: f32,
+    pub boundary: Dielectric,
+    /// The medium inside, derived once up front when the albedo is the same everywhere.
+    medium: Option<Arc<Medium>>,
+}
+
+impl Scatterable for Subsurface {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let mut result = self.boundary.scatter(ray, hit)?;
+        result.interior = Some(match &self.medium {
+            Some(medium) => medium.clone(),
+            None => {
+                let albedo = self.albedo.value_at(hit.tex_coords, hit.point);
+                Medium::subsurface(albedo, self.mean_free_path, self.g)
+            }
+        });
+        Some(result)
+    }
+}
+
+/// Picks reflection or refraction of a smooth interface in proportion to the Fresnel
+/// reflectance. Directions are in the local frame of the surface, on the side of `wo`.
+fn sample_specular_dielectric(wo: Vec3, etap: f32) -> Option<(Vec3, Color, Option<f32>)> {
+    let cos_theta_o = wo.z;
+    let r = fresnel_dielectric(cos_theta_o, etap);
+    // the weights are divided by the cosine term the renderer applies, which specular
+    // scattering doesn't have
+    if random() < r {
+        let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+        Some((wi, Vec3::ONE / cos_theta_o, None))
+    } else {
+        let wi = refract(-wo, Vec3::Z, 1.0 / etap);
+        let cos_theta_i = math::abs_cos_theta(wi);
+        // radiance is compressed into the smaller solid angle on the denser side
+        Some((wi, Vec3::ONE / (cos_theta_i * etap * etap), None))
+    }
+}
+
+/// Samples a rough interface by first sampling a visible microfacet normal, then reflecting or
+/// refracting through it.
+fn sample_rough_dielectric(
+    wo: Vec3,
+    etap: f32,
+    distribution: &TrowbridgeReitz,
+) -> Option<(Vec3, Color, Option<f32>)> {
+    let wm = distribution.sample_wm(wo, vec3::random::gen_2d());
+    let cos_theta_m = wo.dot(wm);
+    let r = fresnel_dielectric(cos_theta_m, etap);
+    let t = 1.0 - r;
+    let cos_theta_o = wo.z;
+
+    if random() < r {
+        let wi = reflect(-wo, wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+
+        let pdf = distribution.pdf(wo, wm) / (4.0 * cos_theta_m.abs()) * r;
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = distribution.d(wm) * distribution.g(wo, wi) * r / (4.0 * wi.z * cos_theta_o);
+        Some((wi, Vec3::splat(f), Some(pdf)))
+    } else {
+        let sin2_theta_t = (1.0 - cos_theta_m * cos_theta_m) / (etap * etap);
+        if sin2_theta_t >= 1.0 {
+            return None;
+        }
+
+        let wi = refract(-wo, wm, 1.0 / etap);
+        if wi.z >= 0.0 {
+            return None;
+        }
+
+        let denominator = (wi.dot(wm) + cos_theta_m / etap).powi(2);
+        let dwm_dwi = wi.dot(wm).abs() / denominator;
+        let pdf = distribution.pdf(wo, wm) * dwm_dwi * t;
+        if pdf <= 0.0 {
+            // the sampled microfacet normal underflowed the distribution
+            return None;
+        }
+        let f = t
+            * distribution.d(wm)
+            * distribution.g(wo, wi)
+            * (wi.dot(wm) * cos_theta_m / (wi.z * cos_theta_o * denominator)).abs()
+            / (etap * etap);
+        Some((wi, Vec3::splat(f), Some(pdf)))
+    }
+}
+
+/// An infinitely thin dielectric sheet such as a window pane, modelled as two parallel
+/// interfaces: light is reflected or passes through without bending, accounting for the
+/// inter-reflections between both sides. A rough sheet spreads both around the mirror and the
+/// straight-through direction by the same Trowbridge-Reitz lobe.
+#[derive(Debug)]
+pub struct ThinDielectric {
+    pub ior: Ior,
+    pub roughness: ScalarTexture,
+    pub tint: Option<Arc<Texture>>,
+}
+
+impl Scatterable for ThinDielectric {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let eta = self.ior.at(ray.wavelength, hit.tex_coords, hit.point);
+        let frame = Onb::build_from_w(hit.normal);
+        let wo = frame.to_local(-ray.direction.normalize());
+        if wo.z <= 0.0 {
+            return None;
+        }
+
+        let mut r = fresnel_dielectric(wo.z, eta);
+        if r < 1.0 {
+            // sum the light bouncing back and forth between the two interfaces
+            r += (1.0 - r) * (1.0 - r) * r / (1.0 - r * r);
+        }
+
+        let roughness = self.roughness.value_at(hit.tex_coords, hit.point);
+        let alpha = TrowbridgeReitz::roughness_to_alpha(roughness.clamp(0.0, 1.0));
+        let (mut wi, mut attenuation, pdf) =
+            sample_glossy_reflection(wo, &TrowbridgeReitz::new(alpha, alpha))?;
+        // transmission mirrors the reflected lobe to the other side, so both share the weight
+        // and picking one with the probability of its share leaves it unchanged
+        if random() >= r {
+            wi.z = -wi.z;
+            if let Some(tint) = &self.tint {
+                attenuation *= tint.value_at(hit.tex_coords, hit.point);
+            }
+        }
+
+        Some(ScatterResult {
+            attenuation,
+            scattered: hit.spawn_ray(frame.local_vec(wi)),
+            pdf,
+            dispersive: self.ior.is_dispersive(),
+            interior: None,
         })
     }
 }
 
-/// Schlick's approximation for reflectance.
-fn reflectance(cosine: f32, refraction_index: f32) -> f32 {
-    let mut r0 = (1.0 - refraction_index) / (1.0 + refraction_index);
-    r0 *= r0;
-    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+/// Unpolarised Fresnel reflectance of a dielectric interface, where `eta` is the ratio of the
+/// index of refraction on the far side to the one on the incident side.
+fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i.max(-1.0), 1.0 / eta)
+    } else {
+        (cos_theta_i.min(1.0), eta)
+    };
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // total internal reflection
+        return 1.0;
+    }
+
+    let cos_theta_t = math::safe_sqrt(1.0 - sin2_theta_t);
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
 }
 
 #[derive(Debug)]
 pub struct DiffuseLight {
     pub texture: Arc<Texture>,
-    pub strength: f32,
+    pub strength: ScalarTexture,
 }
 
 impl Scatterable for DiffuseLight {
@@ -132,7 +763,25 @@ impl Scatterable for DiffuseLight {
     }
 
     fn emit(&self, uv: TextureCoordinates, point: Point3) -> Color {
-        self.texture.value_at(uv, point) * self.strength
+        self.texture.value_at(uv, point) * self.strength.value_at(uv, point)
+    }
+}
+
+/// An invisible surface that only marks the boundary of a participating medium, letting rays
+/// pass through unchanged.
+#[derive(Debug)]
+pub struct Interface;
+
+impl Scatterable for Interface {
+    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
+        let direction = ray.direction.normalize();
+        Some(ScatterResult {
+            attenuation: Color::splat(1.0 / direction.dot(hit.normal).abs()),
+            scattered: hit.spawn_ray(direction),
+            pdf: None,
+            dispersive: false,
+            interior: None,
+        })
     }
 }
 
@@ -144,10 +793,24 @@ pub enum Material {
     Dielectric(Dielectric),
     DiffuseLight(DiffuseLight),
     Mix(Mix),
-    TrowbridgeReitz(TrowbridgeReitz),
+    Conductor(Conductor),
+    Layered(Layered),
+    ThinDielectric(ThinDielectric),
+    DiffuseTransmission(DiffuseTransmission),
+    Interface(Interface),
+    Subsurface(Subsurface),
 }
 
 impl Material {
+    pub fn interface() -> Arc<Material> {
+        Arc::new(Material::Interface(Interface))
+    }
+
+    /// Whether the surface only bounds a medium, so hitting it is not a scattering event.
+    pub fn is_interface(&self) -> bool {
+        matches!(self, Material::Interface(_))
+    }
+
     pub fn lambertian(albedo: Vec3) -> Arc<Material> {
         Arc::new(Material::Lambertian(Lambertian {
             texture: Arc::new(Texture::SolidColor(SolidColor { albedo })),
@@ -158,58 +821,460 @@ impl Material {
         Arc::new(Material::Lambertian(Lambertian { texture }))
     }
 
-    pub fn metal(texture: Arc<Texture>, fuzz: f32) -> Arc<Material> {
-        Arc::new(Material::Metal(Metal { texture, fuzz }))
+    pub fn metal(texture: Arc<Texture>, fuzz: impl Into<ScalarTexture>) -> Arc<Material> {
+        Arc::new(Material::Metal(Metal {
+            texture,
+            fuzz: fuzz.into(),
+        }))
     }
 
-    pub fn mix(left: Arc<Material>, right: Arc<Material>, factor: f32) -> Arc<Material> {
+    pub fn conductor(
+        reflectance: impl Into<Reflectance>,
+        roughness: impl Into<ScalarTexture>,
+    ) -> Arc<Material> {
+        Arc::new(Material::Conductor(Conductor {
+            reflectance: reflectance.into(),
+            roughness: roughness.into(),
+        }))
+    }
+
+    pub fn mix(
+        left: Arc<Material>,
+        right: Arc<Material>,
+        factor: impl Into<ScalarTexture>,
+    ) -> Arc<Material> {
         Arc::new(Material::Mix(Mix {
             left,
             right,
-            factor,
+            factor: factor.into(),
         }))
     }
 
-    pub fn dielectric(refraction_index: f32) -> Arc<Material> {
-        Arc::new(Material::Dielectric(Dielectric { refraction_index }))
+    pub fn dielectric(ior: impl Into<Ior>) -> Arc<Material> {
+        Arc::new(Material::Dielectric(Dielectric {
+            ior: ior.into(),
+            roughness: ScalarTexture::Constant(0.0),
+            absorption: None,
+            tint: None,
+        }))
+    }
+
+    pub fn absorbing_dielectric(ior: impl Into<Ior>, absorption: Absorption) -> Arc<Material> {
+        Arc::new(Material::Dielectric(Dielectric {
+            ior: ior.into(),
+            roughness: ScalarTexture::Constant(0.0),
+            absorption: Some(absorption),
+            tint: None,
+        }))
+    }
+
+    pub fn thin_dielectric(ior: impl Into<Ior>) -> Arc<Material> {
+        Arc::new(Material::ThinDielectric(ThinDielectric {
+            ior: ior.into(),
+            roughness: ScalarTexture::Constant(0.0),
+            tint: None,
+        }))
+    }
+
+    pub fn diffuse_transmission(
+        reflectance: Arc<Texture>,
+        transmittance: Arc<Texture>,
+    ) -> Arc<Material> {
+        Arc::new(Material::DiffuseTransmission(DiffuseTransmission {
+            reflectance,
+            transmittance,
+        }))
+    }
+
+    pub fn subsurface(
+        albedo: Arc<Texture>,
+        mean_free_path: Color,
+        g: f32,
+        ior: impl Into<Ior>,
+        roughness: impl Into<ScalarTexture>,
+    ) -> Arc<Material> {
+        let medium = match &*albedo {
+            Texture::SolidColor(color) => Some(Medium::subsurface(color.albedo, mean_free_path, g)),
+            _ => None,
+        };
+        Arc::new(Material::Subsurface(Subsurface {
+            albedo,
+            mean_free_path,
+            g,
+            medium,
+            boundary: Dielectric {
+                ior: ior.into(),
+                roughness: roughness.into(),
+                absorption: None,
+                tint: None,
+            },
+        }))
+    }
+
+    pub fn diffuse_light(
+        texture: Arc<Texture>,
+        strength: impl Into<ScalarTexture>,
+    ) -> Arc<Material> {
+        Arc::new(Material::DiffuseLight(DiffuseLight {
+            texture,
+            strength: strength.into(),
+        }))
+    }
+
+    /// The glTF metallic-roughness model: the non-metallic part of the surface mixed with a
+    /// conductor reflecting `base_color` at normal incidence, weighted by `metallic`.
+    pub fn metallic_roughness(
+        dielectric: Arc<Material>,
+        base_color: Arc<Texture>,
+        metallic: ScalarTexture,
+        roughness: ScalarTexture,
+    ) -> Arc<Material> {
+        if matches!(metallic, ScalarTexture::Constant(m) if m <= 0.0) {
+            return dielectric;
+        }
+        let metal = Material::conductor(Reflectance::Schlick(base_color), roughness);
+        Material::mix(dielectric, metal, metallic)
+    }
+
+    pub fn layered(base: Arc<Material>, coat: Option<Coat>, sheen: Option<Sheen>) -> Arc<Material> {
+        Arc::new(Material::Layered(Layered { base, coat, sheen }))
     }
 }
 
+/// Scatters off either `left` or `right`, picked at random with the probability of its weight.
 #[derive(Debug)]
 pub struct Mix {
     pub left: Arc<Material>,
     pub right: Arc<Material>,
-    // TODO actually, the mix factor could also be a texture
-    pub factor: f32,
+    /// Weight of `right`, in `[0, 1]`.
+    pub factor: ScalarTexture,
 }
 
 impl Scatterable for Mix {
     fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
-        match self.factor {
-            1.0 => self.right.scatter(ray, hit),
-            0.0 => self.left.scatter(ray, hit),
-            _ => {
-                // FIXME
-                let left = self.left.scatter(ray, hit);
-                let right = self.right.scatter(ray, hit);
-                match (left, right) {
-                    (Some(l), Some(r)) => Some(l.mix(r, self.factor)),
-                    (None, Some(r)) => Some(r),
-                    (Some(l), None) => Some(l),
-                    (None, None) => None,
+        let factor = self
+            .factor
+            .value_at(hit.tex_coords, hit.point)
+            .clamp(0.0, 1.0);
+        // choosing a lobe with the probability of its weight makes the result of that lobe an
+        // unbiased estimate of the mixture, so it is returned as is
+        random::choose(&self.right, &self.left, factor).scatter(ray, hit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::sync::Arc;
+
+    use super::{
+        fresnel_complex, fresnel_dielectric, sample_rough_dielectric, Coat, Complex, ComplexIor,
+        Dielectric, Ior, Material, Scatterable, Sheen, ThinDielectric,
+    };
+    use crate::microfacet::TrowbridgeReitz;
+    use crate::object::HitRecord;
+    use crate::ray::Ray;
+    use crate::texture::{Texture, TextureCoordinates};
+    use crate::vec3::{Color, Point3, Vec3};
+
+    /// Scatters `count` rays arriving at `cos_theta` to the normal of a surface made of
+    /// `material`, returning the throughput weights the renderer applies along with the cosine
+    /// of each scattered direction, negative when transmitted. Absorbed rays weigh nothing.
+    fn scatter_samples(
+        material: &Arc<Material>,
+        cos_theta: f32,
+        count: usize,
+    ) -> Vec<(Color, f32)> {
+        let direction = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, -cos_theta);
+        let ray = Ray::new(Point3::ZERO - direction, direction);
+        let hit = HitRecord::new(
+            &ray,
+            Vec3::Z,
+            Point3::ZERO,
+            1.0,
+            material.clone(),
+            TextureCoordinates::new(0.0, 0.0),
+        );
+
+        (0..count)
+            .map(|_| match material.scatter(&ray, &hit) {
+                Some(sample) => {
+                    let cos_theta_i = sample.scattered.direction.normalize().dot(hit.normal);
+                    let weight = sample.attenuation * cos_theta_i.abs() / sample.pdf.unwrap_or(1.0);
+                    (weight, cos_theta_i)
                 }
+                None => (Color::ZERO, 0.0),
+            })
+            .collect()
+    }
+
+    fn throughputs(material: &Arc<Material>, cos_theta: f32, count: usize) -> Vec<Color> {
+        scatter_samples(material, cos_theta, count)
+            .into_iter()
+            .map(|(weight, _)| weight)
+            .collect()
+    }
+
+    fn mean(values: &[Color]) -> Color {
+        values.iter().sum::<Color>() / values.len() as f32
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        // (n - 1)² / (n + 1)² at normal incidence
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-6);
+        assert!((fresnel_dielectric(-1.0, 1.0 / 1.5) - 0.04).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+        assert!(fresnel_dielectric(0.0, 1.5) > 0.999);
+    }
+
+    #[test]
+    fn test_sellmeier_presets() {
+        let at =
+            |ior: &Ior, lambda| ior.at(lambda, TextureCoordinates::new(0.0, 0.0), Point3::ZERO);
+        assert!((at(&Ior::BK7, None) - 1.5168).abs() < 1e-3);
+        assert!((at(&Ior::FUSED_SILICA, None) - 1.4585).abs() < 1e-3);
+        assert!((at(&Ior::DIAMOND, None) - 2.417).abs() < 1e-3);
+        assert!(at(&Ior::BK7, Some(450.0)) > at(&Ior::BK7, Some(650.0)));
+    }
+
+    #[test]
+    fn test_fresnel_complex() {
+        // with no absorption, the conductor formula matches the dielectric one
+        let dielectric = fresnel_dielectric(0.7, 1.5);
+        assert!((fresnel_complex(0.7, Complex::new(1.5, 0.0)) - dielectric).abs() < 1e-5);
+
+        let gold = ComplexIor::GOLD.fresnel(1.0);
+        assert!(
+            gold.x > gold.z,
+            "gold should reflect more red than blue: {gold}"
+        );
+        assert!((ComplexIor::SILVER.fresnel(0.0) - 1.0).abs().max_element() < 1e-4);
+    }
+
+    #[test]
+    fn test_rough_dielectric_conserves_energy() {
+        let distribution = TrowbridgeReitz::new(0.2, 0.2);
+        let wo = Vec3::new(0.5, 0.0, 0.75f32.sqrt());
+        let etap = 1.5;
+        let count = 20000;
+
+        let mut total = 0.0;
+        for _ in 0..count {
+            if let Some((wi, f, Some(pdf))) = sample_rough_dielectric(wo, etap, &distribution) {
+                // undo the radiance scaling of refraction to compare energy
+                let scale = if wi.z < 0.0 { etap * etap } else { 1.0 };
+                total += f.x * wi.z.abs() / pdf * scale;
             }
         }
+
+        let albedo = total / count as f32;
+        assert!(albedo > 0.9 && albedo < 1.01, "albedo was {albedo}");
     }
-}
 
-#[derive(Debug)]
-pub struct TrowbridgeReitz {
-    pub roughness: f32,
-}
+    #[test]
+    fn test_mix_picks_one_lobe() {
+        let mix = Material::mix(
+            Material::lambertian(Color::splat(0.2)),
+            Material::lambertian(Color::ONE),
+            0.25,
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let hit = HitRecord::new(
+            &ray,
+            Vec3::Z,
+            Point3::ZERO,
+            1.0,
+            mix.clone(),
+            TextureCoordinates::new(0.0, 0.0),
+        );
 
-impl Scatterable for TrowbridgeReitz {
-    fn scatter(&self, ray: &Ray, hit: &HitRecord) -> Option<ScatterResult> {
-        todo!()
+        // every sample is one lobe's own result, weighted by how often that lobe is picked
+        let count = 20000;
+        let mut total = 0.0;
+        for _ in 0..count {
+            let sample = mix.scatter(&ray, &hit).unwrap();
+            let albedo = sample.attenuation.x * PI;
+            assert!(
+                (albedo - 0.2).abs() < 1e-4 || (albedo - 1.0).abs() < 1e-4,
+                "got {albedo}"
+            );
+            assert!(sample.pdf.is_some());
+            total += albedo;
+        }
+        let albedo = total / count as f32;
+        assert!((albedo - 0.4).abs() < 0.02, "albedo was {albedo}");
+    }
+
+    #[test]
+    fn test_metallic_roughness_reflects_base_color() {
+        let base_color = Color::new(0.9, 0.6, 0.2);
+        let metal = Material::metallic_roughness(
+            Material::lambertian(Color::ONE),
+            Texture::solid_color(base_color),
+            1.0.into(),
+            0.0.into(),
+        );
+        assert!(matches!(&*metal, Material::Mix(_)));
+
+        let reflected = |cos_theta: f32| {
+            let direction = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, -cos_theta);
+            let ray = Ray::new(Point3::ZERO - direction, direction);
+            let hit = HitRecord::new(
+                &ray,
+                Vec3::Z,
+                Point3::ZERO,
+                1.0,
+                metal.clone(),
+                TextureCoordinates::new(0.0, 0.0),
+            );
+            let sample = metal.scatter(&ray, &hit).unwrap();
+            sample.attenuation * sample.scattered.direction.normalize().dot(hit.normal).abs()
+        };
+        // a smooth metal reflects its base colour at normal incidence, and everything at grazing
+        assert!((reflected(1.0) - base_color).abs().max_element() < 1e-5);
+        assert!(reflected(0.01).min_element() > 0.9);
+
+        let dielectric = Material::lambertian(Color::ONE);
+        let plain = Material::metallic_roughness(
+            dielectric.clone(),
+            Texture::solid_color(base_color),
+            0.0.into(),
+            0.0.into(),
+        );
+        assert!(Arc::ptr_eq(&plain, &dielectric));
+    }
+
+    #[test]
+    fn test_dielectric_tints_once() {
+        let tint = Color::new(0.8, 0.5, 0.2);
+        let glass = Arc::new(Material::Dielectric(Dielectric {
+            ior: 1.0.into(),
+            roughness: 0.0.into(),
+            absorption: None,
+            tint: Some(Texture::solid_color(tint)),
+        }));
+
+        // an index of one refracts straight through, on the way in and out
+        let entering = throughputs(&glass, 1.0, 1)[0];
+        assert!((entering - tint).abs().max_element() < 1e-5);
+
+        let ray = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::Z);
+        let hit = HitRecord::new(
+            &ray,
+            Vec3::Z,
+            Point3::ZERO,
+            1.0,
+            glass.clone(),
+            TextureCoordinates::new(0.0, 0.0),
+        );
+        assert!(!hit.front_facing);
+        let leaving = glass.scatter(&ray, &hit).unwrap();
+        assert!((leaving.attenuation - Color::ONE).abs().max_element() < 1e-5);
+    }
+
+    #[test]
+    fn test_layered_furnace() {
+        let layered = Material::layered(
+            Material::lambertian(Color::ONE),
+            Some(Coat {
+                weight: 1.0.into(),
+                tint: Texture::solid_color(Color::ONE),
+                roughness: 0.3.into(),
+                ior: 1.5,
+            }),
+            Some(Sheen {
+                color: Texture::solid_color(Color::ONE),
+                roughness: 0.5.into(),
+            }),
+        );
+
+        // a white base under white layers loses at most what the rough lobes lose to shadowing
+        for cos_theta in [1.0, 0.7, 0.3] {
+            let albedo = mean(&throughputs(&layered, cos_theta, 40000));
+            assert!(
+                albedo.max_element() < 1.02,
+                "albedo was {albedo} at {cos_theta}"
+            );
+            assert!(
+                albedo.min_element() > 0.95,
+                "albedo was {albedo} at {cos_theta}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_thin_dielectric_energy() {
+        let cos_theta = 0.6;
+        let r = fresnel_dielectric(cos_theta, 1.5);
+        let t = 1.0 - r;
+        let expected = r + t * t * r / (1.0 - r * r);
+        let count = 40000;
+
+        let smooth = scatter_samples(&Material::thin_dielectric(1.5), cos_theta, count);
+        let reflected = smooth.iter().filter(|(_, cos)| *cos > 0.0).count();
+        assert!((reflected as f32 / count as f32 - expected).abs() < 0.01);
+        for (weight, cos) in &smooth {
+            assert!((weight.x - 1.0).abs() < 1e-4);
+            assert!((cos.abs() - cos_theta).abs() < 1e-4);
+        }
+
+        let rough = Arc::new(Material::ThinDielectric(ThinDielectric {
+            ior: 1.5.into(),
+            roughness: 0.3.into(),
+            tint: None,
+        }));
+        let rough = scatter_samples(&rough, cos_theta, count);
+        let weights: Vec<_> = rough.iter().map(|(weight, _)| *weight).collect();
+        let albedo = mean(&weights).x;
+        assert!(albedo > 0.9 && albedo < 1.01, "albedo was {albedo}");
+        // roughness spreads the transmitted light around the straight-through direction
+        assert!(rough
+            .iter()
+            .any(|(_, cos)| *cos < 0.0 && (cos + cos_theta).abs() > 0.05));
+    }
+
+    #[test]
+    fn test_diffuse_transmission_splits_energy() {
+        let translucent = Material::diffuse_transmission(
+            Texture::solid_color(Color::splat(0.3)),
+            Texture::solid_color(Color::splat(0.5)),
+        );
+        let samples = scatter_samples(&translucent, 0.8, 40000);
+
+        let sum = |transmitted: bool| {
+            samples
+                .iter()
+                .filter(|(_, cos)| (*cos < 0.0) == transmitted)
+                .map(|(weight, _)| weight.x)
+                .sum::<f32>()
+                / samples.len() as f32
+        };
+        assert!((sum(false) - 0.3).abs() < 0.01, "reflected {}", sum(false));
+        assert!((sum(true) - 0.5).abs() < 0.01, "transmitted {}", sum(true));
+    }
+
+    #[test]
+    fn test_subsurface_shares_constant_medium() {
+        let skin = Material::subsurface(
+            Texture::solid_color(Color::new(0.8, 0.5, 0.4)),
+            Color::splat(0.1),
+            0.0,
+            1.0,
+            0.0,
+        );
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let hit = HitRecord::new(
+            &ray,
+            Vec3::Z,
+            Point3::ZERO,
+            1.0,
+            skin.clone(),
+            TextureCoordinates::new(0.0, 0.0),
+        );
+
+        let first = skin.scatter(&ray, &hit).unwrap().interior.unwrap();
+        let second = skin.scatter(&ray, &hit).unwrap().interior.unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
     }
 }
@@ -7,3 +7,44 @@ pub fn safe_sqrt(u: f32) -> f32 {
 pub fn abs_cos_theta(v: Vec3A) -> f32 {
     v.z.abs()
 }
+
+pub fn cos2_theta(v: Vec3A) -> f32 {
+    v.z * v.z
+}
+
+pub fn sin2_theta(v: Vec3A) -> f32 {
+    (1.0 - cos2_theta(v)).max(0.0)
+}
+
+pub fn tan2_theta(v: Vec3A) -> f32 {
+    sin2_theta(v) / cos2_theta(v)
+}
+
+pub fn cos_phi(v: Vec3A) -> f32 {
+    let sin_theta = sin2_theta(v).sqrt();
+    if sin_theta == 0.0 {
+        1.0
+    } else {
+        (v.x / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+pub fn sin_phi(v: Vec3A) -> f32 {
+    let sin_theta = sin2_theta(v).sqrt();
+    if sin_theta == 0.0 {
+        0.0
+    } else {
+        (v.y / sin_theta).clamp(-1.0, 1.0)
+    }
+}
+
+pub fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    (1.0 - t) * a + t * b
+}
+
+/// Conservative bound on the relative rounding error of `n` chained floating point operations
+/// (Higham's γ<sub>n</sub>, as used by pbrt).
+pub fn gamma(n: u32) -> f32 {
+    let eps = f32::EPSILON * 0.5;
+    (n as f32 * eps) / (1.0 - n as f32 * eps)
+}
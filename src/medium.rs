@@ -0,0 +1,675 @@
+//! Participating media such as fog, smoke and coloured liquids, which absorb and scatter light
+//! along the rays travelling through them rather than only at surfaces.
+
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use enum_dispatch::enum_dispatch;
+use glam::{Affine3A, Vec2};
+
+use crate::math;
+use crate::onb::Onb;
+use crate::random::random;
+use crate::ray::Ray;
+use crate::vec3::{Color, Point3, Vec3};
+
+/// Transmittance below which ratio tracking plays Russian roulette with the ray.
+const ROULETTE_THRESHOLD: f32 = 0.1;
+
+/// The Henyey-Greenstein phase function, where `g` in `(-1, 1)` goes from back scattering over
+/// isotropic to forward scattering.
+#[derive(Debug, Clone, Copy)]
+pub struct HenyeyGreenstein {
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f32) -> Self {
+        HenyeyGreenstein {
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    /// Density of scattering by the angle whose cosine is `cos_theta`, measured from the
+    /// direction of propagation.
+    pub fn p(&self, cos_theta: f32) -> f32 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    /// Samples a new direction for light travelling along `direction`. The phase function is
+    /// sampled exactly, so the scattering weight is one.
+    pub fn sample(&self, direction: Vec3, u: Vec2) -> Vec3 {
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u.x
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u.x);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = math::safe_sqrt(1.0 - cos_theta * cos_theta);
+        let phi = 2.0 * PI * u.y;
+
+        Onb::build_from_w(direction).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+    }
+}
+
+/// What happened to a ray on its way through a medium up to the next surface.
+#[derive(Debug)]
+pub enum MediumEvent {
+    /// The ray scattered at `point`, continuing in a direction sampled from `phase`.
+    Scattered {
+        point: Point3,
+        weight: Color,
+        phase: HenyeyGreenstein,
+    },
+    /// The ray was absorbed and carries no more light.
+    Absorbed,
+    /// The ray reached the surface, with its throughput multiplied by `weight`.
+    Transmitted { weight: Color },
+}
+
+#[enum_dispatch]
+pub trait Participating {
+    /// Tracks `ray` through the medium up to the ray parameter `t_max`, where it hits the next
+    /// surface. `history` is the RGB throughput of the path so far, which the probabilities of
+    /// chromatic media follow to keep the path weights bounded.
+    fn sample_interaction(&self, ray: &Ray, t_max: f32, history: Color) -> MediumEvent;
+}
+
+#[enum_dispatch(Participating)]
+#[derive(Debug)]
+pub enum Medium {
+    Homogeneous(Homogeneous),
+    Grid(Grid),
+}
+
+impl Medium {
+    pub fn homogeneous(sigma_a: Color, sigma_s: Color, g: f32) -> Arc<Medium> {
+        Arc::new(Medium::Homogeneous(Homogeneous {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+        }))
+    }
+    /// The medium inside a translucent object whose multiple scattering albedo, the fraction of
+    /// light eventually leaving the surface again, is `albedo`. The single scattering albedo is
+    /// found with the fit of Chiang et al. (2016) for random walk subsurface scattering.
+    pub fn subsurface(albedo: Color, mean_free_path: Color, g: f32) -> Arc<Medium> {
+        let single_scattering = albedo.clamp(Color::ZERO, Color::ONE).to_array().map(|a| {
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - s * s
+        });
+        let sigma_t = mean_free_path.max(Color::splat(1e-6)).recip();
+        let sigma_s = sigma_t * Color::from(single_scattering);
+
+        Medium::homogeneous(sigma_t - sigma_s, sigma_s, g)
+    }
+}
+
+/// The media on both sides of a surface. Rays crossing the surface into the object continue
+/// in `inside`, those leaving it in `outside`, where `None` is vacuum.
+#[derive(Debug, Clone, Default)]
+pub struct MediumInterface {
+    pub inside: Option<Arc<Medium>>,
+    pub outside: Option<Arc<Medium>>,
+}
+
+impl MediumInterface {
+    /// The medium a ray continues in after leaving a surface it hit from the front or back in
+    /// `direction`, relative to the normal facing the incoming ray.
+    pub fn after(&self, front_facing: bool, normal: Vec3, direction: Vec3) -> Option<Arc<Medium>> {
+        let transmitted = direction.dot(normal) < 0.0;
+        if front_facing == transmitted {
+            self.inside.clone()
+        } else {
+            self.outside.clone()
+        }
+    }
+}
+
+/// A medium with the same absorption and scattering coefficients, in inverse scene units,
+/// everywhere.
+#[derive(Debug)]
+pub struct Homogeneous {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub phase: HenyeyGreenstein,
+}
+
+impl Participating for Homogeneous {
+    fn sample_interaction(&self, ray: &Ray, t_max: f32, history: Color) -> MediumEvent {
+        if self.sigma_s == Color::ZERO {
+            // nothing to scatter at, so the transmittance follows from Beer's law directly. The
+            // channels that absorb nothing pass through unbounded segments unharmed.
+            let distance = t_max * ray.direction.length();
+            let weight = Color::select(
+                self.sigma_a.cmpeq(Color::ZERO),
+                Color::ONE,
+                (-self.sigma_a * distance).exp(),
+            );
+            return MediumEvent::Transmitted { weight };
+        }
+
+        let segment = Segment {
+            t_min: 0.0,
+            t_max,
+            sigma_maj: (self.sigma_a + self.sigma_s).max_element(),
+        };
+        delta_track(ray, [segment], self.phase, history, |_| {
+            (self.sigma_a, self.sigma_s)
+        })
+    }
+}
+
+/// Densities on a regular grid of `nx × ny × nz` points spanning the unit cube, stored with x
+/// varying fastest.
+pub struct DensityGrid {
+    pub nx: usize,
+    pub ny: usize,
+    pub nz: usize,
+    pub values: Vec<f32>,
+}
+
+impl fmt::Debug for DensityGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DensityGrid")
+            .field("nx", &self.nx)
+            .field("ny", &self.ny)
+            .field("nz", &self.nz)
+            .field("max_value", &self.max_value())
+            .finish()
+    }
+}
+
+impl DensityGrid {
+    pub fn new(nx: usize, ny: usize, nz: usize, values: Vec<f32>) -> Self {
+        assert_eq!(values.len(), nx * ny * nz, "grid size mismatch");
+        DensityGrid { nx, ny, nz, values }
+    }
+
+    fn at(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(z * self.ny + y) * self.nx + x]
+    }
+
+    /// Trilinearly interpolated density at `p` in the unit cube.
+    pub fn lookup(&self, p: Point3) -> f32 {
+        let scaled = p * Vec3::new(self.nx as f32, self.ny as f32, self.nz as f32) - 0.5;
+        let clamp = |v: f32, n: usize| v.clamp(0.0, (n - 1) as f32);
+        let (x, y, z) = (
+            clamp(scaled.x, self.nx),
+            clamp(scaled.y, self.ny),
+            clamp(scaled.z, self.nz),
+        );
+        let (x0, y0, z0) = (x as usize, y as usize, z as usize);
+        let (x1, y1, z1) = (
+            (x0 + 1).min(self.nx - 1),
+            (y0 + 1).min(self.ny - 1),
+            (z0 + 1).min(self.nz - 1),
+        );
+        let (dx, dy, dz) = (x - x0 as f32, y - y0 as f32, z - z0 as f32);
+
+        let lerp_x = |y, z| math::lerp(dx, self.at(x0, y, z), self.at(x1, y, z));
+        let lerp_y = |z| math::lerp(dy, lerp_x(y0, z), lerp_x(y1, z));
+        math::lerp(dz, lerp_y(z0), lerp_y(z1))
+    }
+
+    pub fn max_value(&self) -> f32 {
+        self.values.iter().copied().fold(0.0, f32::max)
+    }
+}
+
+/// Resolution of the majorant grid along each axis.
+const MAJORANT_RES: usize = 16;
+
+/// A stretch of a ray, between the ray parameters `t_min` and `t_max`, over which the
+/// extinction coefficient is bounded by `sigma_maj`.
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    t_min: f32,
+    t_max: f32,
+    sigma_maj: f32,
+}
+
+/// Upper bounds of the densities of a [`DensityGrid`] over the cells of a coarser grid, so
+/// delta tracking can take long steps through the thin parts of a medium.
+pub struct MajorantGrid {
+    res: [usize; 3],
+    values: Vec<f32>,
+}
+
+impl fmt::Debug for MajorantGrid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MajorantGrid")
+            .field("res", &self.res)
+            .field(
+                "max_value",
+                &self.values.iter().copied().fold(0.0, f32::max),
+            )
+            .finish()
+    }
+}
+
+impl MajorantGrid {
+    pub fn new(density: &DensityGrid, res: [usize; 3]) -> Self {
+        let n = [density.nx, density.ny, density.nz];
+        // the voxels whose trilinear footprint overlaps cell `i` along `axis`
+        let voxels = |axis: usize, i: usize| {
+            let min = i as f32 / res[axis] as f32 * n[axis] as f32 - 0.5;
+            let max = (i + 1) as f32 / res[axis] as f32 * n[axis] as f32 - 0.5;
+            let last = n[axis] as isize - 1;
+            let lo = (min.floor() as isize).clamp(0, last) as usize;
+            let hi = (max.floor() as isize + 1).clamp(0, last) as usize;
+            lo..=hi
+        };
+
+        let mut values = Vec::with_capacity(res[0] * res[1] * res[2]);
+        for z in 0..res[2] {
+            for y in 0..res[1] {
+                for x in 0..res[0] {
+                    let mut max = 0.0f32;
+                    for vz in voxels(2, z) {
+                        for vy in voxels(1, y) {
+                            for vx in voxels(0, x) {
+                                max = max.max(density.at(vx, vy, vz));
+                            }
+                        }
+                    }
+                    values.push(max);
+                }
+            }
+        }
+
+        MajorantGrid { res, values }
+    }
+
+    fn at(&self, cell: [usize; 3]) -> f32 {
+        self.values[(cell[2] * self.res[1] + cell[1]) * self.res[0] + cell[0]]
+    }
+
+    /// Walks the cells that `ray`, in grid space, passes through between `t_min` and `t_max`
+    /// with a 3D DDA, returning their majorants multiplied by `scale`.
+    fn segments(
+        &self,
+        ray: &Ray,
+        t_min: f32,
+        t_max: f32,
+        scale: f32,
+    ) -> impl Iterator<Item = Segment> + '_ {
+        let start = ray.evaluate(t_min);
+        let mut cell = [0; 3];
+        let mut next_t = [f32::INFINITY; 3];
+        let mut delta_t = [f32::INFINITY; 3];
+        let mut step = [0isize; 3];
+        for axis in 0..3 {
+            let res = self.res[axis] as f32;
+            cell[axis] = ((start[axis] * res) as isize).clamp(0, self.res[axis] as isize - 1);
+            let direction = ray.direction[axis];
+            if direction == 0.0 {
+                continue;
+            }
+
+            delta_t[axis] = 1.0 / (res * direction.abs());
+            let (boundary, sign) = if direction > 0.0 {
+                (cell[axis] + 1, 1)
+            } else {
+                (cell[axis], -1)
+            };
+            next_t[axis] = t_min + (boundary as f32 / res - start[axis]) / direction;
+            step[axis] = sign;
+        }
+
+        let mut t = t_min;
+        std::iter::from_fn(move || {
+            if t >= t_max || cell.iter().any(|&c| c < 0) {
+                return None;
+            }
+            let index = cell.map(|c| c as usize);
+            if (0..3).any(|axis| index[axis] >= self.res[axis]) {
+                return None;
+            }
+
+            let axis = (0..3)
+                .min_by(|&a, &b| next_t[a].total_cmp(&next_t[b]))
+                .unwrap();
+            let segment = Segment {
+                t_min: t,
+                t_max: next_t[axis].min(t_max),
+                sigma_maj: self.at(index) * scale,
+            };
+
+            t = segment.t_max;
+            cell[axis] += step[axis];
+            next_t[axis] += delta_t[axis];
+            Some(segment)
+        })
+    }
+}
+
+/// A medium whose coefficients are scaled by the densities of a grid, like smoke or clouds.
+/// The grid fills the unit cube of its own space, placed in the scene by `transform`.
+#[derive(Debug)]
+pub struct Grid {
+    pub sigma_a: Color,
+    pub sigma_s: Color,
+    pub phase: HenyeyGreenstein,
+    pub density: DensityGrid,
+    world_to_grid: Affine3A,
+    majorants: MajorantGrid,
+}
+
+impl Grid {
+    pub fn new(
+        sigma_a: Color,
+        sigma_s: Color,
+        g: f32,
+        density: DensityGrid,
+        transform: Affine3A,
+    ) -> Self {
+        let res = [density.nx, density.ny, density.nz].map(|n| n.min(MAJORANT_RES));
+        Grid {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(g),
+            majorants: MajorantGrid::new(&density, res),
+            density,
+            world_to_grid: transform.inverse(),
+        }
+    }
+
+    /// The ray parameters where `ray`, in grid space, enters and leaves the unit cube.
+    fn clip(ray: &Ray, t_max: f32) -> Option<(f32, f32)> {
+        let inv_direction = ray.direction.recip();
+        let t0 = -ray.origin * inv_direction;
+        let t1 = (Vec3::ONE - ray.origin) * inv_direction;
+        let t_enter = t0.min(t1).max_element().max(0.0);
+        let t_exit = t0.max(t1).min_element().min(t_max);
+
+        (t_enter < t_exit).then_some((t_enter, t_exit))
+    }
+}
+
+impl Participating for Grid {
+    fn sample_interaction(&self, ray: &Ray, t_max: f32, history: Color) -> MediumEvent {
+        let grid_ray = Ray::new(
+            self.world_to_grid.transform_point3a(ray.origin),
+            self.world_to_grid.transform_vector3a(ray.direction),
+        );
+        let Some((t_min, t_max)) = Grid::clip(&grid_ray, t_max) else {
+            return MediumEvent::Transmitted { weight: Color::ONE };
+        };
+
+        // the coefficients are per unit of distance in the scene, not in the grid
+        let sigma_t = (self.sigma_a + self.sigma_s).max_element();
+        let segments = self.majorants.segments(&grid_ray, t_min, t_max, sigma_t);
+        let coefficients = |t| {
+            let density = self.density.lookup(grid_ray.evaluate(t));
+            (self.sigma_a * density, self.sigma_s * density)
+        };
+
+        if self.sigma_s == Color::ZERO {
+            ratio_track(ray, segments, coefficients)
+        } else {
+            delta_track(ray, segments, self.phase, history, coefficients)
+        }
+    }
+}
+
+/// Samples the next real collision along `ray` by delta tracking through `segments`, with
+/// `coefficients` returning the absorption and scattering coefficients at a ray parameter.
+/// Chromatic coefficients are handled with the history aware event probabilities of spectral
+/// tracking (Kutz et al. 2017), weighted by the throughput `history` of the path so far.
+fn delta_track(
+    ray: &Ray,
+    segments: impl IntoIterator<Item = Segment>,
+    phase: HenyeyGreenstein,
+    history: Color,
+    coefficients: impl Fn(f32) -> (Color, Color),
+) -> MediumEvent {
+    let length = ray.direction.length();
+    let mut weight = Color::ONE;
+    for Segment {
+        t_min,
+        t_max,
+        sigma_maj,
+    } in segments
+    {
+        if sigma_maj <= 0.0 {
+            continue;
+        }
+
+        // free flights are memoryless, so each segment can start sampling afresh
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - random()).ln() / (sigma_maj * length);
+            if t >= t_max {
+                break;
+            }
+
+            let (sigma_a, sigma_s) = coefficients(t);
+            let sigma_n = (Color::splat(sigma_maj) - sigma_a - sigma_s).max(Color::ZERO);
+            let path = history * weight;
+            let p_a = (sigma_a * path).element_sum();
+            let p_s = (sigma_s * path).element_sum();
+            let p_n = (sigma_n * path).element_sum();
+
+            let u = random() * (p_a + p_s + p_n);
+            if u < p_a {
+                return MediumEvent::Absorbed;
+            } else if u < p_a + p_s {
+                weight *= sigma_s * (p_a + p_s + p_n) / (p_s * sigma_maj);
+                return MediumEvent::Scattered {
+                    point: ray.evaluate(t),
+                    weight,
+                    phase,
+                };
+            } else {
+                weight *= sigma_n * (p_a + p_s + p_n) / (p_n * sigma_maj);
+            }
+        }
+    }
+
+    MediumEvent::Transmitted { weight }
+}
+
+/// Estimates the transmittance along `ray` through `segments` by ratio tracking, for media
+/// that only absorb.
+fn ratio_track(
+    ray: &Ray,
+    segments: impl IntoIterator<Item = Segment>,
+    coefficients: impl Fn(f32) -> (Color, Color),
+) -> MediumEvent {
+    let length = ray.direction.length();
+    let mut transmittance = Color::ONE;
+    for Segment {
+        t_min,
+        t_max,
+        sigma_maj,
+    } in segments
+    {
+        if sigma_maj <= 0.0 {
+            continue;
+        }
+
+        let mut t = t_min;
+        loop {
+            t -= (1.0 - random()).ln() / (sigma_maj * length);
+            if t >= t_max {
+                break;
+            }
+
+            let (sigma_a, _) = coefficients(t);
+            transmittance *= (Color::ONE - sigma_a / sigma_maj).max(Color::ZERO);
+
+            let max = transmittance.max_element();
+            if max < ROULETTE_THRESHOLD {
+                if random() < max {
+                    transmittance /= max;
+                } else {
+                    return MediumEvent::Absorbed;
+                }
+            }
+        }
+    }
+
+    MediumEvent::Transmitted {
+        weight: transmittance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Affine3A;
+
+    use super::{
+        DensityGrid, Grid, HenyeyGreenstein, Homogeneous, MajorantGrid, Medium, MediumEvent,
+        Participating,
+    };
+    use crate::ray::Ray;
+    use crate::sample;
+    use crate::vec3::{self, Color, Point3, Vec3};
+
+    /// Average throughput of rays passing through `distance` units of the medium.
+    fn mean_transmittance(medium: &impl Participating, distance: f32) -> Color {
+        let samples = 20000;
+        let ray = Ray::new(Point3::new(0.5, 0.5, 0.0), Vec3::Z);
+        let mut sum = Color::ZERO;
+        for _ in 0..samples {
+            if let MediumEvent::Transmitted { weight } =
+                medium.sample_interaction(&ray, distance, Color::ONE)
+            {
+                sum += weight;
+            }
+        }
+        sum / samples as f32
+    }
+
+    #[test]
+    fn test_absorbing_medium_over_unbounded_segment() {
+        let medium = Homogeneous {
+            sigma_a: Color::new(0.5, 0.0, 0.0),
+            sigma_s: Color::ZERO,
+            phase: HenyeyGreenstein::new(0.0),
+        };
+        let ray = Ray::new(Point3::ZERO, Vec3::Z);
+
+        // only the absorbing channel is lost on the way to infinity
+        match medium.sample_interaction(&ray, f32::INFINITY, Color::ONE) {
+            MediumEvent::Transmitted { weight } => assert_eq!(weight, Color::new(0.0, 1.0, 1.0)),
+            event => panic!("expected the ray to get through, got {event:?}"),
+        }
+        match medium.sample_interaction(&ray, 2.0, Color::ONE) {
+            MediumEvent::Transmitted { weight } => {
+                assert!((weight - Color::new((-1.0f32).exp(), 1.0, 1.0)).length() < 1e-6)
+            }
+            event => panic!("expected the ray to get through, got {event:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tracking_matches_beer_lambert() {
+        let sigma_a = Color::new(0.2, 0.5, 1.0);
+        let sigma_s = Color::new(0.5, 0.3, 0.1);
+        let expected = (-(sigma_a + sigma_s)).exp();
+
+        // delta tracking through a chromatic scattering medium
+        let homogeneous = Homogeneous {
+            sigma_a,
+            sigma_s,
+            phase: HenyeyGreenstein::new(0.0),
+        };
+        let estimate = mean_transmittance(&homogeneous, 1.0);
+        assert!(
+            (estimate - expected).abs().max_element() < 0.02,
+            "delta tracking gave {estimate} instead of {expected}"
+        );
+
+        // ratio tracking through an absorbing grid of constant density
+        let grid = Grid::new(
+            sigma_a + sigma_s,
+            Color::ZERO,
+            0.0,
+            DensityGrid::new(2, 2, 2, vec![1.0; 8]),
+            Affine3A::IDENTITY,
+        );
+        let estimate = mean_transmittance(&grid, 2.0);
+        assert!(
+            (estimate - expected).abs().max_element() < 0.02,
+            "ratio tracking gave {estimate} instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn test_majorant_segments_bound_density() {
+        let n = 12;
+        let values = (0..n * n * n)
+            .map(|i| ((i * 7919) % 13) as f32 / 13.0)
+            .collect();
+        let density = DensityGrid::new(n, n, n, values);
+        let majorants = MajorantGrid::new(&density, [4, 4, 4]);
+
+        let ray = Ray::new(Point3::new(0.05, 0.9, 0.3), Vec3::new(0.8, -0.7, 0.4));
+        let (t_min, t_max) = Grid::clip(&ray, f32::INFINITY).unwrap();
+        let mut t = t_min;
+        for segment in majorants.segments(&ray, t_min, t_max, 1.0) {
+            assert!(
+                (segment.t_min - t).abs() < 1e-5,
+                "segments must be contiguous"
+            );
+            for i in 0..16 {
+                let s = segment.t_min + (segment.t_max - segment.t_min) * (i as f32 + 0.5) / 16.0;
+                assert!(density.lookup(ray.evaluate(s)) <= segment.sigma_maj + 1e-6);
+            }
+            t = segment.t_max;
+        }
+        assert!(
+            (t - t_max).abs() < 1e-5,
+            "segments must reach the end of the ray"
+        );
+    }
+
+    #[test]
+    fn test_subsurface_albedo() {
+        // diffuse light entering a half-space below z = 0, walking until it leaves again
+        let albedo = Color::new(0.2, 0.5, 0.8);
+        let medium = Medium::subsurface(albedo, Color::ONE, 0.0);
+        let samples = 20000;
+        let mut escaped = Color::ZERO;
+        for _ in 0..samples {
+            let direction = -sample::cosine_hemisphere(vec3::random::gen_2d());
+            let mut ray = Ray::new(Point3::ZERO, direction);
+            let mut weight = Color::ONE;
+            for _ in 0..1000 {
+                let t_max = if ray.direction.z > 0.0 {
+                    -ray.origin.z / ray.direction.z
+                } else {
+                    f32::INFINITY
+                };
+                match medium.sample_interaction(&ray, t_max, weight) {
+                    MediumEvent::Scattered {
+                        point,
+                        weight: w,
+                        phase,
+                    } => {
+                        weight *= w;
+                        let direction = phase.sample(ray.direction, vec3::random::gen_2d());
+                        ray = Ray::new(point, direction);
+                    }
+                    MediumEvent::Absorbed => break,
+                    MediumEvent::Transmitted { weight: w } => {
+                        escaped += weight * w;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let estimate = escaped / samples as f32;
+        assert!(
+            (estimate - albedo).abs().max_element() < 0.05,
+            "random walk gave an albedo of {estimate} instead of {albedo}"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Trowbridge-Reitz (GGX) microfacet distribution. All directions are in the local shading
+//! frame, where the surface normal is +z.
+
+use std::f32::consts::PI;
+use std::sync::OnceLock;
+
+use glam::Vec2;
+
+use crate::math::{self, abs_cos_theta, cos2_theta, cos_phi, sin2_theta, sin_phi, tan2_theta};
+use crate::sample::{self, sample_uniform_disk_polar};
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: f32, alpha_y: f32) -> Self {
+        TrowbridgeReitz { alpha_x, alpha_y }
+    }
+
+    /// Maps a perceptual roughness in `[0, 1]` to the distribution's alpha, following glTF.
+    pub fn roughness_to_alpha(roughness: f32) -> f32 {
+        roughness * roughness
+    }
+
+    /// Below this roughness the surface is treated as a perfect mirror.
+    pub fn effectively_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    /// Differential area of microfacets with normal `wm`.
+    pub fn d(&self, wm: Vec3) -> f32 {
+        let tan2 = tan2_theta(wm);
+        if tan2.is_infinite() {
+            return 0.0;
+        }
+
+        let cos4_theta = cos2_theta(wm) * cos2_theta(wm);
+        if cos4_theta < 1e-16 {
+            return 0.0;
+        }
+
+        let e =
+            tan2 * ((cos_phi(wm) / self.alpha_x).powi(2) + (sin_phi(wm) / self.alpha_y).powi(2));
+        1.0 / (PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e).powi(2))
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        let tan2 = tan2_theta(w);
+        if tan2.is_infinite() {
+            return 0.0;
+        }
+
+        let alpha2 = (cos_phi(w) * self.alpha_x).powi(2) + (sin_phi(w) * self.alpha_y).powi(2);
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of microfacet normals `wm` as seen from `w`.
+    pub fn pdf(&self, w: Vec3, wm: Vec3) -> f32 {
+        let cos_theta = abs_cos_theta(w);
+        if cos_theta == 0.0 {
+            return 0.0;
+        }
+
+        self.g1(w) / cos_theta * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w`, following Heitz (2018).
+    pub fn sample_wm(&self, w: Vec3, u: Vec2) -> Vec3 {
+        // stretch the view direction into the hemisphere configuration
+        let mut wh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+
+        let t1 = if wh.z < 0.99999 {
+            Vec3::Z.cross(wh).normalize()
+        } else {
+            Vec3::X
+        };
+        let t2 = wh.cross(t1);
+
+        // warp a disk sample onto the projection of the visible hemisphere
+        let mut p = sample_uniform_disk_polar(u);
+        let h = (1.0 - p.x * p.x).sqrt();
+        p.y = math::lerp((1.0 + wh.z) / 2.0, h, p.y);
+        let pz = math::safe_sqrt(1.0 - p.length_squared());
+        let nh = p.x * t1 + p.y * t2 + pz * wh;
+
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+}
+
+/// Resolution of the sheen albedo table along the angle and roughness axes.
+const CHARLIE_ALBEDO_RES: usize = 16;
+
+/// The "Charlie" sheen distribution of Estevez and Kulla (2017), with the visibility term of
+/// Neubelt and Pettineo (2013), for the soft highlights of cloth and dust.
+#[derive(Debug, Clone, Copy)]
+pub struct Charlie {
+    alpha: f32,
+}
+
+impl Charlie {
+    pub fn new(alpha: f32) -> Self {
+        Charlie {
+            alpha: alpha.clamp(1e-3, 1.0),
+        }
+    }
+
+    pub fn d(&self, wm: Vec3) -> f32 {
+        let inv_alpha = 1.0 / self.alpha;
+        (2.0 + inv_alpha) * sin2_theta(wm).sqrt().powf(inv_alpha) / (2.0 * PI)
+    }
+
+    /// The sheen BRDF for a white sheen colour.
+    pub fn f(&self, wo: Vec3, wi: Vec3) -> f32 {
+        let wm = (wo + wi).normalize();
+        let (cos_theta_o, cos_theta_i) = (abs_cos_theta(wo), abs_cos_theta(wi));
+        let visibility = 1.0 / (4.0 * (cos_theta_i + cos_theta_o - cos_theta_i * cos_theta_o));
+        self.d(wm) * visibility
+    }
+
+    /// Fraction of the light arriving from `cos_theta` that the lobe reflects, interpolated
+    /// from a table integrated on first use.
+    pub fn albedo(&self, cos_theta: f32) -> f32 {
+        static TABLE: OnceLock<Vec<f32>> = OnceLock::new();
+        let table = TABLE.get_or_init(Charlie::albedo_table);
+
+        let last = (CHARLIE_ALBEDO_RES - 1) as f32;
+        let x = cos_theta.clamp(0.0, 1.0) * last;
+        let y = self.alpha * last;
+        let (xi, yi) = (
+            (x as usize).min(CHARLIE_ALBEDO_RES - 2),
+            (y as usize).min(CHARLIE_ALBEDO_RES - 2),
+        );
+        let (dx, dy) = (x - xi as f32, y - yi as f32);
+        let at = |x: usize, y: usize| table[y * CHARLIE_ALBEDO_RES + x];
+
+        math::lerp(
+            dy,
+            math::lerp(dx, at(xi, yi), at(xi + 1, yi)),
+            math::lerp(dx, at(xi, yi + 1), at(xi + 1, yi + 1)),
+        )
+    }
+
+    fn albedo_table() -> Vec<f32> {
+        let strata = 32;
+        let last = (CHARLIE_ALBEDO_RES - 1) as f32;
+        let mut table = Vec::with_capacity(CHARLIE_ALBEDO_RES * CHARLIE_ALBEDO_RES);
+        for yi in 0..CHARLIE_ALBEDO_RES {
+            let charlie = Charlie::new(yi as f32 / last);
+            for xi in 0..CHARLIE_ALBEDO_RES {
+                let cos_theta = (xi as f32 / last).max(0.01);
+                let wo = Vec3::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta);
+
+                // cosine weighted quadrature over the hemisphere, so each sample contributes f·π
+                let mut sum = 0.0;
+                for i in 0..strata {
+                    for j in 0..strata {
+                        let u = Vec2::new(
+                            (i as f32 + 0.5) / strata as f32,
+                            (j as f32 + 0.5) / strata as f32,
+                        );
+                        let wi = sample::cosine_hemisphere(u);
+                        sum += charlie.f(wo, wi) * PI;
+                    }
+                }
+                table.push((sum / (strata * strata) as f32).min(1.0));
+            }
+        }
+
+        table
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::{Charlie, TrowbridgeReitz};
+    use crate::vec3::Vec3;
+
+    #[test]
+    fn test_distributions() {
+        // the projected area of the microfacets equals the macro surface: ∫ D(m) cos θm dm = 1
+        let distribution = TrowbridgeReitz::new(0.3, 0.3);
+        let steps = 2000;
+        let mut projected = 0.0;
+        for i in 0..steps {
+            let theta = (i as f32 + 0.5) / steps as f32 * PI / 2.0;
+            let wm = Vec3::new(theta.sin(), 0.0, theta.cos());
+            projected += distribution.d(wm) * theta.cos() * theta.sin() * 2.0 * PI * (PI / 2.0)
+                / steps as f32;
+        }
+        assert!(
+            (projected - 1.0).abs() < 1e-2,
+            "projected area was {projected}"
+        );
+
+        for alpha in [0.05, 0.3, 1.0] {
+            for cos_theta in [0.1, 0.5, 1.0] {
+                let albedo = Charlie::new(alpha).albedo(cos_theta);
+                assert!(albedo > 0.0 && albedo <= 1.0, "sheen albedo was {albedo}");
+            }
+        }
+    }
+}
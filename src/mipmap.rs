@@ -0,0 +1,258 @@
+use glam::Vec4;
+use image::DynamicImage;
+
+use crate::texture::{
+    ColorSpace, FilterMode, Sampler, TextureCoordinates, UvDerivatives, WrapMode,
+};
+
+#[derive(Debug)]
+struct MipLevel {
+    width: u32,
+    height: u32,
+    /// Linear RGB and alpha.
+    texels: Vec<Vec4>,
+}
+
+impl MipLevel {
+    fn texel(&self, x: i64, y: i64, wrap_s: WrapMode, wrap_t: WrapMode) -> Vec4 {
+        let x = wrap_s.apply(x, self.width);
+        let y = wrap_t.apply(y, self.height);
+        self.texels[(y * self.width + x) as usize]
+    }
+
+    /// Box-filters the level down to half its size, rounding odd dimensions up.
+    fn downsample(&self) -> MipLevel {
+        let width = self.width.div_ceil(2).max(1);
+        let height = self.height.div_ceil(2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height as i64 {
+            for x in 0..width as i64 {
+                let clamp = WrapMode::ClampToEdge;
+                let sum = self.texel(2 * x, 2 * y, clamp, clamp)
+                    + self.texel(2 * x + 1, 2 * y, clamp, clamp)
+                    + self.texel(2 * x, 2 * y + 1, clamp, clamp)
+                    + self.texel(2 * x + 1, 2 * y + 1, clamp, clamp);
+                texels.push(sum * 0.25);
+            }
+        }
+
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    /// Looks up the level at texture coordinates `(s, t)`, where `[0, 1]` spans the image once.
+    fn sample(&self, s: f32, t: f32, sampler: &Sampler) -> Vec4 {
+        let (ws, wt) = (sampler.wrap_s, sampler.wrap_t);
+        let x = s * self.width as f32;
+        let y = t * self.height as f32;
+        if sampler.filter == FilterMode::Nearest {
+            return self.texel(x.floor() as i64, y.floor() as i64, ws, wt);
+        }
+
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        self.texel(x0, y0, ws, wt) * (1.0 - dx) * (1.0 - dy)
+            + self.texel(x0 + 1, y0, ws, wt) * dx * (1.0 - dy)
+            + self.texel(x0, y0 + 1, ws, wt) * (1.0 - dx) * dy
+            + self.texel(x0 + 1, y0 + 1, ws, wt) * dx * dy
+    }
+}
+
+/// Image pyramid built once when a texture is loaded, filtered trilinearly using the texture
+/// coordinate derivatives carried by [`TextureCoordinates`].
+#[derive(Debug)]
+pub struct MipMap {
+    levels: Vec<MipLevel>,
+}
+
+impl MipMap {
+    /// Builds the pyramid from `image`, decoding its texels from `color_space` to linear so that
+    /// the levels are averaged in linear space.
+    pub fn new(image: &DynamicImage, color_space: ColorSpace) -> Self {
+        let image = image.to_rgba32f();
+        let base = MipLevel {
+            width: image.width(),
+            height: image.height(),
+            texels: image
+                .pixels()
+                .map(|p| {
+                    let [r, g, b, a] = p.0;
+                    Vec4::new(
+                        color_space.to_linear(r),
+                        color_space.to_linear(g),
+                        color_space.to_linear(b),
+                        a,
+                    )
+                })
+                .collect(),
+        };
+
+        let mut levels = vec![base];
+        while let Some(last) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            levels.push(last.downsample());
+        }
+
+        MipMap { levels }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The continuous mip level matching a footprint with the derivatives `d`, where zero is
+    /// the full resolution.
+    fn level(&self, d: UvDerivatives) -> f32 {
+        let width = 2.0
+            * d.dudx
+                .abs()
+                .max(d.dudy.abs())
+                .max(d.dvdx.abs())
+                .max(d.dvdy.abs());
+        (self.levels.len() - 1) as f32 + width.max(1e-8).log2()
+    }
+
+    /// Looks up the texture at `coords`, blending the two mip levels closest to the footprint
+    /// described by their derivatives.
+    pub fn lookup(&self, coords: TextureCoordinates, sampler: &Sampler) -> Vec4 {
+        let (s, t) = (coords.u, coords.v);
+        if !sampler.mipmaps {
+            return self.levels[0].sample(s, t, sampler);
+        }
+
+        let top = (self.levels.len() - 1) as f32;
+        let level = self.level(coords.derivatives);
+        if level <= 0.0 {
+            return self.levels[0].sample(s, t, sampler);
+        }
+        if level >= top {
+            return self.levels[self.levels.len() - 1].sample(s, t, sampler);
+        }
+
+        let lower = level.floor() as usize;
+        let delta = level - lower as f32;
+        self.levels[lower].sample(s, t, sampler) * (1.0 - delta)
+            + self.levels[lower + 1].sample(s, t, sampler) * delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    use super::MipMap;
+    use crate::material::Material;
+    use crate::object::triangle_mesh::{Surface, TriangleMesh};
+    use crate::object::Hittable;
+    use crate::range::Range;
+    use crate::ray::{Ray, RayDifferentials};
+    use crate::texture::{ColorSpace, Sampler, TextureCoordinates, UvDerivatives};
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_mip_pyramid_averages() {
+        // 4x2 checkerboard of black and white texels
+        let image = RgbImage::from_fn(4, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        });
+        let mipmap = MipMap::new(&DynamicImage::from(image), ColorSpace::Linear);
+        assert_eq!(mipmap.level_count(), 3);
+
+        let sampler = Sampler::default();
+        let sharp = mipmap.lookup(TextureCoordinates::new(0.125, 0.25), &sampler);
+        assert!((sharp.x - 1.0).abs() < 1e-5);
+
+        let blurry = TextureCoordinates {
+            derivatives: UvDerivatives {
+                dudx: 1.0,
+                dvdy: 1.0,
+                ..Default::default()
+            },
+            ..TextureCoordinates::new(0.125, 0.25)
+        };
+        let averaged = mipmap.lookup(blurry, &sampler);
+        assert!((averaged.x - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_footprint_grows_with_distance_and_obliqueness() {
+        let quad = TriangleMesh::new(
+            0,
+            vec![
+                Point3::new(-1.0, -1.0, 0.0),
+                Point3::new(1.0, -1.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(-1.0, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2), (0, 2, 3)],
+            vec![],
+            vec![(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)]
+                .into_iter()
+                .map(|(u, v)| TextureCoordinates::new(u, v))
+                .collect(),
+            vec![],
+            Surface::opaque(Material::lambertian(Vec3::ONE)),
+        );
+        let mipmap = MipMap::new(
+            &DynamicImage::from(RgbImage::new(256, 256)),
+            ColorSpace::Linear,
+        );
+
+        // a pinhole camera at `origin` looking at the centre of the quad, one milliradian per
+        // pixel
+        let footprint = |origin: Point3| {
+            let direction = (Point3::ZERO - origin).normalize();
+            let right = direction.cross(Vec3::Y).normalize();
+            let up = right.cross(direction);
+            let ray = Ray::new(origin, direction).with_differentials(RayDifferentials {
+                rx_origin: origin,
+                rx_direction: direction + right * 1e-3,
+                ry_origin: origin,
+                ry_direction: direction + up * 1e-3,
+            });
+            let mut hit = quad
+                .faces()
+                .find_map(|face| face.hit(&ray, Range::new(0.0, f32::INFINITY)))
+                .unwrap();
+            hit.compute_differentials(&ray);
+            let d = hit.tex_coords.derivatives;
+            let size = d.dudx.abs() + d.dvdx.abs() + d.dudy.abs() + d.dvdy.abs();
+            (size, mipmap.level(d))
+        };
+
+        let (near, near_level) = footprint(Point3::new(0.0, 0.0, 2.0));
+        let (far, far_level) = footprint(Point3::new(0.0, 0.0, 8.0));
+        let angle = 75.0f32.to_radians();
+        let (oblique, oblique_level) =
+            footprint(Point3::new(2.0 * angle.sin(), 0.0, 2.0 * angle.cos()));
+
+        assert!(near > 0.0);
+        assert!(
+            (far / near - 4.0).abs() < 0.01,
+            "{far} is not four times {near}"
+        );
+        assert!(
+            oblique > 2.0 * near,
+            "{oblique} is not stretched from {near}"
+        );
+        assert!((far_level - near_level - 2.0).abs() < 0.01);
+        assert!(oblique_level > near_level + 1.0);
+    }
+}
@@ -0,0 +1,152 @@
+//! Gradient noise and cellular noise for procedural textures.
+
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+use crate::vec3::{Point3, Vec3};
+
+/// Ken Perlin's improved gradient noise, with a permutation table derived from a seed.
+#[derive(Debug, Clone)]
+pub struct Perlin {
+    permutation: Box<[u8]>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut SmallRng::seed_from_u64(seed));
+
+        let permutation = table.iter().chain(table.iter()).copied().collect();
+        Perlin { permutation }
+    }
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> u8 {
+        let p = &self.permutation;
+        let a = p[(x & 255) as usize] as usize + (y & 255) as usize;
+        let b = p[a] as usize + (z & 255) as usize;
+        p[b]
+    }
+
+    /// Gradient noise in roughly `[-1, 1]`, zero at every integer lattice point.
+    pub fn noise(&self, p: Point3) -> f32 {
+        let cell = p.floor();
+        let f = p - cell;
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+
+        let corner = |dx: i32, dy: i32, dz: i32| {
+            let h = self.hash(x + dx, y + dy, z + dz);
+            grad(h, f - Vec3::new(dx as f32, dy as f32, dz as f32))
+        };
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+            ),
+        )
+    }
+
+    /// Fractional Brownian motion: `octaves` layers of noise, each at double the frequency and
+    /// half the amplitude of the previous one. Roughly in `[-1, 1]`.
+    pub fn fbm(&self, p: Point3, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut weight = 1.0;
+        let mut total_weight = 0.0;
+        let mut p = p;
+        for _ in 0..octaves.max(1) {
+            sum += weight * self.noise(p);
+            total_weight += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+
+        sum / total_weight
+    }
+
+    /// Like [`Perlin::fbm`], but summing the absolute value of each octave. In `[0, 1]`.
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f32 {
+        let mut sum = 0.0;
+        let mut weight = 1.0;
+        let mut total_weight = 0.0;
+        let mut p = p;
+        for _ in 0..octaves.max(1) {
+            sum += weight * self.noise(p).abs();
+            total_weight += weight;
+            weight *= 0.5;
+            p *= 2.0;
+        }
+
+        (sum / total_weight).min(1.0)
+    }
+
+    /// Cellular (Worley) noise: the distance from `p` to the closest feature point, with one
+    /// feature point jittered inside every unit cell.
+    pub fn voronoi(&self, p: Point3) -> f32 {
+        let cell = p.floor();
+        let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+        let mut closest = f32::INFINITY;
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let (cx, cy, cz) = (x + dx, y + dy, z + dz);
+                    let jitter = Vec3::new(
+                        self.hash(cx, cy, cz) as f32,
+                        self.hash(cx + 17, cy + 59, cz + 113) as f32,
+                        self.hash(cx + 71, cy + 31, cz + 7) as f32,
+                    ) / 255.0;
+                    let feature = Vec3::new(cx as f32, cy as f32, cz as f32) + jitter;
+                    closest = closest.min(feature.distance(p));
+                }
+            }
+        }
+
+        closest
+    }
+}
+
+/// Dot product of `p` with one of the twelve gradient directions selected by `hash`.
+fn grad(hash: u8, p: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { p.x } else { p.y };
+    let v = if h < 4 {
+        p.y
+    } else if h == 12 || h == 14 {
+        p.x
+    } else {
+        p.z
+    };
+
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Perlin;
+    use crate::vec3::Point3;
+
+    #[test]
+    fn test_noise_ranges() {
+        let perlin = Perlin::new(7);
+        assert_eq!(perlin.noise(Point3::new(3.0, -2.0, 5.0)), 0.0);
+
+        for i in 0..1000 {
+            let t = i as f32 * 0.173;
+            let p = Point3::new(t, t * 0.37 - 4.0, -t * 1.3);
+            assert!(perlin.noise(p).abs() <= 1.1);
+            assert!((0.0..=1.0).contains(&perlin.turbulence(p, 4)));
+            assert!(perlin.voronoi(p) <= 3.0f32.sqrt());
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 
 use enum_dispatch::enum_dispatch;
+pub use moving_mesh::MovingMesh;
 pub use sphere::Sphere;
 use triangle_mesh::TriangleRef;
 pub use world::World;
@@ -10,11 +11,13 @@ pub use world::World;
 use crate::aabb::Aabb;
 use crate::bvh::{BvhNode, FlatBvhTree};
 use crate::material::Material;
+use crate::medium::MediumInterface;
 use crate::range::Range;
 use crate::ray::Ray;
-use crate::texture::TextureCoordinates;
+use crate::texture::{TextureCoordinates, UvDerivatives};
 use crate::vec3::{Point3, Vec3};
 
+pub mod moving_mesh;
 mod sphere;
 pub mod triangle_mesh;
 mod world;
@@ -28,11 +31,19 @@ pub fn get_id() -> u32 {
 #[derive(Debug)]
 pub struct HitRecord {
     pub point: Point3,
+    /// Absolute floating point error bounds of `point`.
+    pub error: Vec3,
     pub normal: Vec3,
+    pub geometric_normal: Vec3,
+    /// Partial derivatives of the surface position with respect to the texture coordinates.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub distance: f32,
     pub front_facing: bool,
     pub material: Arc<Material>,
     pub tex_coords: TextureCoordinates,
+    /// The media on both sides of the surface, if it bounds any.
+    pub medium_interface: Option<MediumInterface>,
 }
 
 impl HitRecord {
@@ -53,13 +64,94 @@ impl HitRecord {
 
         HitRecord {
             point,
+            error: Vec3::ZERO,
             normal,
+            geometric_normal: normal,
+            dpdu: Vec3::ZERO,
+            dpdv: Vec3::ZERO,
             front_facing,
             distance,
             material,
             tex_coords,
+            medium_interface: None,
         }
     }
+
+    pub fn with_error_bounds(self, error: Vec3, geometric_normal: Vec3) -> Self {
+        HitRecord {
+            error,
+            geometric_normal,
+            ..self
+        }
+    }
+
+    pub fn with_medium_interface(self, medium_interface: Option<MediumInterface>) -> Self {
+        HitRecord {
+            medium_interface,
+            ..self
+        }
+    }
+
+    pub fn with_surface_derivatives(self, dpdu: Vec3, dpdv: Vec3) -> Self {
+        HitRecord { dpdu, dpdv, ..self }
+    }
+
+    /// Estimates the texture space footprint of `ray` at this hit from its differentials, by
+    /// intersecting the offset rays with the tangent plane (see pbrt, section 10.1.1).
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        let Some(diff) = ray.differentials else {
+            return;
+        };
+        let n = self.geometric_normal;
+        let dx = n.dot(diff.rx_direction);
+        let dy = n.dot(diff.ry_direction);
+        if dx == 0.0 || dy == 0.0 {
+            return;
+        }
+
+        let d = n.dot(self.point);
+        let tx = (d - n.dot(diff.rx_origin)) / dx;
+        let ty = (d - n.dot(diff.ry_origin)) / dy;
+        let dpdx = diff.rx_origin + diff.rx_direction * tx - self.point;
+        let dpdy = diff.ry_origin + diff.ry_direction * ty - self.point;
+
+        // Solve the overdetermined system dp = dpdu * du + dpdv * dv using the two dimensions
+        // least aligned with the normal.
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let solve = |dp: Vec3| {
+            let det = self.dpdu[a] * self.dpdv[b] - self.dpdv[a] * self.dpdu[b];
+            if det.abs() < 1e-10 {
+                return (0.0, 0.0);
+            }
+            let du = (self.dpdv[b] * dp[a] - self.dpdv[a] * dp[b]) / det;
+            let dv = (self.dpdu[a] * dp[b] - self.dpdu[b] * dp[a]) / det;
+            if du.is_finite() && dv.is_finite() {
+                (du, dv)
+            } else {
+                (0.0, 0.0)
+            }
+        };
+
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+        self.tex_coords.derivatives = UvDerivatives {
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        };
+    }
+
+    /// Spawns a ray leaving the surface in `direction`, offset to avoid self-intersection.
+    pub fn spawn_ray(&self, direction: Vec3) -> Ray {
+        Ray::spawn(self.point, self.error, self.geometric_normal, direction)
+    }
 }
 
 #[enum_dispatch]
@@ -81,6 +173,7 @@ pub enum Object {
     FlatBvhTree(FlatBvhTree),
     World(World),
     TriangleRef(TriangleRef),
+    MovingMesh(MovingMesh),
 }
 
 impl Object {
@@ -91,6 +184,7 @@ impl Object {
             Object::FlatBvhTree(tree) => tree.len(),
             Object::World(world) => world.objects.len(),
             Object::TriangleRef(_) => 1,
+            Object::MovingMesh(mesh) => mesh.face_count(),
         }
     }
 }
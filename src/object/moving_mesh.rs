@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use super::triangle_mesh::TriangleMesh;
+use super::{HitRecord, Hittable, Object};
+use crate::aabb::Aabb;
+use crate::animation::AnimatedTransform;
+use crate::bvh::{BvhNode, FlatBvhTree};
+use crate::math;
+use crate::range::Range;
+use crate::ray::Ray;
+use crate::vec3::{Point3, Vec3};
+
+/// A mesh moved by a keyframed transform. Its faces stay in object space under a BVH of their
+/// own, so a ray is moved into object space once per mesh instead of the vertices being moved
+/// for every triangle tested.
+#[derive(Debug, Clone)]
+pub struct MovingMesh {
+    mesh_index: u32,
+    face_count: usize,
+    transform: Arc<AnimatedTransform>,
+    faces: Arc<FlatBvhTree>,
+    /// The box enclosing the mesh while the shutter is open.
+    bbox: Aabb,
+}
+
+impl MovingMesh {
+    /// Returns `None` for meshes without faces.
+    pub fn new(
+        mesh: &TriangleMesh,
+        transform: Arc<AnimatedTransform>,
+        shutter: Range,
+    ) -> Option<Self> {
+        if mesh.face_count() == 0 {
+            return None;
+        }
+
+        let faces = BvhNode::from(mesh.faces().map(Object::TriangleRef).collect());
+        let mut moving = MovingMesh {
+            mesh_index: mesh.index(),
+            face_count: mesh.face_count(),
+            transform,
+            faces: Arc::new(FlatBvhTree::from_tree(faces)),
+            bbox: Aabb::EMPTY,
+        };
+        moving.set_shutter(shutter);
+        Some(moving)
+    }
+
+    pub fn mesh_index(&self) -> u32 {
+        self.mesh_index
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.face_count
+    }
+
+    /// Bounds the mesh by the poses it takes while the shutter is open.
+    pub fn set_shutter(&mut self, shutter: Range) {
+        let local = self.faces.bounding_box();
+        let corners: Vec<_> = (0..8)
+            .map(|i| {
+                Point3::new(
+                    if i & 1 == 0 { local.x.min } else { local.x.max },
+                    if i & 2 == 0 { local.y.min } else { local.y.max },
+                    if i & 4 == 0 { local.z.min } else { local.z.max },
+                )
+            })
+            .collect();
+        self.bbox = self.transform.motion_bounds(shutter).enclose(&corners);
+    }
+}
+
+impl Hittable for MovingMesh {
+    fn hit(&self, ray: &Ray, hit_range: Range) -> Option<HitRecord> {
+        let transform = self.transform.at(ray.time);
+        let inverse = transform.inverse();
+        // distances along the ray are the same in both spaces, as the direction isn't normalized
+        let local_ray = Ray::new(
+            inverse.transform_point3a(ray.origin),
+            inverse.transform_vector3a(ray.direction),
+        )
+        .with_wavelength(ray.wavelength)
+        .with_time(ray.time);
+        let mut hit = self.faces.hit(&local_ray, hit_range)?;
+
+        // Transforming the point adds rounding error of its own (see pbrt, section 6.8.6)
+        let abs_matrix = transform.matrix3.abs();
+        let local_point = hit.point;
+        hit.point = transform.transform_point3a(local_point);
+        hit.error = abs_matrix * hit.error * (math::gamma(3) + 1.0)
+            + (abs_matrix * local_point.abs() + Vec3::from(transform.translation).abs())
+                * math::gamma(3);
+        let normal_matrix = transform.matrix3.inverse().transpose();
+        hit.normal = (normal_matrix * hit.normal).normalize();
+        hit.geometric_normal = (normal_matrix * hit.geometric_normal).normalize();
+        hit.dpdu = transform.transform_vector3a(hit.dpdu);
+        hit.dpdv = transform.transform_vector3a(hit.dpdv);
+
+        Some(hit)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn id(&self) -> u32 {
+        self.mesh_index
+    }
+
+    fn name(&self) -> &'static str {
+        "MovingMesh"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::Quat;
+
+    use super::MovingMesh;
+    use crate::animation::{AnimatedTransform, Interpolation, Track};
+    use crate::material::Material;
+    use crate::object::triangle_mesh::{single_triangle, Surface};
+    use crate::object::Hittable;
+    use crate::range::Range;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_moving_mesh_is_hit_at_ray_time() {
+        let mut transform = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        transform.translation_track = Track::new(
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![Vec3::ZERO, Vec3::new(4.0, 0.0, 0.0)],
+        );
+        let mesh = single_triangle(Surface::opaque(Material::lambertian(Vec3::ONE)));
+        let mut moving = MovingMesh::new(&mesh, Arc::new(transform), Range::new(0.0, 0.0)).unwrap();
+        let range = Range::new(0.0, f32::INFINITY);
+
+        let ray = |x, time| Ray::new(Point3::new(x, 0.0, 1.0), Vec3::NEG_Z).with_time(time);
+        assert!(moving.hit(&ray(0.0, 0.0), range).is_some());
+        assert!(moving.hit(&ray(0.0, 1.0), range).is_none());
+        let hit = moving.hit(&ray(4.0, 1.0), range).unwrap();
+        assert!((hit.point - Point3::new(4.0, 0.0, 0.0)).length() < 1e-5);
+        assert!((hit.distance - 1.0).abs() < 1e-5);
+
+        // the box only covers the poses taken while the shutter is open
+        let bbox = moving.bounding_box();
+        assert!(bbox.x.min <= -1.0 && bbox.x.max >= 1.0 && bbox.x.max < 2.0);
+        moving.set_shutter(Range::new(0.5, 1.0));
+        let bbox = moving.bounding_box();
+        assert!(bbox.x.min <= 1.0 && bbox.x.min > 0.0 && bbox.x.max >= 5.0);
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use super::{get_id, HitRecord, Hittable};
 use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::math;
 use crate::range::Range;
 use crate::ray::Ray;
 use crate::texture::TextureCoordinates;
@@ -35,9 +36,9 @@ impl Sphere {
 
 /// p: a given point on the sphere of radius one, centered at the origin.
 /// u: returned value [0,1] of angle around the Y axis from X=-1.
-/// v: returned value [0,1] of angle from Y=-1 to Y=+1.
+/// v: returned value [0,1] of angle from Y=+1 to Y=-1, so that images are the right way up.
 ///     <1 0 0> yields <0.50 0.50>       <-1  0  0> yields <0.00 0.50>
-///     <0 1 0> yields <0.50 1.00>       < 0 -1  0> yields <0.50 0.00>
+///     <0 1 0> yields <0.50 0.00>       < 0 -1  0> yields <0.50 1.00>
 ///     <0 0 1> yields <0.25 0.50>       < 0  0 -1> yields <0.75 0.50>
 fn get_uv(p: Point3) -> TextureCoordinates {
     use std::f32::consts::PI;
@@ -45,10 +46,28 @@ fn get_uv(p: Point3) -> TextureCoordinates {
     let theta = (-p.y).acos();
     let phi = f32::atan2(-p.z, p.x) + PI;
 
-    TextureCoordinates {
-        u: phi / (2.0 * PI),
-        v: theta / PI,
-    }
+    TextureCoordinates::new(phi / (2.0 * PI), 1.0 - theta / PI)
+}
+
+/// Partial derivatives of a point `p` on a sphere centered at the origin with respect to the
+/// (u, v) parameterization of [`get_uv`].
+fn surface_derivatives(p: Point3, radius: f32) -> (Vec3, Vec3) {
+    use std::f32::consts::PI;
+
+    let sin_theta = (p.x * p.x + p.z * p.z).sqrt() / radius;
+    let cos_theta = -p.y / radius;
+    let dpdu = Vec3::new(p.z, 0.0, -p.x) * (2.0 * PI);
+    let dpdv = if sin_theta > 1e-6 {
+        Vec3::new(
+            p.x * cos_theta / sin_theta,
+            radius * sin_theta,
+            p.z * cos_theta / sin_theta,
+        ) * -PI
+    } else {
+        Vec3::ZERO
+    };
+
+    (dpdu, dpdv)
 }
 
 impl Hittable for Sphere {
@@ -70,16 +89,25 @@ impl Hittable for Sphere {
                     return None;
                 }
             }
-            let point = ray.evaluate(root);
-            let outward_normal = (point - self.center) / self.radius;
-            Some(HitRecord::new(
-                ray,
-                outward_normal,
-                point,
-                root,
-                self.material.clone(),
-                get_uv(point),
-            ))
+            // Reproject the hit point onto the surface to tighten its error bounds
+            let local = ray.evaluate(root) - self.center;
+            let local = local * (self.radius / local.length());
+            let point = self.center + local;
+            let error = local.abs() * math::gamma(5) + self.center.abs() * math::gamma(1);
+            let outward_normal = local / self.radius;
+            let (dpdu, dpdv) = surface_derivatives(local, self.radius);
+            Some(
+                HitRecord::new(
+                    ray,
+                    outward_normal,
+                    point,
+                    root,
+                    self.material.clone(),
+                    get_uv(outward_normal),
+                )
+                .with_error_bounds(error, outward_normal)
+                .with_surface_derivatives(dpdu, dpdv),
+            )
         }
     }
 
@@ -95,3 +123,43 @@ impl Hittable for Sphere {
         "Sphere"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Sphere;
+    use crate::material::Material;
+    use crate::object::Hittable;
+    use crate::range::Range;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_uv_is_relative_to_center() {
+        let sphere = Sphere::new(
+            Point3::new(5.0, 2.0, -3.0),
+            2.0,
+            Material::lambertian(Vec3::ONE),
+        );
+        // hit the +X pole of the sphere
+        let ray = Ray::new(Point3::new(10.0, 2.0, -3.0), Vec3::NEG_X);
+        let hit = sphere.hit(&ray, Range::new(0.0, f32::INFINITY)).unwrap();
+
+        assert!((hit.tex_coords.u - 0.5).abs() < 1e-5);
+        assert!((hit.tex_coords.v - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_v_runs_from_top_to_bottom() {
+        let sphere = Sphere::new(Point3::ZERO, 1.0, Material::lambertian(Vec3::ONE));
+        let v_at = |origin: Point3| {
+            let ray = Ray::new(origin, -origin.normalize());
+            let hit = sphere.hit(&ray, Range::new(0.0, f32::INFINITY)).unwrap();
+            hit.tex_coords.v
+        };
+
+        // images are stored top row first, so the top of the sphere is at v = 0
+        assert!(v_at(Point3::new(0.0, 5.0, 0.0)) < 1e-5);
+        assert!(v_at(Point3::new(0.0, -5.0, 0.0)) > 1.0 - 1e-5);
+        assert!((v_at(Point3::new(5.0, 5.0, 0.0)) - 0.25).abs() < 1e-5);
+    }
+}
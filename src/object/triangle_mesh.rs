@@ -4,45 +4,120 @@ use std::sync::Arc;
 use super::{HitRecord, Hittable};
 use crate::aabb::Aabb;
 use crate::material::Material;
+use crate::math;
+use crate::medium::MediumInterface;
+use crate::onb::Onb;
+use crate::random::random;
 use crate::range::Range;
 use crate::ray::Ray;
-use crate::texture::TextureCoordinates;
-use crate::vec3::{Point3, Vec3};
+use crate::texture::{HasColorValue, Texture, TextureCoordinates};
+use crate::vec3::{Point3, Vec3, Vec3Ext};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AlphaMode {
+    #[default]
+    Opaque,
+    /// Hits with an alpha below `cutoff` are ignored.
+    Mask { cutoff: f32 },
+    /// Hits are kept with a probability equal to their alpha (stochastic transparency). Each
+    /// ray either stops at the surface or passes through it unchanged, so the blend only
+    /// emerges as the samples of a pixel are averaged, and costs some extra noise.
+    Blend,
+}
+
+/// The material of a mesh, together with the properties that decide whether a ray hits it at
+/// all.
+#[derive(Debug, Clone)]
+pub struct Surface {
+    pub material: Arc<Material>,
+    pub alpha_mode: AlphaMode,
+    /// Constant alpha factor, multiplied with the alpha channel of `alpha_texture`.
+    pub alpha: f32,
+    pub alpha_texture: Option<Arc<Texture>>,
+    /// Whether the back faces, those wound clockwise as seen by the ray, can be hit.
+    pub double_sided: bool,
+    /// Whether the mesh was placed by a mirroring transform, which turns its front faces
+    /// clockwise.
+    pub mirrored: bool,
+    /// The media inside and outside of the mesh, for meshes bounding a participating medium.
+    pub medium_interface: Option<MediumInterface>,
+}
+
+impl Surface {
+    pub fn opaque(material: Arc<Material>) -> Self {
+        Surface {
+            material,
+            alpha_mode: AlphaMode::Opaque,
+            alpha: 1.0,
+            alpha_texture: None,
+            double_sided: true,
+            mirrored: false,
+            medium_interface: None,
+        }
+    }
+
+    /// Decides whether a hit at `coords` is kept or passed through.
+    fn is_opaque_at(&self, coords: TextureCoordinates, point: Point3) -> bool {
+        let alpha = || {
+            let texture_alpha = self
+                .alpha_texture
+                .as_ref()
+                .map(|t| t.alpha_at(coords, point))
+                .unwrap_or(1.0);
+            self.alpha * texture_alpha
+        };
+
+        match self.alpha_mode {
+            AlphaMode::Opaque => true,
+            AlphaMode::Mask { cutoff } => alpha() >= cutoff,
+            AlphaMode::Blend => random() < alpha(),
+        }
+    }
+}
 
 struct TriangleMeshData {
+    index: u32,
     vertices: Box<[Point3]>,
     face_indices: Box<[(u32, u32, u32)]>,
     normals: Box<[Vec3]>,
     uv: Box<[TextureCoordinates]>,
-    material: Arc<Material>,
+    /// Optional second texture coordinate set.
+    uv1: Box<[TextureCoordinates]>,
+    surface: Surface,
 }
 
 impl fmt::Debug for TriangleMeshData {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("TriangleMeshData")
+            .field("index", &self.index)
             .field("vertices", &self.vertices.len())
             .field("face_indices", &self.face_indices.len())
             .field("normals", &self.normals.len())
             .field("uv", &self.uv.len())
-            .field("material", &self.material)
+            .field("uv1", &self.uv1.len())
+            .field("surface", &self.surface)
             .finish()
     }
 }
 
 impl TriangleMeshData {
     pub fn new(
+        index: u32,
         vertices: Vec<Point3>,
         face_indices: Vec<(u32, u32, u32)>,
         normals: Vec<Vec3>,
         uv: Vec<TextureCoordinates>,
-        material: Arc<Material>,
+        uv1: Vec<TextureCoordinates>,
+        surface: Surface,
     ) -> Self {
         TriangleMeshData {
+            index,
             vertices: vertices.into_boxed_slice(),
             face_indices: face_indices.into_boxed_slice(),
             normals: normals.into_boxed_slice(),
             uv: uv.into_boxed_slice(),
-            material,
+            uv1: uv1.into_boxed_slice(),
+            surface,
         }
     }
 
@@ -66,13 +141,15 @@ pub struct TriangleMesh {
 
 impl TriangleMesh {
     pub fn new(
+        index: u32,
         vertices: Vec<Point3>,
         face_indices: Vec<(u32, u32, u32)>,
         normals: Vec<Vec3>,
         uv: Vec<TextureCoordinates>,
-        material: Arc<Material>,
+        uv1: Vec<TextureCoordinates>,
+        surface: Surface,
     ) -> Self {
-        let data = TriangleMeshData::new(vertices, face_indices, normals, uv, material);
+        let data = TriangleMeshData::new(index, vertices, face_indices, normals, uv, uv1, surface);
         TriangleMesh {
             data: Arc::new(data),
         }
@@ -85,6 +162,14 @@ impl TriangleMesh {
         }
     }
 
+    pub fn index(&self) -> u32 {
+        self.data.index
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.data.face_indices.len()
+    }
+
     pub fn faces(&self) -> impl Iterator<Item = TriangleRef> + '_ {
         self.data
             .face_indices
@@ -102,6 +187,14 @@ pub struct TriangleRef {
 }
 
 impl TriangleRef {
+    pub fn mesh_index(&self) -> u32 {
+        self.mesh.index
+    }
+
+    pub fn face_index(&self) -> u32 {
+        self.index
+    }
+
     pub fn vertices(&self) -> (Point3, Point3, Point3) {
         let (v0, v1, v2) = self.mesh.face_indices[self.index as usize];
         (
@@ -123,15 +216,54 @@ impl TriangleRef {
         }
     }
 
+    /// Partial derivatives of the surface position along u and v. Triangles without texture
+    /// coordinates, or with degenerate ones, get an arbitrary tangent frame instead.
+    fn surface_derivatives(&self, v0: Point3, v1: Point3, v2: Point3, n: Vec3) -> (Vec3, Vec3) {
+        let (uv0, uv1, uv2) = if self.mesh.uv.is_empty() {
+            (
+                TextureCoordinates::new(0.0, 0.0),
+                TextureCoordinates::new(1.0, 0.0),
+                TextureCoordinates::new(1.0, 1.0),
+            )
+        } else {
+            let (i0, i1, i2) = self.mesh.face_indices[self.index as usize];
+            (self.mesh.uv(i0), self.mesh.uv(i1), self.mesh.uv(i2))
+        };
+
+        let (du02, dv02) = (uv0.u - uv2.u, uv0.v - uv2.v);
+        let (du12, dv12) = (uv1.u - uv2.u, uv1.v - uv2.v);
+        let dp02 = v0 - v2;
+        let dp12 = v1 - v2;
+        let det = du02 * dv12 - dv02 * du12;
+
+        if det.abs() < 1e-8 {
+            let onb = Onb::build_from_w(n);
+            (onb.u(), onb.v())
+        } else {
+            let inv_det = 1.0 / det;
+            (
+                (dp02 * dv12 - dp12 * dv02) * inv_det,
+                (dp12 * du02 - dp02 * du12) * inv_det,
+            )
+        }
+    }
+
     pub fn uv(&self, a: f32, b: f32) -> TextureCoordinates {
-        if self.mesh.uv.len() == 0 {
+        if self.mesh.uv.is_empty() {
             TextureCoordinates::default()
         } else {
             let (v0, v1, v2) = self.mesh.face_indices[self.index as usize];
             let uv0 = self.mesh.uv(v0);
             let uv1 = self.mesh.uv(v1);
             let uv2 = self.mesh.uv(v2);
-            TextureCoordinates::tri_lerp(uv0, uv1, uv2, a, b)
+            let mut coords = TextureCoordinates::tri_lerp(uv0, uv1, uv2, a, b);
+
+            if !self.mesh.uv1.is_empty() {
+                let [uv0, uv1, uv2] = [v0, v1, v2].map(|i| self.mesh.uv1[i as usize]);
+                let secondary = TextureCoordinates::tri_lerp(uv0, uv1, uv2, a, b);
+                coords.secondary = Some((secondary.u, secondary.v));
+            }
+            coords
         }
     }
 }
@@ -140,51 +272,116 @@ impl Hittable for TriangleRef {
     fn hit(&self, ray: &Ray, hit_range: Range) -> Option<HitRecord> {
         let (v0, v1, v2) = self.vertices();
 
-        // Möller-Trumbore algorithm
-        let e1 = v1 - v0;
-        let e2 = v2 - v0;
-        let p = ray.direction.cross(e2);
-        let det = e1.dot(p);
+        // Watertight ray-triangle intersection (Woop, Benthin and Wald 2013), following pbrt.
+        // Translate the vertices into ray space, with the ray's dominant axis becoming +z.
+        let kz = ray.direction.abs().max_dimension();
+        let kx = (kz + 1) % 3;
+        let ky = (kx + 1) % 3;
+        let permute = |v: Vec3| Vec3::new(v[kx], v[ky], v[kz]);
 
-        if det.abs() < f32::EPSILON {
-            return None;
+        let d = permute(ray.direction);
+        let mut p0 = permute(v0 - ray.origin);
+        let mut p1 = permute(v1 - ray.origin);
+        let mut p2 = permute(v2 - ray.origin);
+
+        // Shear so that the ray direction becomes (0, 0, 1)
+        let sx = -d.x / d.z;
+        let sy = -d.y / d.z;
+        let sz = 1.0 / d.z;
+        for p in [&mut p0, &mut p1, &mut p2] {
+            p.x += sx * p.z;
+            p.y += sy * p.z;
         }
 
-        let inv_det = 1.0 / det;
-        let t = ray.origin - v0;
-        let u = t.dot(p) * inv_det;
+        // Edge functions, falling back to double precision when they're exactly zero
+        let mut e0 = p1.x * p2.y - p1.y * p2.x;
+        let mut e1 = p2.x * p0.y - p2.y * p0.x;
+        let mut e2 = p0.x * p1.y - p0.y * p1.x;
+        if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+            let edge =
+                |a: Vec3, b: Vec3| (a.x as f64 * b.y as f64 - a.y as f64 * b.x as f64) as f32;
+            e0 = edge(p1, p2);
+            e1 = edge(p2, p0);
+            e2 = edge(p0, p1);
+        }
 
-        if u < 0.0 || u > 1.0 {
+        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+            return None;
+        }
+        let det = e0 + e1 + e2;
+        if det == 0.0 {
             return None;
         }
 
-        let q = t.cross(e1);
-        let v = ray.direction.dot(q) * inv_det;
-        if v < 0.0 || u + v > 1.0 {
+        // Scaled hit distance, compared against the range before dividing by the determinant
+        p0.z *= sz;
+        p1.z *= sz;
+        p2.z *= sz;
+        let t_scaled = e0 * p0.z + e1 * p1.z + e2 * p2.z;
+        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < hit_range.max * det) {
             return None;
         }
+        if det > 0.0 && (t_scaled <= 0.0 || t_scaled > hit_range.max * det) {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let b0 = e0 * inv_det;
+        let b1 = e1 * inv_det;
+        let b2 = e2 * inv_det;
+        let t = t_scaled * inv_det;
 
-        let t = e2.dot(q) * inv_det;
-        if !hit_range.contains(t) {
+        // Conservatively make sure that t is positive given the rounding error of the above
+        let max_x = Vec3::new(p0.x, p1.x, p2.x).abs().max_element();
+        let max_y = Vec3::new(p0.y, p1.y, p2.y).abs().max_element();
+        let max_z = Vec3::new(p0.z, p1.z, p2.z).abs().max_element();
+        let delta_x = math::gamma(5) * (max_x + max_z);
+        let delta_y = math::gamma(5) * (max_y + max_z);
+        let delta_z = math::gamma(3) * max_z;
+        let delta_e = 2.0 * (math::gamma(2) * max_x * max_y + delta_y * max_x + delta_x * max_y);
+        let max_e = Vec3::new(e0, e1, e2).abs().max_element();
+        let delta_t = 3.0
+            * (math::gamma(3) * max_e * max_z + delta_e * max_z + delta_z * max_e)
+            * inv_det.abs();
+        if t <= delta_t || !hit_range.contains(t) {
             return None;
         }
 
+        // Interpolating the vertices is more accurate than evaluating the ray at t
+        let point = v0 * b0 + v1 * b1 + v2 * b2;
+        let error = ((v0 * b0).abs() + (v1 * b1).abs() + (v2 * b2).abs()) * math::gamma(7);
+
+        let geometric_normal = default_normal(v0, v1, v2);
+        let surface = &self.mesh.surface;
+        let back_facing = (ray.direction.dot(geometric_normal) > 0.0) != surface.mirrored;
+        if !surface.double_sided && back_facing {
+            return None;
+        }
         let normal = if let Some((n0, n1, n2)) = self.normals() {
             // interpolate normals based on barycentric coordinates
-            n0 * (1.0 - u - v) + n1 * u + n2 * v
+            n0 * b0 + n1 * b1 + n2 * b2
         } else {
-            default_normal(v0, v1, v2)
+            geometric_normal
         };
-        let uv = self.uv(u, v);
+        let uv = self.uv(b1, b2);
+        if !self.mesh.surface.is_opaque_at(uv, point) {
+            return None;
+        }
+        let (dpdu, dpdv) = self.surface_derivatives(v0, v1, v2, geometric_normal);
 
-        Some(HitRecord::new(
-            ray,
-            normal,
-            ray.evaluate(t),
-            t,
-            self.mesh.material.clone(),
-            uv,
-        ))
+        Some(
+            HitRecord::new(
+                ray,
+                normal,
+                point,
+                t,
+                self.mesh.surface.material.clone(),
+                uv,
+            )
+            .with_error_bounds(error, geometric_normal)
+            .with_surface_derivatives(dpdu, dpdv)
+            .with_medium_interface(self.mesh.surface.medium_interface.clone()),
+        )
     }
 
     fn bounding_box(&self) -> Aabb {
@@ -207,3 +404,196 @@ fn default_normal(v0: Point3, v1: Point3, v2: Point3) -> Vec3 {
 
     e1.cross(e2).normalize()
 }
+
+/// A mesh of a single triangle in the XY plane facing +Z, around the origin.
+#[cfg(test)]
+pub fn single_triangle(surface: Surface) -> TriangleMesh {
+    TriangleMesh::new(
+        0,
+        vec![
+            Point3::new(-1.0, -1.0, 0.0),
+            Point3::new(1.0, -1.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+        ],
+        vec![(0, 1, 2)],
+        vec![],
+        vec![],
+        vec![],
+        surface,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Affine3A;
+
+    use super::{single_triangle, AlphaMode, Surface, TriangleMesh};
+    use crate::material::Material;
+    use crate::object::Hittable;
+    use crate::range::Range;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3, Vec3};
+
+    #[test]
+    fn test_shared_edge_is_watertight() {
+        let mesh = TriangleMesh::new(
+            0,
+            vec![
+                Point3::new(-1.0, -1.0, 0.0),
+                Point3::new(1.0, -1.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(-1.0, 1.0, 0.0),
+            ],
+            vec![(0, 1, 2), (0, 2, 3)],
+            vec![],
+            vec![],
+            vec![],
+            Surface::opaque(Material::lambertian(Vec3::ONE)),
+        );
+        let origins = [
+            Point3::new(0.123, -0.456, 3.0),
+            Point3::new(-2.5, 1.75, 1.0),
+            Point3::new(0.0, 0.0, -4.0),
+        ];
+        let hits = |mesh: &TriangleMesh, origin: Point3, target: Point3| {
+            let ray = Ray::new(origin, target - origin);
+            mesh.faces()
+                .filter(|f| f.hit(&ray, Range::new(0.0, f32::INFINITY)).is_some())
+                .count()
+        };
+
+        // aim at points along the shared diagonal from slightly off-axis origins
+        for i in 0..=100 {
+            let s = i as f32 / 50.0 - 1.0;
+            let target = Point3::new(s, s, 0.0);
+            for origin in origins {
+                let count = hits(&mesh, origin, target);
+                assert!(count >= 1, "ray towards {target} leaked through the edge");
+            }
+        }
+
+        // a vertex shared by a fan of triangles can't slip between them either
+        let ring: Vec<_> = (0..7)
+            .map(|i| {
+                let angle = i as f32 * std::f32::consts::TAU / 7.0;
+                Point3::new(angle.cos(), angle.sin(), 0.0)
+            })
+            .collect();
+        let fan = TriangleMesh::new(
+            0,
+            [vec![Point3::new(0.1, 0.2, 0.0)], ring].concat(),
+            (1..=7).map(|i| (0, i, i % 7 + 1)).collect(),
+            vec![],
+            vec![],
+            vec![],
+            Surface::opaque(Material::lambertian(Vec3::ONE)),
+        );
+        let shared = [
+            (&mesh, Point3::new(-1.0, -1.0, 0.0)),
+            (&mesh, Point3::new(1.0, 1.0, 0.0)),
+            (&fan, Point3::new(0.1, 0.2, 0.0)),
+        ];
+        for (mesh, target) in shared {
+            for origin in origins {
+                let count = hits(mesh, origin, target);
+                assert!(
+                    count >= 1,
+                    "ray from {origin} missed the shared vertex {target}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_alpha_mask_skips_hits() {
+        let triangle = |alpha| {
+            single_triangle(Surface {
+                alpha_mode: AlphaMode::Mask { cutoff: 0.5 },
+                alpha,
+                ..Surface::opaque(Material::lambertian(Vec3::ONE))
+            })
+        };
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let range = Range::new(0.0, f32::INFINITY);
+
+        assert!(triangle(0.2).face(0).hit(&ray, range).is_none());
+        assert!(triangle(0.8).face(0).hit(&ray, range).is_some());
+    }
+
+    #[test]
+    fn test_alpha_blend_keeps_hits_in_proportion() {
+        let triangle = single_triangle(Surface {
+            alpha_mode: AlphaMode::Blend,
+            alpha: 0.3,
+            ..Surface::opaque(Material::lambertian(Vec3::ONE))
+        });
+        let face = triangle.face(0);
+        let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::NEG_Z);
+        let range = Range::new(0.0, f32::INFINITY);
+
+        // within five standard deviations of the binomial distribution
+        let count = 10000;
+        let hits = (0..count)
+            .filter(|_| face.hit(&ray, range).is_some())
+            .count();
+        let fraction = hits as f32 / count as f32;
+        let sigma = (0.3f32 * 0.7 / count as f32).sqrt();
+        assert!(
+            (fraction - 0.3).abs() < 5.0 * sigma,
+            "kept {fraction} of the hits"
+        );
+    }
+
+    #[test]
+    fn test_single_sided_culls_back_faces() {
+        let triangle = |double_sided| {
+            single_triangle(Surface {
+                double_sided,
+                ..Surface::opaque(Material::lambertian(Vec3::ONE))
+            })
+        };
+        let range = Range::new(0.0, f32::INFINITY);
+        let front = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let back = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+
+        let single = triangle(false);
+        let face = single.faces().next().unwrap();
+        assert!(face.hit(&front, range).is_some());
+        assert!(face.hit(&back, range).is_none());
+
+        let double = triangle(true);
+        assert!(double.faces().next().unwrap().hit(&back, range).is_some());
+    }
+
+    #[test]
+    fn test_mirrored_mesh_keeps_its_front_faces() {
+        let mirror = Affine3A::from_scale(Vec3::new(-1.0, 1.0, 1.0).into());
+        let mesh = TriangleMesh::new(
+            0,
+            [
+                Point3::new(-1.0, -1.0, 0.0),
+                Point3::new(1.0, -1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ]
+            .map(|p| mirror.transform_point3a(p))
+            .to_vec(),
+            vec![(0, 1, 2)],
+            vec![],
+            vec![],
+            vec![],
+            Surface {
+                double_sided: false,
+                mirrored: mirror.matrix3.determinant() < 0.0,
+                ..Surface::opaque(Material::lambertian(Vec3::ONE))
+            },
+        );
+        let range = Range::new(0.0, f32::INFINITY);
+        let front = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let back = Ray::new(Point3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 0.0, 1.0));
+
+        // mirroring turns the winding around, but not which side of the triangle is its front
+        let face = mesh.face(0);
+        assert!(face.hit(&front, range).is_some());
+        assert!(face.hit(&back, range).is_none());
+    }
+}
@@ -33,10 +33,17 @@ impl Onb {
     }
 
     pub fn local(&self, a: f32, b: f32, c: f32) -> Vec3 {
-        a * self.u() + b * self.v() + c * self.v()
+        a * self.u() + b * self.v() + c * self.w()
     }
 
+    /// Transforms `vec` from the local frame, where `w` is +z, to world space.
     pub fn local_vec(&self, vec: Vec3) -> Vec3 {
-        vec.x * self.u() + vec.y * self.v() + vec.z * self.v()
+        vec.x * self.u() + vec.y * self.v() + vec.z * self.w()
+    }
+
+    /// Transforms the world space `vec` into the local frame, the inverse of
+    /// [`Onb::local_vec`].
+    pub fn to_local(&self, vec: Vec3) -> Vec3 {
+        Vec3::new(vec.dot(self.u()), vec.dot(self.v()), vec.dot(self.w()))
     }
 }
@@ -20,6 +20,13 @@ impl Range {
         }
     }
 
+    pub fn intersect(a: Range, b: Range) -> Self {
+        Range {
+            min: a.min.max(b.min),
+            max: a.max.min(b.max),
+        }
+    }
+
     pub fn contains(&self, value: f32) -> bool {
         self.min <= value && value <= self.max
     }
@@ -1,14 +1,92 @@
+use glam::Affine3A;
+
 use crate::vec3::{Point3, Vec3};
 
+/// Offset rays through the neighbouring pixels in x and y, used to estimate the footprint of a
+/// camera ray on the surfaces it hits.
+#[derive(Debug, Clone, Copy)]
+pub struct RayDifferentials {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
+}
+
 #[derive(Debug)]
 pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
+    pub differentials: Option<RayDifferentials>,
+    /// Hero wavelength in nanometres when rendering spectrally, for wavelength dependent
+    /// materials.
+    pub wavelength: Option<f32>,
+    /// Time in seconds at which the ray is cast, within the camera's shutter interval.
+    pub time: f32,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            differentials: None,
+            wavelength: None,
+            time: 0.0,
+        }
+    }
+
+    pub fn with_differentials(self, differentials: RayDifferentials) -> Self {
+        Ray {
+            differentials: Some(differentials),
+            ..self
+        }
+    }
+
+    pub fn with_wavelength(self, wavelength: Option<f32>) -> Self {
+        Ray { wavelength, ..self }
+    }
+
+    pub fn with_time(self, time: f32) -> Self {
+        Ray { time, ..self }
+    }
+
+    /// The ray, and its differentials, moved by `transform`.
+    pub fn transformed(self, transform: Affine3A) -> Self {
+        let point = |p| transform.transform_point3a(p);
+        let vector = |v| transform.transform_vector3a(v);
+        Ray {
+            origin: point(self.origin),
+            direction: vector(self.direction),
+            differentials: self.differentials.map(|d| RayDifferentials {
+                rx_origin: point(d.rx_origin),
+                rx_direction: vector(d.rx_direction),
+                ry_origin: point(d.ry_origin),
+                ry_direction: vector(d.ry_direction),
+            }),
+            ..self
+        }
+    }
+
+    /// Spawns a ray leaving a surface at `point`, whose position is only known up to `error` in
+    /// each dimension. The origin is pushed along `normal` just outside of the error bounds, so
+    /// the new ray can't re-intersect the surface it started on.
+    pub fn spawn(point: Point3, error: Vec3, normal: Vec3, direction: Vec3) -> Self {
+        let d = normal.abs().dot(error);
+        let mut offset = normal * d;
+        if direction.dot(normal) < 0.0 {
+            offset = -offset;
+        }
+
+        let mut origin = point + offset;
+        for i in 0..3 {
+            if offset[i] > 0.0 {
+                origin[i] = origin[i].next_up();
+            } else if offset[i] < 0.0 {
+                origin[i] = origin[i].next_down();
+            }
+        }
+
+        Ray::new(origin, direction)
     }
 
     pub fn evaluate(&self, t: f32) -> Point3 {
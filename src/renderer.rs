@@ -8,11 +8,14 @@ use tracing::info;
 
 use crate::camera::Camera;
 use crate::material::Scatterable;
+use crate::medium::{MediumEvent, MediumInterface, Participating};
 use crate::object::Hittable;
+use crate::random::random;
 use crate::range::Range;
 use crate::ray::Ray;
-use crate::scene::{RenderSettings, SceneDescription};
-use crate::vec3::{Color, Vec3};
+use crate::scene::{CropWindow, RenderSettings, SceneDescription};
+use crate::spectrum::{ColorModel, Rgb, SampledWavelengths};
+use crate::vec3::{self, Color, Vec3};
 use crate::Result;
 
 pub struct Renderer {
@@ -30,64 +33,193 @@ impl Renderer {
         }
     }
 
-    fn ray_color(&self, ray: Ray, world: &impl Hittable) -> Color {
-        let mut l = Color::ZERO;
-        let mut beta = Color::ONE;
+    /// Traces one camera ray through pixel `(i, j)` and returns its linear RGB radiance.
+    fn sample_pixel(&self, i: u32, j: u32) -> Color {
+        let Some(ray) = self.camera.get_ray(i, j) else {
+            return Color::ZERO;
+        };
+        let world = &self.scene.root_object;
+        let debug = self.render.debug_pixel == Some((i, j));
+        if debug {
+            info!("tracing pixel ({i}, {j}) with {ray:#?}");
+        }
+        let color = if self.render.spectral {
+            let wavelengths = SampledWavelengths::sample_visible(random());
+            let radiance = self.ray_color(ray, world, &wavelengths, debug);
+            wavelengths.to_rgb(radiance)
+        } else {
+            self.ray_color(ray, world, &Rgb, debug)
+        };
+        if debug {
+            info!("pixel ({i}, {j}) received {color}");
+        }
+        color
+    }
+
+    /// Follows a path from `ray`, logging every bounce if `debug` is set.
+    fn ray_color<M: ColorModel>(
+        &self,
+        ray: Ray,
+        world: &impl Hittable,
+        model: &M,
+        debug: bool,
+    ) -> M::Value {
+        let mut l = M::ZERO;
+        let mut beta = M::ONE;
         let mut depth = 0;
-        let range = Range::new(self.camera.z_near, self.camera.z_far);
-        let mut ray = ray;
-        while beta != Color::ZERO {
+        let mut range = Range::new(self.camera.z_near, self.camera.z_far);
+        let mut ray = ray.with_wavelength(model.hero_wavelength());
+        let mut single_wavelength = false;
+        let mut medium = self.camera.medium.clone();
+        // the RGB weights picked up in media, guiding their chromatic tracking
+        let mut medium_throughput = Color::ONE;
+        while beta != M::ZERO {
             if depth >= self.render.max_depth {
                 break;
             }
 
             let si = world.hit(&ray, range);
 
+            if let Some(current) = &medium {
+                let t_max = si.as_ref().map_or(f32::INFINITY, |hit| hit.distance);
+                let event = current.sample_interaction(&ray, t_max, medium_throughput);
+                if debug {
+                    info!("bounce {depth}: {event:#?}");
+                }
+                match event {
+                    MediumEvent::Scattered {
+                        point,
+                        weight,
+                        phase,
+                    } => {
+                        beta *= model.albedo(weight);
+                        medium_throughput *= weight;
+                        if debug {
+                            info!("bounce {depth}: throughput {beta:?}");
+                        }
+                        let direction =
+                            phase.sample(ray.direction.normalize(), vec3::random::gen_2d());
+                        ray = Ray::new(point, direction)
+                            .with_wavelength(ray.wavelength)
+                            .with_time(ray.time);
+                        depth += 1;
+                        range = Range::new(0.0, f32::INFINITY);
+                        continue;
+                    }
+                    MediumEvent::Absorbed => break,
+                    MediumEvent::Transmitted { weight } => {
+                        beta *= model.albedo(weight);
+                        medium_throughput *= weight;
+                        if debug {
+                            info!("bounce {depth}: throughput {beta:?}");
+                        }
+                    }
+                }
+            }
+
             match si {
-                Some(hit) => {
-                    l += beta * hit.material.emit(hit.tex_coords, hit.point);
+                Some(hit) if hit.material.is_interface() => {
+                    if debug {
+                        info!("bounce {depth}: crossing medium interface {hit:#?}");
+                    }
+                    // crossing a medium boundary doesn't count as a bounce
+                    if let Some(interface) = &hit.medium_interface {
+                        medium = interface.after(hit.front_facing, hit.normal, ray.direction);
+                    }
+                    ray = hit
+                        .spawn_ray(ray.direction)
+                        .with_wavelength(ray.wavelength)
+                        .with_time(ray.time);
+                    range = Range::new(0.0, f32::INFINITY);
+                    continue;
+                }
+                Some(mut hit) => {
+                    hit.compute_differentials(&ray);
+                    l += beta * model.illuminant(hit.material.emit(hit.tex_coords, hit.point));
                     let sample = hit.material.scatter(&ray, &hit);
+                    if debug {
+                        info!("bounce {depth}: hit {hit:#?}");
+                        info!("bounce {depth}: scattered {sample:#?}");
+                    }
                     if let Some(sample) = sample {
-                        beta *= sample.attenuation
-                            * sample.scattered.direction.dot(hit.normal).abs()
-                            / sample.pdf.unwrap_or(1.0);
-                        ray = sample.scattered;
+                        beta *= model.albedo(
+                            sample.attenuation * sample.scattered.direction.dot(hit.normal).abs()
+                                / sample.pdf.unwrap_or(1.0),
+                        );
+                        if sample.dispersive && !single_wavelength {
+                            beta *= model.terminate_secondary();
+                            single_wavelength = true;
+                        }
+                        if debug {
+                            info!("bounce {depth}: throughput {beta:?}");
+                        }
+                        let interface = match sample.interior {
+                            Some(inside) => Some(MediumInterface {
+                                inside: Some(inside),
+                                outside: hit
+                                    .medium_interface
+                                    .and_then(|interface| interface.outside),
+                            }),
+                            None => hit.medium_interface,
+                        };
+                        if let Some(interface) = interface {
+                            medium = interface.after(
+                                hit.front_facing,
+                                hit.normal,
+                                sample.scattered.direction,
+                            );
+                        }
+                        ray = sample
+                            .scattered
+                            .with_wavelength(ray.wavelength)
+                            .with_time(ray.time);
                     } else {
                         break;
                     }
                 }
                 None => {
+                    if debug {
+                        info!("bounce {depth}: escaped with throughput {beta:?}");
+                    }
                     // TODO infinite lights
-                    l = self.render.background_color;
+                    l = model.illuminant(self.render.background_color);
                     break;
                 }
             }
 
             depth += 1;
+            // spawned rays are offset from the surface, so they don't need the near plane
+            range = Range::new(0.0, f32::INFINITY);
         }
 
         l
     }
 
+    pub fn into_scene(self) -> SceneDescription {
+        self.scene
+    }
+
     pub fn render(&self, square_size: usize) -> RgbImage {
+        DynamicImage::from(self.render_hdr(square_size)).into_rgb8()
+    }
+
+    /// Renders the image with linear colours, unclamped for high dynamic range output.
+    pub fn render_hdr(&self, square_size: usize) -> Rgb32FImage {
         let start = Instant::now();
 
         let sample_scale = 1.0 / self.render.samples_per_pixel as f32;
-        let width = self.render.image_width;
-        let height = self.render.image_height;
-        let pixel_count = width * height;
+        let region = self.render.region();
+        let pixel_count = region.width() * region.height();
         let pixels: Vec<_> = (0..pixel_count).collect();
         let pixels = pixels
             .par_chunks(square_size * square_size)
             .flat_map(|chunk| {
                 let mut pixels = vec![];
-                for index in chunk {
-                    let i = index % width;
-                    let j = index / width;
+                for &index in chunk {
+                    let (i, j) = region.pixel(index);
                     let mut color = Vec3::ZERO;
                     for _ in 0..self.render.samples_per_pixel {
-                        let ray = self.camera.get_ray(i, j);
-                        color += self.ray_color(ray, &self.scene.root_object);
+                        color += self.sample_pixel(i, j);
                     }
                     let result = color * sample_scale;
                     pixels.extend([result.x, result.y, result.z]);
@@ -99,12 +231,14 @@ impl Renderer {
         let elapsed = start.elapsed();
         info!("Rendering took {elapsed:?}");
 
-        pixels_to_image(pixels, self.render.image_width, self.render.image_height)
+        Rgb32FImage::from_vec(region.width(), region.height(), pixels)
+            .expect("dimensions must match")
     }
 
     pub fn render_progressive(&self, mut output: ImageOutput, square_size: usize) -> Result<()> {
         let start = Instant::now();
-        let pixel_count = self.render.image_height * self.render.image_width;
+        let region = self.render.region();
+        let pixel_count = region.width() * region.height();
         let image_size = (pixel_count * 3) as usize;
 
         let mut aggregate_image = vec![0.0; image_size];
@@ -120,11 +254,9 @@ impl Renderer {
                 .par_chunks(chunk_size)
                 .flat_map(|chunk| {
                     let mut pixels = vec![];
-                    for index in chunk {
-                        let i = index % self.render.image_width;
-                        let j = index / self.render.image_width;
-                        let ray = self.camera.get_ray(i, j);
-                        let color = self.ray_color(ray, &self.scene.root_object);
+                    for &index in chunk {
+                        let (i, j) = region.pixel(index);
+                        let color = self.sample_pixel(i, j);
                         pixels.extend([color.x, color.y, color.z]);
                     }
                     pixels
@@ -139,11 +271,7 @@ impl Renderer {
                 let intermediate_image: Vec<_> =
                     aggregate_image.iter().map(|c| c * sample_scale).collect();
 
-                output.write(
-                    intermediate_image,
-                    self.render.image_width,
-                    self.render.image_height,
-                )?;
+                output.write(intermediate_image, region)?;
                 info!("Outputted image after sample {}", current_sample);
             }
             let elapsed = sample_start.elapsed();
@@ -167,7 +295,9 @@ fn linear_to_gamma(linear_component: f32) -> f32 {
 fn should_save_image(current_sample: u32, sample_count: u32) -> bool {
     let save_interval = 16;
 
-    current_sample <= 5 || current_sample % save_interval == 0 || current_sample == sample_count
+    current_sample <= 5
+        || current_sample.is_multiple_of(save_interval)
+        || current_sample == sample_count
 }
 
 fn pixels_to_image(pixels: Vec<f32>, width: u32, height: u32) -> RgbImage {
@@ -196,7 +326,10 @@ impl ImageOutput {
         Ok(())
     }
 
-    pub fn write(&mut self, pixels: Vec<f32>, width: u32, height: u32) -> Result<()> {
+    /// Writes the pixels of `region`, which files receive on their own and viewers in place
+    /// within the full image.
+    pub fn write(&mut self, pixels: Vec<f32>, region: CropWindow) -> Result<()> {
+        let (width, height) = (region.width(), region.height());
         match self {
             Self::File(destination) => {
                 let image = pixels_to_image(pixels, width, height);
@@ -212,8 +345,8 @@ impl ImageOutput {
                     width,
                     height,
                     channel_names: &["R", "G", "B"],
-                    x: 0,
-                    y: 0,
+                    x: region.x0,
+                    y: region.y0,
                     data: &pixels,
                     channel_offsets,
                     channel_strides,
@@ -1,4 +1,4 @@
-use std::f32::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4};
+use std::f32::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, TAU};
 
 use glam::{Vec2, Vec3A};
 
@@ -7,10 +7,10 @@ use crate::math;
 pub fn sample_uniform_disk_concentric(u: Vec2) -> Vec2 {
     let u_offset = 2.0 * u - Vec2::ONE;
     if u_offset.x == 0.0 && u_offset.y == 0.0 {
-        return Vec2::ONE;
+        return Vec2::ZERO;
     }
 
-    let (theta, r) = if u_offset.x.abs() > u_offset.y.abs() {
+    let (r, theta) = if u_offset.x.abs() > u_offset.y.abs() {
         (u_offset.x, FRAC_PI_4 * (u_offset.y / u_offset.x))
     } else {
         (
@@ -22,6 +22,27 @@ pub fn sample_uniform_disk_concentric(u: Vec2) -> Vec2 {
     r * Vec2::new(theta.cos(), theta.sin())
 }
 
+pub fn sample_uniform_disk_polar(u: Vec2) -> Vec2 {
+    let r = u.x.sqrt();
+    let theta = TAU * u.y;
+    r * Vec2::new(theta.cos(), theta.sin())
+}
+
+/// Samples a regular polygon with `sides` corners on the unit circle, the first at angle
+/// `rotation`, by picking one of the equally sized triangles around its centre.
+pub fn sample_regular_polygon(u: Vec2, sides: u32, rotation: f32) -> Vec2 {
+    let n = sides as f32;
+    let triangle = (u.x * n).floor().min(n - 1.0);
+    let u_x = u.x * n - triangle;
+
+    let corner = |k: f32| {
+        let phi = rotation + k * TAU / n;
+        Vec2::new(phi.cos(), phi.sin())
+    };
+    let r = u_x.sqrt();
+    r * ((1.0 - u.y) * corner(triangle) + u.y * corner(triangle + 1.0))
+}
+
 pub fn cosine_hemisphere(u: Vec2) -> Vec3A {
     let d = sample_uniform_disk_concentric(u);
     let z = math::safe_sqrt(1.0 - (d.x * d.x) - (d.y * d.y));
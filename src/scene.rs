@@ -1,24 +1,43 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::Arc;
 
-use glam::{Affine3A, Mat4};
+use color_eyre::eyre::eyre;
+use glam::{Affine3A, Mat4, Quat};
 use gltf::camera::Projection;
+use gltf::json::Value;
 use gltf::mesh::Mode;
-use image::{DynamicImage, ImageBuffer, Luma, LumaA, Rgb, Rgba};
-use tracing::{debug, info};
+use image::{DynamicImage, ImageBuffer, Luma, LumaA, Pixel, Rgb, Rgba};
+use tracing::{debug, info, warn};
 
+use crate::aabb::Aabb;
+use crate::animation::{AnimatedTransform, Interpolation, Track};
 use crate::bvh::{BvhNode, BvhType, FlatBvhTree};
-use crate::material::{DiffuseLight, Material};
-use crate::object::triangle_mesh::TriangleMesh;
-use crate::object::{Object, World};
-use crate::texture::{Image, SolidColor, Texture, TextureCoordinates};
+use crate::bvh_cache;
+use crate::camera::{AspectFit, FisheyeMapping};
+use crate::material::{Absorption, Coat, Dielectric, Material, Sheen, ThinDielectric};
+use crate::medium::{DensityGrid, Grid, Medium, MediumInterface};
+use crate::object::triangle_mesh::{AlphaMode, Surface, TriangleMesh};
+use crate::object::{Hittable, MovingMesh, Object, World};
+use crate::range::Range;
+use crate::ray::Ray;
+use crate::texture::{
+    Channel, ColorSpace, FilterMode, Image, Pattern, Procedural, Sampler, ScalarTexture,
+    SolidColor, Texture, TextureCoordinates, TextureTransform, WrapMode,
+};
 use crate::vec3::{Color, Point3, Vec3};
-use crate::Result;
+use crate::{volume, Result};
 
 #[derive(Debug)]
 pub struct SceneDescription {
     pub root_object: Object,
     pub cameras: Vec<CameraSettings>,
+    pub meshes: Vec<TriangleMesh>,
+    /// Content hash of the glTF file and its buffers, used to key the BVH cache.
+    pub source_hash: u64,
+    /// Times in seconds the moving meshes are bounded over, see [`Self::set_shutter`].
+    pub shutter: Range,
 }
 
 impl SceneDescription {
@@ -41,15 +60,73 @@ impl SceneDescription {
 
             Self {
                 root_object,
-                cameras: self.cameras,
+                ..self
             }
         } else {
             self
         }
     }
+
+    /// Bounds moving meshes by the poses they take while the shutter is open, instead of their
+    /// whole animation, and refits the BVH around them. Cheaper than a rebuild, as the tree's
+    /// structure stays the same.
+    pub fn set_shutter(&mut self, shutter: Range) {
+        self.shutter = shutter;
+        let mut leaf_bounds = |object: &mut Object| {
+            if let Object::MovingMesh(mesh) = object {
+                mesh.set_shutter(shutter);
+            }
+            object.bounding_box()
+        };
+        match &mut self.root_object {
+            Object::World(world) => {
+                for object in &mut world.objects {
+                    leaf_bounds(object);
+                }
+                world.bounding_box = Aabb::from_objects(&world.objects);
+            }
+            Object::FlatBvhTree(tree) => tree.refit(leaf_bounds),
+            Object::BvhNode(node) => {
+                node.refit(&mut leaf_bounds);
+            }
+            _ => {}
+        }
+    }
+
+    /// Builds a flat BVH, reusing the one stored in `cache_path` if it was built for the same
+    /// scene contents. A missing or stale cache is rebuilt and written back; failing to read or
+    /// write the cache only logs a warning.
+    pub fn build_bvh_cached(self, cache_path: impl AsRef<Path>) -> Self {
+        let cache_path = cache_path.as_ref();
+        if !matches!(self.root_object, Object::World(_)) {
+            return self;
+        }
+
+        match bvh_cache::load(cache_path, &self) {
+            Ok(Some(tree)) => {
+                info!("loaded BVH from cache {}", cache_path.display());
+                return Self {
+                    root_object: Object::FlatBvhTree(tree),
+                    ..self
+                };
+            }
+            Ok(None) => info!("BVH cache {} is missing or stale", cache_path.display()),
+            Err(e) => warn!("failed to read BVH cache {}: {e}", cache_path.display()),
+        }
+
+        let scene = self.build_bvh(BvhType::Flat);
+        if let Object::FlatBvhTree(tree) = &scene.root_object {
+            match bvh_cache::save(cache_path, tree, &scene) {
+                Ok(()) => info!("wrote BVH cache {}", cache_path.display()),
+                Err(e) => warn!("failed to write BVH cache {}: {e}", cache_path.display()),
+            }
+        }
+
+        scene
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RenderSettings {
     pub image_width: u32,
     pub image_height: u32,
@@ -57,29 +134,216 @@ pub struct RenderSettings {
     pub max_depth: u32,
     pub samples_per_pixel: u32,
     pub background_color: Color,
+    /// Trace sampled wavelengths instead of RGB.
+    pub spectral: bool,
+    /// Render only these pixels of the full image.
+    pub crop: Option<CropWindow>,
+    /// Log every bounce of the paths traced through this pixel.
+    pub debug_pixel: Option<(u32, u32)>,
+}
+
+impl RenderSettings {
+    /// The pixels to render: the crop window, just the debug pixel without one, or else the
+    /// whole image.
+    pub fn region(&self) -> CropWindow {
+        match (self.crop, self.debug_pixel) {
+            (Some(crop), _) => crop,
+            (None, Some((x, y))) => CropWindow {
+                x0: x,
+                y0: y,
+                x1: x + 1,
+                y1: y + 1,
+            },
+            (None, None) => CropWindow {
+                x0: 0,
+                y0: 0,
+                x1: self.image_width,
+                y1: self.image_height,
+            },
+        }
+    }
+
+    /// Checks that the crop window fits in the image, and that the debug pixel lies within
+    /// both, as it would otherwise never be rendered.
+    pub fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(crop) = self.crop {
+            if crop.x1 > self.image_width || crop.y1 > self.image_height {
+                return Err(format!(
+                    "crop window {},{},{},{} does not fit in the {}x{} image",
+                    crop.x0, crop.y0, crop.x1, crop.y1, self.image_width, self.image_height
+                ));
+            }
+        }
+        let Some((x, y)) = self.debug_pixel else {
+            return Ok(());
+        };
+        if x >= self.image_width || y >= self.image_height {
+            return Err(format!(
+                "debug pixel {x},{y} is outside of the {}x{} image",
+                self.image_width, self.image_height
+            ));
+        }
+        if let Some(crop) = self.crop {
+            if !(crop.x0..crop.x1).contains(&x) || !(crop.y0..crop.y1).contains(&y) {
+                return Err(format!(
+                    "debug pixel {x},{y} is outside of the crop window {},{},{},{}",
+                    crop.x0, crop.y0, crop.x1, crop.y1
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A rectangle of pixels from `(x0, y0)` up to, but excluding, `(x1, y1)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropWindow {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl CropWindow {
+    pub fn width(&self) -> u32 {
+        self.x1.saturating_sub(self.x0)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1.saturating_sub(self.y0)
+    }
+
+    /// The pixel at `index` counting row by row through the window.
+    pub fn pixel(&self, index: u32) -> (u32, u32) {
+        (
+            self.x0 + index % self.width(),
+            self.y0 + index / self.width(),
+        )
+    }
+}
+
+impl FromStr for CropWindow {
+    type Err = String;
+
+    /// Parses `x0,y0,x1,y1`.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("expected a crop window as x0,y0,x1,y1, got {s:?}");
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse().map_err(|_| invalid()))
+            .collect::<std::result::Result<Vec<u32>, _>>()?;
+        match values[..] {
+            [x0, y0, x1, y1] if x0 < x1 && y0 < y1 => Ok(CropWindow { x0, y0, x1, y1 }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum CameraProjection {
+    /// A perspective projection with a vertical field of view in radians.
+    Perspective { y_fov: f32 },
+    /// A parallel projection of a view volume extending `x_mag` and `y_mag` scene units to
+    /// either side of the camera.
+    Orthographic { x_mag: f32, y_mag: f32 },
+    /// A 360° latitude-longitude panorama, stacking the views of two eyes `stereo_ipd` scene
+    /// units apart on top of each other for stereo.
+    Equirectangular { stereo_ipd: Option<f32> },
+    /// The six faces of a cube around the camera.
+    Cubemap,
+    /// A circular fisheye image covering a field of view of `fov` radians.
+    Fisheye { fov: f32, mapping: FisheyeMapping },
 }
 
 #[derive(Debug, Clone)]
 pub struct CameraSettings {
     pub name: Option<String>,
-    pub y_fov: f32,
+    pub projection: CameraProjection,
+    /// Width over height of the view, which defaults to that of the image.
+    pub aspect_ratio: Option<f32>,
+    pub aspect_fit: AspectFit,
     pub z_near: f32,
     pub z_far: f32,
     pub transform: Affine3A,
-    pub focus_dist: f32,
-    pub defocus_angle: f32,
+    pub lens: Lens,
+    pub medium: Option<Arc<Medium>>,
+    /// Times in seconds at which the shutter opens and closes.
+    pub shutter: Range,
+    /// Keyframed transform of an animated camera, replacing `transform` while the shutter is
+    /// open.
+    pub motion: Option<Arc<AnimatedTransform>>,
+}
+
+impl CameraSettings {
+    /// Focuses the lens on whatever `world` shows at the centre of the image as the shutter
+    /// opens, by casting a probe ray along the view axis. Returns the new focus distance, or
+    /// `None` if the ray escapes and the focus is left as it was.
+    pub fn autofocus(&mut self, world: &impl Hittable) -> Option<f32> {
+        let time = self.shutter.min;
+        let transform = match &self.motion {
+            Some(motion) => motion.at(time),
+            None => self.transform,
+        };
+        let origin = transform.transform_point3a(Point3::ZERO);
+        let direction = transform.transform_vector3a(Vec3::NEG_Z).normalize();
+        let hit = world.hit(
+            &Ray::new(origin, direction).with_time(time),
+            Range::new(self.z_near, self.z_far),
+        )?;
+        self.lens.focus_distance = Some(hit.distance);
+        Some(hit.distance)
+    }
+}
+
+/// The lens of a perspective camera, in the units photographers and Blender use, with scene
+/// units taken to be metres as in glTF. Unset values fall back to the field of view of the
+/// projection, a 36 mm full frame sensor, a pinhole aperture and a focus distance of 10.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lens {
+    /// Focal length in millimetres, which replaces the field of view of the projection.
+    pub focal_length: Option<f32>,
+    /// Focal length divided by the diameter of the aperture.
+    pub f_stop: Option<f32>,
+    /// Sensor width in millimetres, spanning the longer side of the image.
+    pub sensor_width: Option<f32>,
+    /// Distance to the plane in focus, in scene units.
+    pub focus_distance: Option<f32>,
+    /// Number of straight aperture blades, where fewer than three give a round aperture.
+    pub blades: Option<u32>,
+    /// Rotation of the aperture blades in radians.
+    pub blade_rotation: Option<f32>,
+}
+
+impl Lens {
+    /// This lens with every parameter set in `overrides` replaced.
+    pub fn overridden_by(self, overrides: Lens) -> Lens {
+        Lens {
+            focal_length: overrides.focal_length.or(self.focal_length),
+            f_stop: overrides.f_stop.or(self.f_stop),
+            sensor_width: overrides.sensor_width.or(self.sensor_width),
+            focus_distance: overrides.focus_distance.or(self.focus_distance),
+            blades: overrides.blades.or(self.blades),
+            blade_rotation: overrides.blade_rotation.or(self.blade_rotation),
+        }
+    }
 }
 
 impl Default for CameraSettings {
     fn default() -> Self {
         Self {
             name: Default::default(),
-            y_fov: 80.0f32.to_radians(),
+            projection: CameraProjection::Perspective {
+                y_fov: 80.0f32.to_radians(),
+            },
+            aspect_ratio: None,
+            aspect_fit: AspectFit::default(),
             z_near: 0.0001,
             z_far: f32::INFINITY,
             transform: Affine3A::IDENTITY,
-            focus_dist: 10.0,
-            defocus_angle: 0.0,
+            lens: Lens::default(),
+            medium: None,
+            shutter: Range::new(0.0, 0.0),
+            motion: None,
         }
     }
 }
@@ -87,75 +351,779 @@ impl Default for CameraSettings {
 fn load_image(image: gltf::image::Data, name: &str) -> Result<DynamicImage> {
     use gltf::image::Format;
 
+    fn buffer<P: Pixel>(
+        width: u32,
+        height: u32,
+        data: Vec<P::Subpixel>,
+        name: &str,
+    ) -> Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+        ImageBuffer::from_raw(width, height, data)
+            .ok_or_else(|| eyre!("image {name} has fewer pixels than its dimensions"))
+    }
+
+    // gltf hands out 16 bit and float pixels as native endian bytes
+    let to_u16 = |bytes: &[u8]| -> Vec<u16> {
+        bytes
+            .chunks_exact(2)
+            .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+            .collect()
+    };
+    let to_f32 = |bytes: &[u8]| -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    };
+
+    let (w, h) = (image.width, image.height);
+    let pixels = image.pixels;
     let image = match image.format {
-        Format::R8 => DynamicImage::from(
-            ImageBuffer::<Luma<u8>, _>::from_raw(image.width, image.height, image.pixels)
-                .expect("failed to construct image"),
+        Format::R8 => DynamicImage::from(buffer::<Luma<u8>>(w, h, pixels, name)?),
+        Format::R8G8 => DynamicImage::from(buffer::<LumaA<u8>>(w, h, pixels, name)?),
+        Format::R8G8B8 => DynamicImage::from(buffer::<Rgb<u8>>(w, h, pixels, name)?),
+        Format::R8G8B8A8 => DynamicImage::from(buffer::<Rgba<u8>>(w, h, pixels, name)?),
+        Format::R16 => DynamicImage::from(buffer::<Luma<u16>>(w, h, to_u16(&pixels), name)?),
+        Format::R16G16 => DynamicImage::from(buffer::<LumaA<u16>>(w, h, to_u16(&pixels), name)?),
+        Format::R16G16B16 => DynamicImage::from(buffer::<Rgb<u16>>(w, h, to_u16(&pixels), name)?),
+        Format::R16G16B16A16 => {
+            DynamicImage::from(buffer::<Rgba<u16>>(w, h, to_u16(&pixels), name)?)
+        }
+        Format::R32G32B32FLOAT => {
+            DynamicImage::from(buffer::<Rgb<f32>>(w, h, to_f32(&pixels), name)?)
+        }
+        Format::R32G32B32A32FLOAT => {
+            DynamicImage::from(buffer::<Rgba<f32>>(w, h, to_f32(&pixels), name)?)
+        }
+    };
+
+    Ok(image)
+}
+
+fn read_sampler(sampler: gltf::texture::Sampler) -> Sampler {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let wrap = |mode| match mode {
+        WrappingMode::Repeat => WrapMode::Repeat,
+        WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+    };
+
+    Sampler {
+        wrap_s: wrap(sampler.wrap_s()),
+        wrap_t: wrap(sampler.wrap_t()),
+        filter: match sampler.mag_filter() {
+            Some(MagFilter::Nearest) => FilterMode::Nearest,
+            Some(MagFilter::Linear) | None => FilterMode::Linear,
+        },
+        mipmaps: !matches!(
+            sampler.min_filter(),
+            Some(MinFilter::Nearest | MinFilter::Linear)
         ),
-        Format::R8G8 => DynamicImage::from(
-            ImageBuffer::<LumaA<u8>, _>::from_raw(image.width, image.height, image.pixels)
-                .expect("failed to construct image"),
+    }
+}
+
+fn load_texture(
+    info: &gltf::texture::Info,
+    images: &[gltf::image::Data],
+    color_space: ColorSpace,
+) -> Result<Image> {
+    let (tex_coord, transform) = match info.texture_transform() {
+        Some(transform) => (
+            transform.tex_coord().unwrap_or(info.tex_coord()),
+            TextureTransform {
+                offset: transform.offset(),
+                rotation: transform.rotation(),
+                scale: transform.scale(),
+            },
         ),
-        Format::R8G8B8 => DynamicImage::from(
-            ImageBuffer::<Rgb<u8>, _>::from_raw(image.width, image.height, image.pixels)
-                .expect("failed to construct image"),
+        None => (info.tex_coord(), TextureTransform::IDENTITY),
+    };
+
+    load_texture_source(info.texture(), tex_coord, transform, images, color_space)
+}
+
+fn load_texture_source(
+    texture: gltf::Texture,
+    tex_coord: u32,
+    transform: TextureTransform,
+    images: &[gltf::image::Data],
+    color_space: ColorSpace,
+) -> Result<Image> {
+    let image = images[texture.source().index()].clone();
+    let image = load_image(image, texture.name().unwrap_or("<no name>"))?;
+
+    Ok(Image::new(image, color_space)
+        .with_sampler(read_sampler(texture.sampler()))
+        .with_tex_coord(tex_coord, transform))
+}
+
+/// Loads the texture stored under `key` in the JSON of a material extension that the gltf
+/// crate doesn't parse itself.
+fn load_extension_texture(
+    document: &gltf::Document,
+    extension: &Value,
+    key: &str,
+    images: &[gltf::image::Data],
+    color_space: ColorSpace,
+) -> Result<Option<Arc<Texture>>> {
+    let Some(value) = extension.get(key) else {
+        return Ok(None);
+    };
+
+    let info: gltf::json::texture::Info = gltf::json::deserialize::from_value(value.clone())?;
+    let texture = document
+        .textures()
+        .nth(info.index.value())
+        .ok_or_else(|| eyre!("{key} refers to a missing texture"))?;
+    let (tex_coord, transform) = match info
+        .extensions
+        .as_ref()
+        .and_then(|e| e.texture_transform.as_ref())
+    {
+        Some(transform) => (
+            transform.tex_coord.unwrap_or(info.tex_coord),
+            TextureTransform {
+                offset: transform.offset.0,
+                rotation: transform.rotation.0,
+                scale: transform.scale.0,
+            },
         ),
-        Format::R8G8B8A8 => DynamicImage::from(
-            ImageBuffer::<Rgba<u8>, _>::from_raw(image.width, image.height, image.pixels)
-                .expect("failed to construct image"),
+        None => (info.tex_coord, TextureTransform::IDENTITY),
+    };
+
+    let image = load_texture_source(texture, tex_coord, transform, images, color_space)?;
+    Ok(Some(Arc::new(Texture::Image(image))))
+}
+
+fn extension_factor(extension: &Value, key: &str, default: f32) -> f32 {
+    extension
+        .get(key)
+        .and_then(Value::as_f64)
+        .map_or(default, |v| v as f32)
+}
+
+fn extension_color(extension: &Value, key: &str, default: Color) -> Color {
+    extension
+        .get(key)
+        .and_then(|v| gltf::json::deserialize::from_value::<[f32; 3]>(v.clone()).ok())
+        .map_or(default, Color::from)
+}
+
+/// A scalar material input from one channel of an optional texture, scaled by `factor`.
+fn read_scalar(texture: Option<Arc<Texture>>, channel: Channel, factor: f32) -> ScalarTexture {
+    match texture {
+        Some(texture) => ScalarTexture::texture(texture, channel, factor),
+        None => factor.into(),
+    }
+}
+
+fn load_optional_texture(
+    info: Option<gltf::texture::Info>,
+    images: &[gltf::image::Data],
+    color_space: ColorSpace,
+) -> Result<Option<Arc<Texture>>> {
+    info.map(|info| {
+        Ok(Arc::new(Texture::Image(load_texture(
+            &info,
+            images,
+            color_space,
+        )?)))
+    })
+    .transpose()
+}
+
+/// The specular layer of a non-metal using `KHR_materials_specular`.
+fn read_specular_coat(
+    material: &gltf::Material,
+    roughness: ScalarTexture,
+    images: &[gltf::image::Data],
+) -> Result<Option<Coat>> {
+    let Some(specular) = material.specular() else {
+        return Ok(None);
+    };
+
+    let weight = read_scalar(
+        load_optional_texture(specular.specular_texture(), images, ColorSpace::Linear)?,
+        Channel::Alpha,
+        specular.specular_factor(),
+    );
+    let tint =
+        match load_optional_texture(specular.specular_color_texture(), images, ColorSpace::Srgb)? {
+            Some(texture) => texture,
+            None => Texture::solid_color(Color::from(specular.specular_color_factor())),
+        };
+
+    Ok(Some(Coat {
+        weight,
+        tint,
+        roughness,
+        ior: material.ior().unwrap_or(1.5),
+    }))
+}
+
+/// The clearcoat layer from `KHR_materials_clearcoat`.
+fn read_clearcoat(
+    document: &gltf::Document,
+    material: &gltf::Material,
+    images: &[gltf::image::Data],
+) -> Result<Option<Coat>> {
+    let Some(extension) = material.extension_value("KHR_materials_clearcoat") else {
+        return Ok(None);
+    };
+
+    let texture =
+        |key| load_extension_texture(document, extension, key, images, ColorSpace::Linear);
+    Ok(Some(Coat {
+        weight: read_scalar(
+            texture("clearcoatTexture")?,
+            Channel::Red,
+            extension_factor(extension, "clearcoatFactor", 0.0),
         ),
-        _ => panic!(
-            "unsupported image format {:?} for image {}",
-            image.format, name
+        tint: Texture::solid_color(Color::ONE),
+        roughness: read_scalar(
+            texture("clearcoatRoughnessTexture")?,
+            Channel::Green,
+            extension_factor(extension, "clearcoatRoughnessFactor", 0.0),
         ),
+        ior: 1.5,
+    }))
+}
+
+/// The sheen layer from `KHR_materials_sheen`.
+fn read_sheen(
+    document: &gltf::Document,
+    material: &gltf::Material,
+    images: &[gltf::image::Data],
+) -> Result<Option<Sheen>> {
+    let Some(extension) = material.extension_value("KHR_materials_sheen") else {
+        return Ok(None);
     };
 
-    Ok(image)
+    let color = match load_extension_texture(
+        document,
+        extension,
+        "sheenColorTexture",
+        images,
+        ColorSpace::Srgb,
+    )? {
+        Some(texture) => texture,
+        None => Texture::solid_color(extension_color(extension, "sheenColorFactor", Color::ZERO)),
+    };
+    let roughness = read_scalar(
+        load_extension_texture(
+            document,
+            extension,
+            "sheenRoughnessTexture",
+            images,
+            ColorSpace::Linear,
+        )?,
+        Channel::Alpha,
+        extension_factor(extension, "sheenRoughnessFactor", 0.0),
+    );
+
+    Ok(Some(Sheen { color, roughness }))
+}
+
+/// The diffuse transmission lobe from `KHR_materials_diffuse_transmission`, as the fraction of
+/// light transmitted and the transmitted colour. The colour is only tinted by the base colour
+/// when it has no texture of its own.
+fn read_diffuse_transmission(
+    document: &gltf::Document,
+    material: &gltf::Material,
+    base_color: Arc<Texture>,
+    images: &[gltf::image::Data],
+) -> Result<Option<(ScalarTexture, Arc<Texture>)>> {
+    let Some(extension) = material.extension_value("KHR_materials_diffuse_transmission") else {
+        return Ok(None);
+    };
+
+    let factor = read_scalar(
+        load_extension_texture(
+            document,
+            extension,
+            "diffuseTransmissionTexture",
+            images,
+            ColorSpace::Linear,
+        )?,
+        Channel::Alpha,
+        extension_factor(extension, "diffuseTransmissionFactor", 0.0),
+    );
+    let color = extension_color(extension, "diffuseTransmissionColorFactor", Color::ONE);
+    let color = match load_extension_texture(
+        document,
+        extension,
+        "diffuseTransmissionColorTexture",
+        images,
+        ColorSpace::Srgb,
+    )? {
+        Some(texture) => texture,
+        None => match &*base_color {
+            Texture::SolidColor(base) => Texture::solid_color(base.albedo * color),
+            _ => base_color,
+        },
+    };
+
+    Ok(Some((factor, color)))
+}
+
+/// The proposed glTF extension for scattering inside of volumes.
+const VOLUME_SCATTER: &str = "KHR_materials_volume_scatter";
+
+/// A subsurface scattering material for glTF materials using `KHR_materials_volume_scatter`,
+/// where `multiscatterColor` is the albedo and the mean free path follows from the extinction
+/// given by the attenuation of `KHR_materials_volume`, one scene unit if there is none.
+fn read_subsurface(
+    material: &gltf::Material,
+    base_color: Arc<Texture>,
+    roughness: ScalarTexture,
+) -> Option<Arc<Material>> {
+    let extension = material.extension_value(VOLUME_SCATTER)?;
+    let albedo = match extension.get("multiscatterColor") {
+        Some(_) => {
+            Texture::solid_color(extension_color(extension, "multiscatterColor", Color::ONE))
+        }
+        None => base_color,
+    };
+    let mean_free_path = material
+        .volume()
+        .filter(|volume| volume.attenuation_distance().is_finite())
+        .map_or(Color::ONE, |volume| {
+            // an attenuation colour c after a distance d means an extinction of -ln(c) / d
+            let color = volume
+                .attenuation_color()
+                .map(|c| c.clamp(1e-4, 1.0 - 1e-4));
+            Color::from(color.map(|c| -volume.attenuation_distance() / c.ln()))
+        });
+
+    Some(Material::subsurface(
+        albedo,
+        mean_free_path,
+        extension_factor(extension, "scatterAnisotropy", 0.0),
+        material.ior().unwrap_or(1.5),
+        roughness,
+    ))
+}
+
+/// The transmissive part of a glTF material using `KHR_materials_transmission`, along with its
+/// `KHR_materials_ior` and `KHR_materials_volume` settings.
+fn read_transmission_material(
+    material: &gltf::Material,
+    tint: Arc<Texture>,
+    roughness: ScalarTexture,
+) -> Arc<Material> {
+    // a thickness of zero marks thin-walled geometry, which has no interior to absorb light
+    let absorption = material
+        .volume()
+        .filter(|v| v.thickness_factor() > 0.0 && v.attenuation_distance().is_finite())
+        .map(|volume| Absorption {
+            color: Vec3::from(volume.attenuation_color()),
+            distance: volume.attenuation_distance(),
+        });
+
+    let ior = material.ior().unwrap_or(1.5).into();
+    // without a volume the surface is thin-walled, so rays pass through it without bending
+    if material
+        .volume()
+        .is_none_or(|v| v.thickness_factor() <= 0.0)
+    {
+        return Arc::new(Material::ThinDielectric(ThinDielectric {
+            ior,
+            roughness,
+            tint: Some(tint),
+        }));
+    }
+
+    Arc::new(Material::Dielectric(Dielectric {
+        ior,
+        roughness,
+        absorption,
+        tint: Some(tint),
+    }))
+}
+
+/// Reads the procedural base colour described under `texture` in the extras of a material, as
+///
+/// `{"pattern": "marble", "scale": s, "seed": n, "low": [r, g, b], "high": [r, g, b]}`
+///
+/// where `pattern` is one of `noise`, `fbm` and `turbulence` with `octaves`, `marble` with
+/// `frequency` and `distortion`, `wood` with `rings` and `distortion`, or `voronoi`. The pattern
+/// blends from `low` to `high`, or is mapped through a colour ramp given as
+/// `"ramp": [[position, [r, g, b]], ...]` instead. It's evaluated in the space of the mesh, placed
+/// by `transform`, and scaled by `scale`.
+fn read_procedural(
+    extras: &gltf::json::Extras,
+    transform: Affine3A,
+) -> Result<Option<Arc<Texture>>> {
+    let Some(extras) = extras else {
+        return Ok(None);
+    };
+    let extras: Value = gltf::json::deserialize::from_str(extras.get())?;
+    let Some(texture) = extras.get("texture") else {
+        return Ok(None);
+    };
+
+    let octaves = texture
+        .get("octaves")
+        .and_then(Value::as_u64)
+        .map_or(4, |v| v as u32);
+    let distortion = extension_factor(texture, "distortion", 1.0);
+    let pattern = match texture.get("pattern").and_then(Value::as_str) {
+        Some("noise") => Pattern::Noise,
+        Some("fbm") => Pattern::Fbm { octaves },
+        Some("turbulence") => Pattern::Turbulence { octaves },
+        Some("marble") => Pattern::Marble {
+            frequency: extension_factor(texture, "frequency", 1.0),
+            distortion,
+        },
+        Some("wood") => Pattern::Wood {
+            rings: extension_factor(texture, "rings", 4.0),
+            distortion,
+        },
+        Some("voronoi") => Pattern::Voronoi,
+        Some(other) => return Err(eyre!("unknown texture pattern {other}")),
+        None => return Err(eyre!("procedural texture without a pattern")),
+    };
+
+    let ramp: Option<Vec<(f32, [f32; 3])>> = texture
+        .get("ramp")
+        .map(|v| gltf::json::deserialize::from_value(v.clone()))
+        .transpose()?;
+    let (low, high) = match ramp {
+        Some(_) => (Color::ZERO, Color::ONE),
+        None => (
+            extension_color(texture, "low", Color::ZERO),
+            extension_color(texture, "high", Color::ONE),
+        ),
+    };
+
+    let scale = extension_factor(texture, "scale", 1.0);
+    let procedural = Procedural::new(
+        pattern,
+        scale,
+        Texture::solid_color(low),
+        Texture::solid_color(high),
+    )
+    .with_seed(texture.get("seed").and_then(Value::as_u64).unwrap_or(0))
+    .with_transform(Affine3A::from_scale(Vec3::splat(scale).into()) * transform.inverse());
+    let procedural = Texture::procedural(procedural);
+
+    Ok(Some(match ramp {
+        Some(mut stops) => {
+            if stops.is_empty() {
+                return Err(eyre!("colour ramp without stops"));
+            }
+            stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let stops = stops
+                .into_iter()
+                .map(|(position, color)| (position, Texture::solid_color(color.into())))
+                .collect();
+            // a pattern from black to white has the pattern's value as its luminance
+            Texture::color_ramp(procedural, stops)
+        }
+        None => procedural,
+    }))
+}
+
+/// Reads the participating medium described under `medium` in the extras of a node or scene,
+/// placed by `transform`. Media are either
+///
+/// - `{"type": "homogeneous", "sigmaA": [r, g, b], "sigmaS": [r, g, b], "scale": s, "g": g}`
+/// - `{"type": "grid", ..., "resolution": [nx, ny, nz], "density": [...], "bounds": [min, max]}`
+/// - `{"type": "grid", ..., "file": "smoke.vol", "bounds": [min, max]}`
+///
+/// where the coefficients are per scene unit and multiplied by `scale`, and the density grid
+/// of a `grid` medium fills `bounds` in the node's space. Grids loaded from a file, relative to
+/// `directory`, default to the bounds stored in the file, inline ones to the unit cube.
+fn read_medium(
+    extras: &gltf::json::Extras,
+    transform: Affine3A,
+    directory: &Path,
+) -> Result<Option<Arc<Medium>>> {
+    let Some(extras) = extras else {
+        return Ok(None);
+    };
+    let extras: Value = gltf::json::deserialize::from_str(extras.get())?;
+    let Some(medium) = extras.get("medium") else {
+        return Ok(None);
+    };
+
+    let scale = extension_factor(medium, "scale", 1.0);
+    let sigma_a = extension_color(medium, "sigmaA", Color::ZERO) * scale;
+    let sigma_s = extension_color(medium, "sigmaS", Color::ZERO) * scale;
+    let g = extension_factor(medium, "g", 0.0);
+
+    let medium = match medium.get("type").and_then(Value::as_str) {
+        Some("homogeneous") | None => Medium::homogeneous(sigma_a, sigma_s, g),
+        Some("grid") => {
+            let (density, file_bounds) = match medium.get("file").and_then(Value::as_str) {
+                Some(file) => {
+                    let path = directory.join(file);
+                    info!("loading density grid {}", path.display());
+                    let data = volume::load(&path)?;
+                    (data.grid, data.bounds)
+                }
+                None => {
+                    let [nx, ny, nz]: [usize; 3] = medium
+                        .get("resolution")
+                        .map(|v| gltf::json::deserialize::from_value(v.clone()))
+                        .transpose()?
+                        .ok_or_else(|| eyre!("grid medium without a resolution or file"))?;
+                    let density: Vec<f32> = medium
+                        .get("density")
+                        .map(|v| gltf::json::deserialize::from_value(v.clone()))
+                        .transpose()?
+                        .ok_or_else(|| eyre!("grid medium without densities"))?;
+                    if density.len() != volume::voxel_count([nx, ny, nz])? {
+                        return Err(eyre!(
+                            "grid medium has {} densities for a {nx}×{ny}×{nz} grid",
+                            density.len()
+                        ));
+                    }
+                    (DensityGrid::new(nx, ny, nz, density), None)
+                }
+            };
+
+            let bounds: Option<[[f32; 3]; 2]> = medium
+                .get("bounds")
+                .map(|v| gltf::json::deserialize::from_value(v.clone()))
+                .transpose()?;
+            let (min, max) = match bounds {
+                Some([min, max]) => (Vec3::from(min), Vec3::from(max)),
+                None => file_bounds.unwrap_or((Vec3::ZERO, Vec3::ONE)),
+            };
+            let grid_transform = transform
+                * Affine3A::from_scale_rotation_translation(
+                    (max - min).into(),
+                    Default::default(),
+                    min.into(),
+                );
+
+            Arc::new(Medium::Grid(Grid::new(
+                sigma_a,
+                sigma_s,
+                g,
+                density,
+                grid_transform,
+            )))
+        }
+        Some(other) => return Err(eyre!("unknown medium type {other}")),
+    };
+
+    Ok(Some(medium))
+}
+
+/// Reads the animated transforms of all nodes, by node index, merging the channels of every
+/// animation. Morph target weights aren't supported.
+fn read_animations(
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> HashMap<usize, AnimatedTransform> {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut transforms = HashMap::new();
+    for animation in document.animations() {
+        for channel in animation.channels() {
+            let node = channel.target().node();
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(times) = reader.read_inputs() else {
+                continue;
+            };
+            let times: Vec<_> = times.collect();
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+
+            let transform = transforms.entry(node.index()).or_insert_with(|| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                AnimatedTransform::new(scale.into(), Quat::from_array(rotation), translation.into())
+            });
+            match reader.read_outputs() {
+                Some(ReadOutputs::Translations(values)) => {
+                    let values = values.map(Vec3::from).collect();
+                    transform.translation_track = Track::new(interpolation, times, values);
+                }
+                Some(ReadOutputs::Rotations(values)) => {
+                    let values = values.into_f32().map(Quat::from_array).collect();
+                    transform.rotation_track = Track::new(interpolation, times, values);
+                }
+                Some(ReadOutputs::Scales(values)) => {
+                    let values = values.map(Vec3::from).collect();
+                    transform.scale_track = Track::new(interpolation, times, values);
+                }
+                Some(ReadOutputs::MorphTargetWeights(_)) | None => {
+                    warn!(
+                        "ignoring animation channel of node {:?} in {:?}",
+                        node.name(),
+                        animation.name()
+                    );
+                }
+            }
+        }
+    }
+
+    transforms.retain(|_, transform| transform.is_animated());
+    transforms
+}
+
+/// Reads the lens of a camera from the extras of a camera or its node, using the names of
+/// Blender's camera properties: `lens` for the focal length and `sensor_width` in millimetres,
+/// and `focus_distance`, `aperture_fstop`, `aperture_blades` and `aperture_rotation` in radians
+/// for the depth of field.
+fn read_lens(extras: &gltf::json::Extras) -> Result<Lens> {
+    let Some(extras) = extras else {
+        return Ok(Lens::default());
+    };
+    let extras: Value = gltf::json::deserialize::from_str(extras.get())?;
+    let float = |key| extras.get(key).and_then(Value::as_f64).map(|v| v as f32);
+    let positive = |key| match float(key) {
+        Some(value) if value <= 0.0 => Err(eyre!("camera {key} must be positive, got {value}")),
+        value => Ok(value),
+    };
+
+    Ok(Lens {
+        focal_length: positive("lens")?,
+        f_stop: positive("aperture_fstop")?,
+        sensor_width: float("sensor_width"),
+        focus_distance: float("focus_distance"),
+        blades: extras
+            .get("aperture_blades")
+            .and_then(Value::as_u64)
+            .map(|v| v as u32),
+        blade_rotation: float("aperture_rotation"),
+    })
 }
 
 fn read_mesh(
+    index: u32,
+    document: &gltf::Document,
     source_mesh: &gltf::Mesh,
     buffers: &[gltf::buffer::Data],
     images: &[gltf::image::Data],
     transform: Affine3A,
+    medium_interface: Option<MediumInterface>,
 ) -> Result<TriangleMesh> {
     info!("loading mesh {:?}", source_mesh.name());
     let mut vertices = Vec::new();
     let mut face_indices = Vec::new();
     let mut normals = Vec::new();
     let mut uv = Vec::new();
+    let mut uv1 = Vec::new();
     let primitive = source_mesh
         .primitives()
-        .filter(|p| p.mode() == Mode::Triangles)
-        .nth(0)
+        .find(|p| p.mode() == Mode::Triangles)
         .expect("mesh must have at least one triangles primitive");
 
     // for primitive in source_mesh.primitives() {
     let reader = primitive.reader(|b| Some(&buffers[b.index()]));
     let material = primitive.material();
     let emissive_factor = Vec3::from(material.emissive_factor());
+    let pbr = material.pbr_metallic_roughness();
 
-    let color_texture =
-        if let Some(texture) = material.pbr_metallic_roughness().base_color_texture() {
-            let idx = texture.texture().source().index();
-            let image = images[idx].clone();
-            let image = load_image(image, texture.texture().name().unwrap_or("<no name>"))?;
-            Arc::new(Texture::Image(Image::new(image)))
-        } else {
-            let color = material.pbr_metallic_roughness().base_color_factor();
-            Arc::new(Texture::SolidColor(SolidColor {
-                albedo: Vec3::new(color[0], color[1], color[2]),
-            }))
-        };
+    let color_texture = if let Some(texture) = read_procedural(material.extras(), transform)? {
+        texture
+    } else if let Some(texture) = pbr.base_color_texture() {
+        Arc::new(Texture::Image(load_texture(
+            &texture,
+            images,
+            ColorSpace::Srgb,
+        )?))
+    } else {
+        let color = pbr.base_color_factor();
+        Arc::new(Texture::SolidColor(SolidColor {
+            albedo: Vec3::new(color[0], color[1], color[2]),
+        }))
+    };
+
+    let alpha_mode = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Mask {
+            cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        },
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+    };
+    let alpha = pbr.base_color_factor()[3];
+    let alpha_texture = matches!(*color_texture, Texture::Image(_)).then(|| color_texture.clone());
+    // closed volumes are entered through their front faces and left through their back faces
+    let bounds_medium = medium_interface
+        .as_ref()
+        .is_some_and(|interface| interface.inside.is_some());
+    let double_sided = material.double_sided()
+        || material.volume().is_some()
+        || material.extension_value(VOLUME_SCATTER).is_some()
+        || bounds_medium;
 
     // TODO actual PBR shader
-    let material = if emissive_factor != Vec3::ZERO {
-        Arc::new(Material::DiffuseLight(DiffuseLight {
-            texture: Texture::solid_color(Vec3::ONE),
-            strength: material.emissive_strength().unwrap_or(1.0),
-        }))
+    let material = if material.index().is_none() && bounds_medium {
+        // a medium bounded by a mesh without a material of its own
+        Material::interface()
+    } else if emissive_factor != Vec3::ZERO {
+        let texture = match material.emissive_texture() {
+            Some(texture) => Texture::scaled(
+                Arc::new(Texture::Image(load_texture(
+                    &texture,
+                    images,
+                    ColorSpace::Srgb,
+                )?)),
+                emissive_factor,
+            ),
+            None => Texture::solid_color(emissive_factor),
+        };
+        Material::diffuse_light(texture, material.emissive_strength().unwrap_or(1.0))
     } else {
-        Material::lambertian_texture(color_texture)
+        let metallic_roughness =
+            load_optional_texture(pbr.metallic_roughness_texture(), images, ColorSpace::Linear)?;
+        // metalness is stored in the blue channel, roughness in the green one
+        let roughness = read_scalar(
+            metallic_roughness.clone(),
+            Channel::Green,
+            pbr.roughness_factor(),
+        );
+
+        let mut dielectric = read_subsurface(&material, color_texture.clone(), roughness.clone())
+            .unwrap_or_else(|| Material::lambertian_texture(color_texture.clone()));
+        if let Some((factor, color)) =
+            read_diffuse_transmission(document, &material, color_texture.clone(), images)?
+        {
+            let translucent =
+                Material::diffuse_transmission(Texture::solid_color(Color::ZERO), color);
+            dielectric = Material::mix(dielectric, translucent, factor);
+        }
+        if let Some(coat) = read_specular_coat(&material, roughness.clone(), images)? {
+            dielectric = Material::layered(dielectric, Some(coat), None);
+        }
+        let surface = Material::metallic_roughness(
+            dielectric,
+            color_texture.clone(),
+            read_scalar(metallic_roughness, Channel::Blue, pbr.metallic_factor()),
+            roughness.clone(),
+        );
+
+        let surface = match material.transmission() {
+            Some(transmission) => {
+                let factor = read_scalar(
+                    load_optional_texture(
+                        transmission.transmission_texture(),
+                        images,
+                        ColorSpace::Linear,
+                    )?,
+                    Channel::Red,
+                    transmission.transmission_factor(),
+                );
+                let dielectric = read_transmission_material(&material, color_texture, roughness);
+                Material::mix(surface, dielectric, factor)
+            }
+            None => surface,
+        };
+
+        let clearcoat = read_clearcoat(document, &material, images)?;
+        let sheen = read_sheen(document, &material, images)?;
+        if clearcoat.is_some() || sheen.is_some() {
+            Material::layered(surface, clearcoat, sheen)
+        } else {
+            surface
+        }
     };
 
     if let Some(positions) = reader.read_positions() {
@@ -175,16 +1143,16 @@ fn read_mesh(
     }
 
     if let Some(normals_iter) = reader.read_normals() {
-        normals.extend(normals_iter.map(|n| Vec3::from(n)));
+        normals.extend(normals_iter.map(Vec3::from));
     }
 
     if let Some(tex_coords) = reader.read_tex_coords(0) {
         let tex_coords: Vec<_> = tex_coords.into_f32().collect();
-        uv.extend(
-            tex_coords
-                .into_iter()
-                .map(|uv| TextureCoordinates::from_array(uv)),
-        )
+        uv.extend(tex_coords.into_iter().map(TextureCoordinates::from_array))
+    }
+
+    if let Some(tex_coords) = reader.read_tex_coords(1) {
+        uv1.extend(tex_coords.into_f32().map(TextureCoordinates::from_array))
     }
     info!(
         "loaded mesh {} with {} vertices, {} faces, {} normals and {} texture coordinates",
@@ -197,54 +1165,247 @@ fn read_mesh(
     info!("assigned material {material:#?}");
     // }
     Ok(TriangleMesh::new(
+        index,
         vertices,
         face_indices,
         normals,
         uv,
-        material,
+        uv1,
+        Surface {
+            material,
+            alpha_mode,
+            alpha,
+            alpha_texture,
+            double_sided,
+            mirrored: transform.matrix3.determinant() < 0.0,
+            medium_interface,
+        },
     ))
 }
 
 pub fn load_from_gltf(path: impl AsRef<Path>) -> Result<SceneDescription> {
+    let path = path.as_ref();
     let (gltf, buffers, images) = gltf::import(path)?;
+    let directory = path.parent().unwrap_or(Path::new("."));
+    let source_hash = bvh_cache::scene_hash(&std::fs::read(path)?, &buffers);
     let mut meshes = Vec::new();
     let mut cameras = vec![];
 
+    // the medium filling the scene, which the cameras and all other media are placed in
+    let scene_medium = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => read_medium(scene.extras(), Affine3A::IDENTITY, directory)?,
+        None => None,
+    };
+
+    let mut animations = read_animations(&gltf, &buffers);
+    let shutter = CameraSettings::default().shutter;
+    let mut objects = Vec::new();
+
     // TODO this would have to walk the entire scene graph
     for node in gltf.nodes() {
         let matrix = Mat4::from_cols_array_2d(&node.transform().matrix());
         let transform = Affine3A::from_mat4(matrix);
+        let medium = read_medium(node.extras(), transform, directory)?;
+        let motion = animations.remove(&node.index()).map(Arc::new);
 
         if let Some(mesh) = node.mesh() {
-            let mesh = read_mesh(&mesh, &buffers, &images, transform)?;
+            // meshes without a medium of their own still let rays back out into the scene's
+            let medium_interface =
+                (medium.is_some() || scene_medium.is_some()).then(|| MediumInterface {
+                    inside: medium.clone(),
+                    outside: scene_medium.clone(),
+                });
+            let mesh = read_mesh(
+                meshes.len() as u32,
+                &gltf,
+                &mesh,
+                &buffers,
+                &images,
+                // moving meshes are kept in object space, where rays are moved to
+                if motion.is_some() {
+                    Affine3A::IDENTITY
+                } else {
+                    transform
+                },
+                medium_interface,
+            )?;
+            match &motion {
+                Some(motion) => objects.extend(
+                    MovingMesh::new(&mesh, motion.clone(), shutter).map(Object::MovingMesh),
+                ),
+                None => objects.extend(mesh.faces().map(Object::TriangleRef)),
+            }
             meshes.push(mesh);
         }
 
         if let Some(camera) = node.camera() {
-            if let Projection::Perspective(projection) = camera.projection() {
-                cameras.push(CameraSettings {
-                    name: camera.name().map(From::from),
-                    y_fov: projection.yfov(),
-                    z_near: projection.znear(),
-                    z_far: projection.zfar().unwrap_or(f32::INFINITY),
-                    transform,
-                    focus_dist: 10.0,
-                    defocus_angle: 0.0,
-                });
-            }
+            let (projection, aspect_ratio, z_near, z_far) = match camera.projection() {
+                Projection::Perspective(projection) => (
+                    CameraProjection::Perspective {
+                        y_fov: projection.yfov(),
+                    },
+                    projection.aspect_ratio(),
+                    projection.znear(),
+                    projection.zfar().unwrap_or(f32::INFINITY),
+                ),
+                Projection::Orthographic(projection) => (
+                    CameraProjection::Orthographic {
+                        x_mag: projection.xmag(),
+                        y_mag: projection.ymag(),
+                    },
+                    None,
+                    projection.znear(),
+                    projection.zfar(),
+                ),
+            };
+            cameras.push(CameraSettings {
+                name: camera.name().map(From::from),
+                projection,
+                aspect_ratio,
+                aspect_fit: AspectFit::default(),
+                z_near,
+                z_far,
+                transform,
+                lens: read_lens(camera.extras())?.overridden_by(read_lens(node.extras())?),
+                medium: medium.or_else(|| scene_medium.clone()),
+                shutter: CameraSettings::default().shutter,
+                motion,
+            });
         }
     }
 
-    let objects = meshes
-        .into_iter()
-        .flat_map(|m| m.faces().collect::<Vec<_>>())
-        .map(|f| Object::TriangleRef(f))
-        .collect();
-
     debug!("cameras: {cameras:#?}");
 
     Ok(SceneDescription {
         root_object: Object::World(World::new(objects)),
         cameras,
+        meshes,
+        source_hash,
+        shutter,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use glam::{Affine3A, Quat};
+
+    use super::{read_lens, read_procedural, CameraSettings, CropWindow, RenderSettings};
+    use crate::animation::{AnimatedTransform, Interpolation, Track};
+    use crate::material::Material;
+    use crate::object::Sphere;
+    use crate::range::Range;
+    use crate::texture::{HasColorValue, Texture, TextureCoordinates};
+    use crate::vec3::{Color, Point3, Vec3};
+    use crate::Result;
+
+    #[test]
+    fn test_render_region() {
+        let settings = RenderSettings {
+            image_width: 100,
+            image_height: 50,
+            selected_camera: 0,
+            max_depth: 1,
+            samples_per_pixel: 1,
+            background_color: Color::ZERO,
+            spectral: false,
+            crop: None,
+            debug_pixel: Some((7, 8)),
+        };
+        assert_eq!(settings.region(), "7,8,8,9".parse().unwrap());
+        assert!(settings.validate().is_ok());
+        let outside = RenderSettings {
+            debug_pixel: Some((100, 8)),
+            ..settings.clone()
+        };
+        assert!(outside.validate().is_err());
+
+        let crop: CropWindow = "90, 40, 100, 50".parse().unwrap();
+        let cropped = RenderSettings {
+            crop: Some(crop),
+            ..settings
+        };
+        assert!(cropped.validate().is_err());
+        let region = cropped.region();
+        assert_eq!((region.width(), region.height()), (10, 10));
+        assert_eq!(region.pixel(11), (91, 41));
+        let oversized = RenderSettings {
+            crop: Some("90, 40, 120, 60".parse().unwrap()),
+            debug_pixel: None,
+            ..cropped.clone()
+        };
+        assert!(oversized.validate().is_err());
+        let fitting = RenderSettings {
+            debug_pixel: None,
+            ..cropped
+        };
+        assert!(fitting.validate().is_ok());
+        assert!("4,4,2,8".parse::<CropWindow>().is_err());
+    }
+
+    #[test]
+    fn test_read_procedural_texture() -> Result<()> {
+        let extras = Some(gltf::json::deserialize::from_str(
+            r#"{"texture": {"pattern": "voronoi", "scale": 2.0, "ramp": [[1.0, [0, 0, 1]], [0.0, [1, 0, 0]]]}}"#,
+        )?);
+        let texture = read_procedural(&extras, Affine3A::IDENTITY)?.expect("texture in extras");
+        let Texture::ColorRamp(_) = &*texture else {
+            panic!("expected a colour ramp, got {texture:?}");
+        };
+        // every lookup blends between the red and blue stops
+        let color = texture.value_at(
+            TextureCoordinates::new(0.0, 0.0),
+            Point3::new(0.3, 0.1, 0.7),
+        );
+        assert!((color.x + color.z - 1.0).abs() < 1e-5 && color.y == 0.0);
+
+        let extras = Some(gltf::json::deserialize::from_str(
+            r#"{"texture": {"pattern": "plaid"}}"#,
+        )?);
+        assert!(read_procedural(&extras, Affine3A::IDENTITY).is_err());
+        assert!(read_procedural(&None, Affine3A::IDENTITY)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_lens() -> Result<()> {
+        let extras = Some(gltf::json::deserialize::from_str(
+            r#"{"lens": 50.0, "aperture_fstop": 2.8, "aperture_blades": 6}"#,
+        )?);
+        let lens = read_lens(&extras)?;
+        assert_eq!((lens.focal_length, lens.f_stop), (Some(50.0), Some(2.8)));
+        assert_eq!(lens.blades, Some(6));
+
+        for extras in [r#"{"lens": 0.0}"#, r#"{"aperture_fstop": -2.0}"#] {
+            let extras = Some(gltf::json::deserialize::from_str(extras)?);
+            assert!(read_lens(&extras).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_autofocus_follows_camera_motion() {
+        let world = Sphere::new(
+            Point3::new(0.0, 0.0, -10.0),
+            1.0,
+            Material::lambertian(Color::ONE),
+        );
+        let mut motion = AnimatedTransform::new(Vec3::ONE, Quat::IDENTITY, Vec3::ZERO);
+        motion.translation_track = Track::new(
+            Interpolation::Linear,
+            vec![0.0, 1.0],
+            vec![Vec3::ZERO, Vec3::new(0.0, 0.0, -5.0)],
+        );
+        let mut settings = CameraSettings {
+            shutter: Range::new(1.0, 2.0),
+            motion: Some(Arc::new(motion)),
+            ..CameraSettings::default()
+        };
+
+        // the camera has moved halfway to the sphere by the time the shutter opens
+        let distance = settings.autofocus(&world).unwrap();
+        assert!((distance - 4.0).abs() < 1e-4, "focused at {distance}");
+        assert_eq!(settings.lens.focus_distance, Some(distance));
+    }
+}
@@ -0,0 +1,515 @@
+//! Spectral rendering support: hero wavelength sampling, upsampling of RGB colours to smooth
+//! spectra with the sigmoid-polynomial model of Jakob and Hanika (2019), and conversion of the
+//! sampled spectra through CIE XYZ to linear sRGB.
+
+use std::fmt::Debug;
+use std::ops::{AddAssign, Div, Mul, MulAssign};
+use std::sync::OnceLock;
+
+use glam::{DMat3, DVec3, Mat3, Vec4};
+use rayon::prelude::*;
+use tracing::info;
+
+use crate::vec3::{Color, Vec3, Vec3Ext};
+
+pub const LAMBDA_MIN: f32 = 360.0;
+pub const LAMBDA_MAX: f32 = 830.0;
+
+/// Number of wavelengths traced together along each camera path.
+pub const SAMPLE_COUNT: usize = 4;
+
+/// Resolution of the RGB to spectrum coefficient table along each axis.
+const TABLE_RES: usize = 24;
+/// Spacing of the wavelengths used to fit the coefficient table, in nanometres.
+const FIT_STEP: f32 = 5.0;
+const FIT_ITERATIONS: usize = 50;
+
+/// Values of a spectral distribution at the wavelengths of a [`SampledWavelengths`].
+pub type SampledSpectrum = Vec4;
+
+/// How the renderer represents radiance along a path: either RGB triples or spectral samples.
+/// Materials and textures always produce RGB, which the model lifts into its own representation.
+pub trait ColorModel {
+    type Value: Copy
+        + Debug
+        + PartialEq
+        + Mul<Output = Self::Value>
+        + MulAssign
+        + AddAssign
+        + Div<f32, Output = Self::Value>;
+
+    const ZERO: Self::Value;
+    const ONE: Self::Value;
+
+    /// An emitted RGB radiance.
+    fn illuminant(&self, rgb: Color) -> Self::Value;
+
+    /// A scattering weight, such as a reflectance multiplied by the cosine term.
+    fn albedo(&self, rgb: Color) -> Self::Value;
+
+    /// The wavelength that wavelength dependent materials should use, if the model has one.
+    fn hero_wavelength(&self) -> Option<f32> {
+        None
+    }
+
+    /// Weight that drops all but the hero wavelength after a wavelength dependent scattering
+    /// event, since the others would have been scattered differently.
+    fn terminate_secondary(&self) -> Self::Value {
+        Self::ONE
+    }
+}
+
+/// Plain RGB rendering, where every colour is used as is.
+#[derive(Debug, Clone, Copy)]
+pub struct Rgb;
+
+impl ColorModel for Rgb {
+    type Value = Color;
+
+    const ZERO: Color = Color::ZERO;
+    const ONE: Color = Color::ONE;
+
+    fn illuminant(&self, rgb: Color) -> Color {
+        rgb
+    }
+
+    fn albedo(&self, rgb: Color) -> Color {
+        rgb
+    }
+}
+
+/// The wavelengths carried by one camera path, with the density they were sampled with.
+#[derive(Debug, Clone, Copy)]
+pub struct SampledWavelengths {
+    lambda: Vec4,
+    pdf: Vec4,
+}
+
+impl SampledWavelengths {
+    /// Picks a hero wavelength from `u` and spaces the others evenly after it, importance
+    /// sampling the part of the spectrum the eye is most sensitive to.
+    pub fn sample_visible(u: f32) -> Self {
+        let mut lambda = [0.0; SAMPLE_COUNT];
+        let mut pdf = [0.0; SAMPLE_COUNT];
+        for i in 0..SAMPLE_COUNT {
+            let u = (u + i as f32 / SAMPLE_COUNT as f32).fract();
+            lambda[i] = sample_visible_wavelength(u);
+            pdf[i] = visible_wavelength_pdf(lambda[i]);
+        }
+
+        SampledWavelengths {
+            lambda: Vec4::from_array(lambda),
+            pdf: Vec4::from_array(pdf),
+        }
+    }
+
+    pub fn lambda(&self) -> Vec4 {
+        self.lambda
+    }
+
+    /// Monte Carlo estimate of the XYZ colour of a spectrum sampled at these wavelengths.
+    pub fn to_xyz(self, spectrum: SampledSpectrum) -> Vec3 {
+        let mut xyz = Vec3::ZERO;
+        for i in 0..SAMPLE_COUNT {
+            if self.pdf[i] > 0.0 {
+                xyz += cie_xyz(self.lambda[i]) * spectrum[i] / self.pdf[i];
+            }
+        }
+
+        xyz / SAMPLE_COUNT as f32
+    }
+
+    pub fn to_rgb(self, spectrum: SampledSpectrum) -> Color {
+        tables().xyz_to_rgb.mul_vec3a(self.to_xyz(spectrum))
+    }
+
+    /// Evaluates the smooth spectrum of a reflectance in `[0, 1]`.
+    pub fn reflectance(&self, rgb: Color) -> SampledSpectrum {
+        self.evaluate(tables().polynomial(rgb.clamp(Vec3::ZERO, Vec3::ONE)))
+    }
+
+    /// Like [`SampledWavelengths::reflectance`], but for colours that may exceed one.
+    pub fn unbounded(&self, rgb: Color) -> SampledSpectrum {
+        let rgb = rgb.max(Vec3::ZERO);
+        let max = rgb.max_element();
+        if max <= 1.0 {
+            return self.reflectance(rgb);
+        }
+
+        let scale = 2.0 * max;
+        self.reflectance(rgb / scale) * scale
+    }
+
+    fn evaluate(&self, polynomial: SigmoidPolynomial) -> SampledSpectrum {
+        Vec4::from_array(
+            self.lambda
+                .to_array()
+                .map(|lambda| polynomial.evaluate(lambda)),
+        )
+    }
+}
+
+impl ColorModel for SampledWavelengths {
+    type Value = SampledSpectrum;
+
+    const ZERO: SampledSpectrum = Vec4::ZERO;
+    const ONE: SampledSpectrum = Vec4::ONE;
+
+    /// Emission is upsampled relative to the white point of sRGB, so that `(1, 1, 1)` becomes
+    /// the D65 illuminant.
+    fn illuminant(&self, rgb: Color) -> SampledSpectrum {
+        let scale = tables().d65_scale;
+        let d65 = Vec4::from_array(self.lambda.to_array().map(|lambda| d65(lambda) * scale));
+        self.unbounded(rgb) * d65
+    }
+
+    fn albedo(&self, rgb: Color) -> SampledSpectrum {
+        self.unbounded(rgb)
+    }
+
+    fn hero_wavelength(&self) -> Option<f32> {
+        Some(self.lambda.x)
+    }
+
+    /// The hero wavelength keeps its share of the estimate, now weighted as if it had been
+    /// sampled alone.
+    fn terminate_secondary(&self) -> SampledSpectrum {
+        Vec4::new(SAMPLE_COUNT as f32, 0.0, 0.0, 0.0)
+    }
+}
+
+/// `538 - 138.9 atanh(0.857 - 1.83 u)`, inverting the density of
+/// [`visible_wavelength_pdf`]. From pbrt-v4.
+fn sample_visible_wavelength(u: f32) -> f32 {
+    538.0 - 138.888_89 * (0.856_910_6 - 1.827_502 * u).atanh()
+}
+
+fn visible_wavelength_pdf(lambda: f32) -> f32 {
+    if !(LAMBDA_MIN..=LAMBDA_MAX).contains(&lambda) {
+        return 0.0;
+    }
+
+    0.003_939_804 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+/// The CIE 1931 2° colour matching functions, using the multi-lobe Gaussian fit of Wyman, Sloan
+/// and Shirley (2013).
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    let g = |mu: f32, sigma_low: f32, sigma_high: f32| {
+        let sigma = if lambda < mu { sigma_low } else { sigma_high };
+        let t = (lambda - mu) / sigma;
+        (-0.5 * t * t).exp()
+    };
+
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Relative spectral power of CIE standard illuminant D65 from 300 to 830 nm in 10 nm steps.
+const D65: [f32; 54] = [
+    0.0341, 3.2945, 20.236, 37.0535, 39.9488, 44.9117, 46.6383, 52.0891, 49.9755, 54.6482, 82.7549,
+    91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861, 115.923, 108.811, 109.354,
+    107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342, 95.788, 88.6856, 90.0062, 89.5991,
+    87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842, 69.7213, 71.6091, 74.349,
+    61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828, 64.304, 59.4519, 51.959, 57.4406,
+    60.3125,
+];
+
+fn d65(lambda: f32) -> f32 {
+    let x = (lambda - 300.0) / 10.0;
+    if x < 0.0 || x > (D65.len() - 1) as f32 {
+        return 0.0;
+    }
+
+    let i = (x as usize).min(D65.len() - 2);
+    let t = x - i as f32;
+    D65[i] * (1.0 - t) + D65[i + 1] * t
+}
+
+/// A smooth spectrum `s(λ) = σ(c₀t² + c₁t + c₂)`, where `t` maps the visible range to `[0, 1]`
+/// and `σ` is a sigmoid, so the spectrum always stays in `[0, 1]`.
+#[derive(Debug, Clone, Copy)]
+struct SigmoidPolynomial {
+    coefficients: Vec3,
+}
+
+impl SigmoidPolynomial {
+    /// A flat spectrum of `value`.
+    fn constant(value: f32) -> Self {
+        let c2 = if value <= 0.0 {
+            f32::NEG_INFINITY
+        } else if value >= 1.0 {
+            f32::INFINITY
+        } else {
+            (value - 0.5) / (value * (1.0 - value)).sqrt()
+        };
+        SigmoidPolynomial {
+            coefficients: Vec3::new(0.0, 0.0, c2),
+        }
+    }
+
+    fn evaluate(&self, lambda: f32) -> f32 {
+        let t = (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+        let c = self.coefficients;
+        let x = if c.x == 0.0 && c.y == 0.0 {
+            c.z
+        } else {
+            (c.x * t + c.y) * t + c.z
+        };
+        if x.is_infinite() {
+            return if x > 0.0 { 1.0 } else { 0.0 };
+        }
+
+        0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+    }
+}
+
+/// Data shared by all spectral renders, built on first use.
+struct Tables {
+    /// Normalisation of the D65 table so that its luminance is one.
+    d65_scale: f32,
+    /// XYZ to linear sRGB, balanced so that D65 maps exactly to white under the fitted matching
+    /// functions.
+    xyz_to_rgb: Mat3,
+    /// Brightness of each table slice, denser near black and white.
+    z_nodes: Vec<f32>,
+    /// Sigmoid polynomial coefficients, indexed by the largest RGB channel, then the two other
+    /// channels relative to it, then the brightness.
+    coefficients: Vec<[f32; 3]>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(Tables::build)
+}
+
+/// The standard XYZ to linear sRGB matrix.
+const XYZ_TO_SRGB: Mat3 = Mat3::from_cols_array(&[
+    3.240_454_2,
+    -0.969_266,
+    0.055_643_4,
+    -1.537_138_5,
+    1.876_010_8,
+    -0.204_025_9,
+    -0.498_531_4,
+    0.041_556,
+    1.057_225_2,
+]);
+
+impl Tables {
+    fn build() -> Self {
+        let wavelengths = |step: f32| {
+            let count = ((LAMBDA_MAX - LAMBDA_MIN) / step) as usize + 1;
+            (0..count).map(move |i| LAMBDA_MIN + i as f32 * step)
+        };
+
+        let d65_luminance: f32 = wavelengths(1.0).map(|l| d65(l) * cie_xyz(l).y).sum();
+        let d65_scale = 1.0 / d65_luminance;
+        let white: Vec3 = wavelengths(1.0)
+            .map(|l| cie_xyz(l) * d65(l) * d65_scale)
+            .sum();
+        let white_rgb = XYZ_TO_SRGB.mul_vec3a(white);
+        let xyz_to_rgb = Mat3::from_diagonal((Vec3::ONE / white_rgb).into()) * XYZ_TO_SRGB;
+
+        // RGB contributed by each fitting wavelength when the spectrum is one there
+        let basis: Vec<(f64, DVec3)> = wavelengths(FIT_STEP)
+            .map(|l| {
+                let t = (l - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN);
+                let rgb = xyz_to_rgb.mul_vec3a(cie_xyz(l) * d65(l) * d65_scale * FIT_STEP);
+                (t as f64, rgb.as_dvec3())
+            })
+            .collect();
+
+        let smoothstep = |x: f32| x * x * (3.0 - 2.0 * x);
+        let z_nodes: Vec<f32> = (0..TABLE_RES)
+            .map(|k| smoothstep(smoothstep(k as f32 / (TABLE_RES - 1) as f32)))
+            .collect();
+
+        let start = std::time::Instant::now();
+        let coefficients = (0..3 * TABLE_RES * TABLE_RES)
+            .into_par_iter()
+            .flat_map_iter(|column| {
+                let channel = column / (TABLE_RES * TABLE_RES);
+                let y = ((column / TABLE_RES) % TABLE_RES) as f32 / (TABLE_RES - 1) as f32;
+                let x = (column % TABLE_RES) as f32 / (TABLE_RES - 1) as f32;
+                let target = |z: f32| {
+                    let mut rgb = DVec3::ZERO;
+                    rgb[channel] = z as f64;
+                    rgb[(channel + 1) % 3] = (x * z) as f64;
+                    rgb[(channel + 2) % 3] = (y * z) as f64;
+                    rgb
+                };
+
+                // fit outwards from a medium brightness, starting each fit from its neighbour
+                let mut column = vec![DVec3::ZERO; TABLE_RES];
+                let middle = TABLE_RES / 5;
+                let mut c = DVec3::ZERO;
+                for z in middle..TABLE_RES {
+                    c = fit(target(z_nodes[z]), &basis, c);
+                    column[z] = c;
+                }
+                c = column[middle];
+                for z in (0..middle).rev() {
+                    c = fit(target(z_nodes[z]), &basis, c);
+                    column[z] = c;
+                }
+
+                column.into_iter().map(|c| c.as_vec3().to_array())
+            })
+            .collect();
+        info!("fitted RGB to spectrum table in {:?}", start.elapsed());
+
+        Tables {
+            d65_scale,
+            xyz_to_rgb,
+            z_nodes,
+            coefficients,
+        }
+    }
+
+    fn coefficient(&self, channel: usize, y: usize, x: usize, z: usize) -> Vec3 {
+        let index = ((channel * TABLE_RES + y) * TABLE_RES + x) * TABLE_RES + z;
+        Vec3::from_array(self.coefficients[index])
+    }
+
+    /// Interpolates the table for an RGB colour in `[0, 1]`.
+    fn polynomial(&self, rgb: Color) -> SigmoidPolynomial {
+        if rgb.x == rgb.y && rgb.y == rgb.z {
+            return SigmoidPolynomial::constant(rgb.x);
+        }
+
+        let channel = rgb.max_dimension();
+        let z = rgb[channel];
+        let scale = (TABLE_RES - 1) as f32 / z;
+        let x = rgb[(channel + 1) % 3] * scale;
+        let y = rgb[(channel + 2) % 3] * scale;
+
+        let zi = self
+            .z_nodes
+            .partition_point(|node| *node <= z)
+            .clamp(1, TABLE_RES - 1)
+            - 1;
+        let dz = (z - self.z_nodes[zi]) / (self.z_nodes[zi + 1] - self.z_nodes[zi]);
+        let xi = (x as usize).min(TABLE_RES - 2);
+        let yi = (y as usize).min(TABLE_RES - 2);
+        let (dx, dy) = (x - xi as f32, y - yi as f32);
+
+        let mut coefficients = Vec3::ZERO;
+        for (oy, wy) in [(0, 1.0 - dy), (1, dy)] {
+            for (ox, wx) in [(0, 1.0 - dx), (1, dx)] {
+                for (oz, wz) in [(0, 1.0 - dz), (1, dz)] {
+                    coefficients +=
+                        self.coefficient(channel, yi + oy, xi + ox, zi + oz) * (wx * wy * wz);
+                }
+            }
+        }
+
+        SigmoidPolynomial { coefficients }
+    }
+}
+
+/// Evaluates the RGB colour of the sigmoid polynomial `c` under `basis`, along with its
+/// Jacobian with respect to the coefficients.
+fn project(c: DVec3, basis: &[(f64, DVec3)]) -> (DVec3, DMat3) {
+    let mut rgb = DVec3::ZERO;
+    let mut jacobian = DMat3::ZERO;
+    for &(t, weight) in basis {
+        let x = (c.x * t + c.y) * t + c.z;
+        let root = (1.0 + x * x).sqrt();
+        let ds = 0.5 / (root * root * root);
+        rgb += weight * (0.5 + x / (2.0 * root));
+        jacobian.x_axis += weight * (ds * t * t);
+        jacobian.y_axis += weight * (ds * t);
+        jacobian.z_axis += weight * ds;
+    }
+
+    (rgb, jacobian)
+}
+
+/// Levenberg-Marquardt fit of sigmoid polynomial coefficients reproducing `target` under
+/// `basis`.
+fn fit(target: DVec3, basis: &[(f64, DVec3)], initial: DVec3) -> DVec3 {
+    let mut c = initial;
+    let mut damping: f64 = 1e-3;
+    let (mut rgb, mut jacobian) = project(c, basis);
+    for _ in 0..FIT_ITERATIONS {
+        let residual = rgb - target;
+        if residual.length_squared() < 1e-12 {
+            break;
+        }
+
+        let normal = jacobian.transpose() * jacobian;
+        let gradient = jacobian.transpose() * residual;
+        let diagonal = DVec3::new(normal.x_axis.x, normal.y_axis.y, normal.z_axis.z);
+        let step = (normal + DMat3::from_diagonal(diagonal * damping + 1e-12)).inverse() * gradient;
+        let candidate = c - step;
+        let (candidate_rgb, candidate_jacobian) = project(candidate, basis);
+        if (candidate_rgb - target).length_squared() < residual.length_squared() {
+            c = candidate;
+            (rgb, jacobian) = (candidate_rgb, candidate_jacobian);
+            damping = (damping * 0.1).max(1e-9);
+        } else {
+            damping *= 10.0;
+        }
+    }
+
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{tables, ColorModel, SampledWavelengths, LAMBDA_MAX, LAMBDA_MIN};
+    use crate::vec3::Color;
+
+    /// Integrates the reflectance spectrum of `rgb` under D65 back to RGB.
+    fn round_trip(rgb: Color) -> Color {
+        let tables = tables();
+        let polynomial = tables.polynomial(rgb);
+        let mut xyz = Color::ZERO;
+        let mut lambda = LAMBDA_MIN;
+        while lambda <= LAMBDA_MAX {
+            xyz += super::cie_xyz(lambda)
+                * super::d65(lambda)
+                * tables.d65_scale
+                * polynomial.evaluate(lambda);
+            lambda += 1.0;
+        }
+        tables.xyz_to_rgb.mul_vec3a(xyz)
+    }
+
+    #[test]
+    fn test_rgb_round_trip() {
+        for rgb in [
+            Color::new(0.5, 0.5, 0.5),
+            Color::new(0.8, 0.1, 0.1),
+            Color::new(0.2, 0.6, 0.3),
+            Color::new(0.05, 0.1, 0.7),
+            Color::new(0.9, 0.85, 0.2),
+        ] {
+            let result = round_trip(rgb);
+            assert!(
+                (result - rgb).abs().max_element() < 0.02,
+                "{rgb} came back as {result}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_white_illuminant_estimate() {
+        let count = 4096;
+        let mut sum = Color::ZERO;
+        for i in 0..count {
+            let wavelengths = SampledWavelengths::sample_visible((i as f32 + 0.5) / count as f32);
+            sum += wavelengths.to_rgb(wavelengths.illuminant(Color::ONE));
+        }
+
+        let average = sum / count as f32;
+        assert!(
+            (average - Color::ONE).abs().max_element() < 0.02,
+            "white came back as {average}"
+        );
+    }
+}
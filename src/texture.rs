@@ -3,40 +3,80 @@ use std::path::Path;
 use std::sync::Arc;
 
 use enum_dispatch::enum_dispatch;
-use image::{DynamicImage, GenericImageView, ImageError};
+use glam::Affine3A;
+use image::{DynamicImage, ImageError};
 
+use crate::mipmap::MipMap;
+use crate::noise::Perlin;
 use crate::range::Range;
 use crate::vec3::{Color, Point3, Vec3};
 
+/// Screen-space derivatives of the texture coordinates, describing the footprint of a pixel
+/// on the texture. All zero when the ray that produced the hit carried no differentials.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UvDerivatives {
+    pub dudx: f32,
+    pub dvdx: f32,
+    pub dudy: f32,
+    pub dvdy: f32,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TextureCoordinates {
     pub u: f32,
     pub v: f32,
+    pub derivatives: UvDerivatives,
+    /// The second texture coordinate set (`TEXCOORD_1`), if the mesh has one.
+    pub secondary: Option<(f32, f32)>,
 }
 
 impl TextureCoordinates {
+    pub fn new(u: f32, v: f32) -> Self {
+        TextureCoordinates {
+            u,
+            v,
+            derivatives: UvDerivatives::default(),
+            secondary: None,
+        }
+    }
+
+    /// Selects the texture coordinate set `index`. Only the first two sets are carried on hits,
+    /// other sets fall back to the first one. The derivatives are shared between the sets.
+    pub fn set(self, index: u32) -> Self {
+        match (index, self.secondary) {
+            (1, Some((u, v))) => TextureCoordinates { u, v, ..self },
+            _ => self,
+        }
+    }
+
     pub fn clamp01(self) -> Self {
         TextureCoordinates {
             u: Range::UNIT.clamp(self.u),
             v: Range::UNIT.clamp(self.v),
+            ..self
         }
     }
 
     pub fn from_array(uv: [f32; 2]) -> Self {
-        TextureCoordinates { u: uv[0], v: uv[1] }
+        TextureCoordinates::new(uv[0], uv[1])
     }
 
     pub fn tri_lerp(uv0: Self, uv1: Self, uv2: Self, a: f32, b: f32) -> TextureCoordinates {
-        TextureCoordinates {
-            u: uv0.u * (1.0 - a - b) + uv1.u * a + uv2.u * b,
-            v: uv0.v * (1.0 - a - b) + uv1.v * a + uv2.v * b,
-        }
+        TextureCoordinates::new(
+            uv0.u * (1.0 - a - b) + uv1.u * a + uv2.u * b,
+            uv0.v * (1.0 - a - b) + uv1.v * a + uv2.v * b,
+        )
     }
 }
 
 #[enum_dispatch]
 pub trait HasColorValue: Send + Sync {
     fn value_at(&self, coords: TextureCoordinates, p: Point3) -> Color;
+
+    fn alpha_at(&self, _: TextureCoordinates, _: Point3) -> f32 {
+        // textures are opaque unless they carry an alpha channel
+        1.0
+    }
 }
 
 #[derive(Debug)]
@@ -82,56 +122,354 @@ impl HasColorValue for Checkerboard {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl WrapMode {
+    /// Maps the integer texel coordinate `i` into `[0, size)`.
+    pub fn apply(self, i: i64, size: u32) -> u32 {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+            WrapMode::ClampToEdge => i.clamp(0, size - 1),
+        };
+        i as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    #[default]
+    Linear,
+}
+
+/// How an image texture is sampled, mirroring glTF samplers.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler {
+    pub wrap_s: WrapMode,
+    pub wrap_t: WrapMode,
+    pub filter: FilterMode,
+    /// Whether minified lookups blend between mip levels, or always read the full resolution.
+    pub mipmaps: bool,
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler {
+            wrap_s: WrapMode::Repeat,
+            wrap_t: WrapMode::Repeat,
+            filter: FilterMode::Linear,
+            mipmaps: true,
+        }
+    }
+}
+
+/// Affine transform of texture coordinates, as described by `KHR_texture_transform`: scale,
+/// then rotate counter-clockwise by `rotation` radians, then offset.
+#[derive(Debug, Clone, Copy)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl TextureTransform {
+    pub const IDENTITY: TextureTransform = TextureTransform {
+        offset: [0.0, 0.0],
+        rotation: 0.0,
+        scale: [1.0, 1.0],
+    };
+
+    pub fn apply(&self, coords: TextureCoordinates) -> TextureCoordinates {
+        let (sin, cos) = self.rotation.sin_cos();
+        let [sx, sy] = self.scale;
+        // linear part of the transform, applied to the coordinates and their derivatives alike
+        let transform =
+            |u: f32, v: f32| (cos * sx * u + sin * sy * v, -sin * sx * u + cos * sy * v);
+
+        let (u, v) = transform(coords.u, coords.v);
+        let d = coords.derivatives;
+        let (dudx, dvdx) = transform(d.dudx, d.dvdx);
+        let (dudy, dvdy) = transform(d.dudy, d.dvdy);
+
+        TextureCoordinates {
+            u: u + self.offset[0],
+            v: v + self.offset[1],
+            derivatives: UvDerivatives {
+                dudx,
+                dvdx,
+                dudy,
+                dvdy,
+            },
+            ..coords
+        }
+    }
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        TextureTransform::IDENTITY
+    }
+}
+
+/// Scalar fields in `[0, 1]` that drive procedural textures.
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    /// Plain gradient noise.
+    Noise,
+    Fbm {
+        octaves: u32,
+    },
+    Turbulence {
+        octaves: u32,
+    },
+    /// Sine bands along x, distorted by turbulence.
+    Marble {
+        frequency: f32,
+        distortion: f32,
+    },
+    /// Concentric rings around the y axis, distorted by fBm.
+    Wood {
+        rings: f32,
+        distortion: f32,
+    },
+    /// Distance to the closest cell center of a jittered grid.
+    Voronoi,
+}
+
+impl Pattern {
+    fn evaluate(&self, perlin: &Perlin, p: Point3) -> f32 {
+        let value = match *self {
+            Pattern::Noise => 0.5 * (perlin.noise(p) + 1.0),
+            Pattern::Fbm { octaves } => 0.5 * (perlin.fbm(p, octaves) + 1.0),
+            Pattern::Turbulence { octaves } => perlin.turbulence(p, octaves),
+            Pattern::Marble {
+                frequency,
+                distortion,
+            } => 0.5 * (1.0 + (frequency * p.x + distortion * perlin.turbulence(p, 7)).sin()),
+            Pattern::Wood { rings, distortion } => {
+                let radius = (p.x * p.x + p.z * p.z).sqrt();
+                (rings * radius + distortion * perlin.fbm(p, 4)).rem_euclid(1.0)
+            }
+            Pattern::Voronoi => perlin.voronoi(p),
+        };
+
+        Range::UNIT.clamp(value)
+    }
+}
+
+/// A procedural texture that blends between `low` and `high` by a [`Pattern`] evaluated at the
+/// hit point. `transform` maps world space points into the pattern's space, e.g. the inverse
+/// of an object's transform to evaluate it in object space.
+#[derive(Debug)]
+pub struct Procedural {
+    pattern: Pattern,
+    perlin: Perlin,
+    transform: Affine3A,
+    low: Arc<Texture>,
+    high: Arc<Texture>,
+}
+
+impl Procedural {
+    pub fn new(pattern: Pattern, scale: f32, low: Arc<Texture>, high: Arc<Texture>) -> Self {
+        Procedural {
+            pattern,
+            perlin: Perlin::new(0),
+            transform: Affine3A::from_scale(Vec3::splat(scale).into()),
+            low,
+            high,
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Procedural {
+            perlin: Perlin::new(seed),
+            ..self
+        }
+    }
+
+    pub fn with_transform(self, transform: Affine3A) -> Self {
+        Procedural { transform, ..self }
+    }
+}
+
+impl HasColorValue for Procedural {
+    fn value_at(&self, coords: TextureCoordinates, p: Point3) -> Color {
+        let t = self
+            .pattern
+            .evaluate(&self.perlin, self.transform.transform_point3a(p));
+        self.low.value_at(coords, p) * (1.0 - t) + self.high.value_at(coords, p) * t
+    }
+}
+
+/// Maps the luminance of `input` through a piecewise linear ramp of textures. Stops are
+/// positions in `[0, 1]` and must be sorted.
+#[derive(Debug)]
+pub struct ColorRamp {
+    input: Arc<Texture>,
+    stops: Vec<(f32, Arc<Texture>)>,
+}
+
+impl ColorRamp {
+    pub fn new(input: Arc<Texture>, stops: Vec<(f32, Arc<Texture>)>) -> Self {
+        assert!(!stops.is_empty(), "color ramp needs at least one stop");
+        ColorRamp { input, stops }
+    }
+}
+
+impl HasColorValue for ColorRamp {
+    fn value_at(&self, coords: TextureCoordinates, p: Point3) -> Color {
+        let t = luminance(self.input.value_at(coords, p));
+
+        let upper = self.stops.partition_point(|(position, _)| *position < t);
+        if upper == 0 {
+            return self.stops[0].1.value_at(coords, p);
+        }
+        if upper == self.stops.len() {
+            return self.stops[upper - 1].1.value_at(coords, p);
+        }
+
+        let (p0, ref low) = self.stops[upper - 1];
+        let (p1, ref high) = self.stops[upper];
+        let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 0.0 };
+        low.value_at(coords, p) * (1.0 - f) + high.value_at(coords, p) * f
+    }
+}
+
+/// Another texture multiplied by a constant colour, such as a glTF texture and its factor.
+#[derive(Debug)]
+pub struct Scaled {
+    texture: Arc<Texture>,
+    factor: Color,
+}
+
+impl HasColorValue for Scaled {
+    fn value_at(&self, coords: TextureCoordinates, p: Point3) -> Color {
+        self.texture.value_at(coords, p) * self.factor
+    }
+}
+
+/// The encoding of the values stored in an image texture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB encoded colours, used for base colour and emission textures.
+    #[default]
+    Srgb,
+    /// Linear values, used for data textures like roughness or normal maps.
+    Linear,
+}
+
+impl ColorSpace {
+    /// Decodes a single channel value in `[0, 1]` to linear.
+    pub fn to_linear(self, value: f32) -> f32 {
+        match self {
+            ColorSpace::Linear => value,
+            ColorSpace::Srgb => {
+                if value <= 0.04045 {
+                    value / 12.92
+                } else {
+                    ((value + 0.055) / 1.055).powf(2.4)
+                }
+            }
+        }
+    }
+}
+
+/// An image texture. Texture coordinates follow the glTF convention, with `(0, 0)` being the
+/// top left corner of the image.
 pub struct Image {
-    image: DynamicImage,
+    mipmap: MipMap,
+    sampler: Sampler,
+    transform: TextureTransform,
+    tex_coord: u32,
 }
 
 impl Image {
-    pub fn new(image: DynamicImage) -> Self {
-        Image { image }
+    /// Creates an image texture, decoding the texels to linear values. Floating point images are
+    /// always treated as linear.
+    pub fn new(image: DynamicImage, color_space: ColorSpace) -> Self {
+        let color_space = match image {
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ColorSpace::Linear,
+            _ => color_space,
+        };
+
+        Image {
+            mipmap: MipMap::new(&image, color_space),
+            sampler: Sampler::default(),
+            transform: TextureTransform::IDENTITY,
+            tex_coord: 0,
+        }
+    }
+
+    pub fn with_sampler(self, sampler: Sampler) -> Self {
+        Image { sampler, ..self }
+    }
+
+    /// Sets the texture coordinate set to read and the transform applied to it.
+    pub fn with_tex_coord(self, tex_coord: u32, transform: TextureTransform) -> Self {
+        Image {
+            tex_coord,
+            transform,
+            ..self
+        }
     }
 
     pub fn load(path: impl AsRef<Path>) -> Result<Self, ImageError> {
         let image = image::open(path)?;
-        Ok(Image { image })
+        Ok(Image::new(image, ColorSpace::Srgb))
     }
 }
 
 impl fmt::Debug for Image {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Image")
-            .field("width", &self.image.width())
-            .field("height", &self.image.height())
+            .field("width", &self.mipmap.width())
+            .field("height", &self.mipmap.height())
+            .field("levels", &self.mipmap.level_count())
+            .field("sampler", &self.sampler)
+            .field("transform", &self.transform)
+            .field("tex_coord", &self.tex_coord)
             .finish()
     }
 }
 
 impl From<DynamicImage> for Image {
     fn from(image: DynamicImage) -> Self {
-        Image { image }
+        Image::new(image, ColorSpace::Srgb)
     }
 }
 
 impl HasColorValue for Image {
     fn value_at(&self, coords: TextureCoordinates, _: Point3) -> Color {
-        if self.image.height() == 0 {
+        if self.mipmap.height() == 0 || self.mipmap.width() == 0 {
             return Vec3::new(0.0, 1.0, 1.0);
         }
-        let mut coords = coords.clamp01();
-        // Flip V to image coordinates
-        coords.v = 1.0 - coords.v;
+        let coords = self.transform.apply(coords.set(self.tex_coord));
 
-        // TODO anti-aliasing
-        let i = ((coords.u * self.image.width() as f32) as u32).min(self.image.height() - 1);
-        let j = ((coords.v * self.image.height() as f32) as u32).min(self.image.height() - 1);
-        let pixel = self.image.get_pixel(i, j);
-        let color_scale = 1.0 / 255.0;
+        Color::from(self.mipmap.lookup(coords, &self.sampler).truncate())
+    }
 
-        Vec3::new(
-            pixel.0[0] as f32 * color_scale,
-            pixel.0[1] as f32 * color_scale,
-            pixel.0[2] as f32 * color_scale,
-        )
+    fn alpha_at(&self, coords: TextureCoordinates, _: Point3) -> f32 {
+        if self.mipmap.height() == 0 || self.mipmap.width() == 0 {
+            return 1.0;
+        }
+        let coords = self.transform.apply(coords.set(self.tex_coord));
+
+        self.mipmap.lookup(coords, &self.sampler).w
     }
 }
 
@@ -141,6 +479,9 @@ pub enum Texture {
     SolidColor(SolidColor),
     Checkerboard(Checkerboard),
     Image(Image),
+    Procedural(Procedural),
+    ColorRamp(ColorRamp),
+    Scaled(Scaled),
 }
 
 impl Texture {
@@ -148,7 +489,215 @@ impl Texture {
         Arc::new(Texture::SolidColor(SolidColor { albedo }))
     }
 
-    pub fn image(image: DynamicImage) -> Arc<Self> {
-        Arc::new(Texture::Image(Image::new(image)))
+    pub fn procedural(procedural: Procedural) -> Arc<Self> {
+        Arc::new(Texture::Procedural(procedural))
+    }
+
+    pub fn color_ramp(input: Arc<Texture>, stops: Vec<(f32, Arc<Texture>)>) -> Arc<Self> {
+        Arc::new(Texture::ColorRamp(ColorRamp::new(input, stops)))
+    }
+
+    /// `texture` multiplied by `factor`, or `texture` itself when the factor is one.
+    pub fn scaled(texture: Arc<Texture>, factor: Color) -> Arc<Self> {
+        if factor == Color::ONE {
+            texture
+        } else {
+            Arc::new(Texture::Scaled(Scaled { texture, factor }))
+        }
+    }
+
+    pub fn image(image: DynamicImage, color_space: ColorSpace) -> Arc<Self> {
+        Arc::new(Texture::Image(Image::new(image, color_space)))
+    }
+}
+
+/// Relative luminance of a linear Rec. 709 colour.
+fn luminance(color: Color) -> f32 {
+    color.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Which part of a colour texture a [`ScalarTexture`] reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+    Luminance,
+}
+
+/// A single-valued material input: either a constant, or one channel of a texture evaluated at
+/// the hit and multiplied by `factor`, following the glTF convention for factors and maps.
+#[derive(Debug, Clone)]
+pub enum ScalarTexture {
+    Constant(f32),
+    Texture {
+        texture: Arc<Texture>,
+        channel: Channel,
+        factor: f32,
+    },
+}
+
+impl ScalarTexture {
+    pub fn texture(texture: Arc<Texture>, channel: Channel, factor: f32) -> Self {
+        ScalarTexture::Texture {
+            texture,
+            channel,
+            factor,
+        }
+    }
+
+    pub fn value_at(&self, coords: TextureCoordinates, p: Point3) -> f32 {
+        match self {
+            ScalarTexture::Constant(value) => *value,
+            ScalarTexture::Texture {
+                texture,
+                channel,
+                factor,
+            } => {
+                let value = match channel {
+                    Channel::Red => texture.value_at(coords, p).x,
+                    Channel::Green => texture.value_at(coords, p).y,
+                    Channel::Blue => texture.value_at(coords, p).z,
+                    Channel::Alpha => texture.alpha_at(coords, p),
+                    Channel::Luminance => luminance(texture.value_at(coords, p)),
+                };
+                value * factor
+            }
+        }
+    }
+}
+
+impl From<f32> for ScalarTexture {
+    fn from(value: f32) -> Self {
+        ScalarTexture::Constant(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+    use std::sync::Arc;
+
+    use super::{
+        Channel, ColorSpace, HasColorValue, Pattern, ScalarTexture, Texture, TextureCoordinates,
+        TextureTransform, WrapMode,
+    };
+    use crate::noise::Perlin;
+    use crate::vec3::{Color, Point3};
+
+    #[test]
+    fn test_srgb_decoding() {
+        assert_eq!(ColorSpace::Srgb.to_linear(0.0), 0.0);
+        assert!((ColorSpace::Srgb.to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((ColorSpace::Srgb.to_linear(0.5) - 0.2140).abs() < 1e-4);
+        assert_eq!(ColorSpace::Linear.to_linear(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_wrap_modes() {
+        let wrapped: Vec<_> = (-3..6).map(|i| WrapMode::Repeat.apply(i, 3)).collect();
+        assert_eq!(wrapped, [0, 1, 2, 0, 1, 2, 0, 1, 2]);
+
+        let mirrored: Vec<_> = (-3..6)
+            .map(|i| WrapMode::MirroredRepeat.apply(i, 3))
+            .collect();
+        assert_eq!(mirrored, [2, 1, 0, 0, 1, 2, 2, 1, 0]);
+
+        let clamped: Vec<_> = (-2..5).map(|i| WrapMode::ClampToEdge.apply(i, 3)).collect();
+        assert_eq!(clamped, [0, 0, 0, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_texture_transform() {
+        let transform = TextureTransform {
+            offset: [0.5, 0.0],
+            rotation: FRAC_PI_2,
+            scale: [2.0, 1.0],
+        };
+        let coords = transform.apply(TextureCoordinates::new(1.0, 0.0));
+        assert!((coords.u - 0.5).abs() < 1e-6);
+        assert!((coords.v + 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_scalar_texture_channels() {
+        let texture = Texture::solid_color(Color::new(0.2, 0.4, 0.8));
+        let coords = TextureCoordinates::new(0.0, 0.0);
+        let p = Point3::ZERO;
+
+        assert_eq!(ScalarTexture::from(0.3).value_at(coords, p), 0.3);
+        let green = ScalarTexture::texture(texture.clone(), Channel::Green, 0.5);
+        assert!((green.value_at(coords, p) - 0.2).abs() < 1e-6);
+        let alpha = ScalarTexture::texture(texture, Channel::Alpha, 1.0);
+        assert_eq!(alpha.value_at(coords, p), 1.0);
+    }
+
+    #[test]
+    fn test_scaled_texture() {
+        let texture = Texture::solid_color(Color::new(0.2, 0.4, 0.8));
+        let coords = TextureCoordinates::new(0.0, 0.0);
+
+        let scaled = Texture::scaled(texture.clone(), Color::new(2.0, 1.0, 0.5));
+        assert_eq!(
+            scaled.value_at(coords, Point3::ZERO),
+            Color::new(0.4, 0.4, 0.4)
+        );
+        assert!(Arc::ptr_eq(
+            &Texture::scaled(texture.clone(), Color::ONE),
+            &texture
+        ));
+    }
+
+    #[test]
+    fn test_pattern_ranges() {
+        let perlin = Perlin::new(3);
+        let patterns = [
+            Pattern::Noise,
+            Pattern::Fbm { octaves: 5 },
+            Pattern::Turbulence { octaves: 5 },
+            Pattern::Marble {
+                frequency: 2.0,
+                distortion: 5.0,
+            },
+            Pattern::Wood {
+                rings: 4.0,
+                distortion: 0.5,
+            },
+            Pattern::Voronoi,
+        ];
+
+        for pattern in patterns {
+            for i in 0..500 {
+                let t = i as f32 * 0.291;
+                let value = pattern.evaluate(&perlin, Point3::new(t, -t * 0.7, t * 1.9 - 3.0));
+                assert!((0.0..=1.0).contains(&value), "{pattern:?} gave {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_color_ramp_lookup() {
+        let coords = TextureCoordinates::new(0.0, 0.0);
+        let ramp = |gray: f32| {
+            Texture::color_ramp(
+                Texture::solid_color(Color::splat(gray)),
+                vec![
+                    (0.25, Texture::solid_color(Color::new(1.0, 0.0, 0.0))),
+                    (0.75, Texture::solid_color(Color::new(0.0, 0.0, 1.0))),
+                ],
+            )
+            .value_at(coords, Point3::ZERO)
+        };
+
+        assert_eq!(ramp(0.0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(ramp(1.0), Color::new(0.0, 0.0, 1.0));
+        assert!((ramp(0.5) - Color::new(0.5, 0.0, 0.5)).abs().max_element() < 1e-5);
+        assert!(
+            (ramp(0.625) - Color::new(0.25, 0.0, 0.75))
+                .abs()
+                .max_element()
+                < 1e-5
+        );
     }
 }
@@ -23,6 +23,7 @@ pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
 pub trait Vec3Ext {
     fn near_zero(&self) -> bool;
     fn at(&self, axis: Axis) -> f32;
+    fn max_dimension(&self) -> usize;
 }
 
 impl Vec3Ext for Vec3 {
@@ -38,6 +39,20 @@ impl Vec3Ext for Vec3 {
             Axis::Z => self.z,
         }
     }
+
+    fn max_dimension(&self) -> usize {
+        if self.x > self.y {
+            if self.x > self.z {
+                0
+            } else {
+                2
+            }
+        } else if self.y > self.z {
+            1
+        } else {
+            2
+        }
+    }
 }
 
 pub mod random {
@@ -0,0 +1,250 @@
+//! Loading density grids for [`Grid`](crate::medium::Grid) media from files. Two formats are
+//! supported, both little endian:
+//!
+//! - Mitsuba's dense `.vol` format: the bytes `VOL` and the version `3`, an `i32` encoding (`1`
+//!   for `f32` and `3` for `u8` voxels), the resolution as three `i32`, an `i32` channel count
+//!   and the bounding box as six `f32`, followed by the voxels with x varying fastest. Grids
+//!   with several channels are averaged.
+//! - A sparse `.bvol` brick format in the spirit of NanoVDB's leaf nodes: the bytes `BVOL`, a
+//!   `u32` version `1`, the resolution as three `u32`, the bounding box as six `f32` and a `u32`
+//!   brick count, followed by that many bricks, each an origin of three `u32` voxel indices and
+//!   8×8×8 `f32` voxels with x varying fastest. Voxels outside of every brick are empty.
+//!
+//! OpenVDB files need to be converted to one of these, for example with a short `pyopenvdb`
+//! script copying the active voxels of the density grid into `.bvol` bricks.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use color_eyre::eyre::eyre;
+
+use crate::medium::DensityGrid;
+use crate::vec3::Point3;
+use crate::Result;
+
+/// Edge length of the bricks in `.bvol` files, in voxels.
+pub const BRICK_SIZE: usize = 8;
+
+/// Largest number of voxels a grid may have, 4 GiB worth of densities.
+pub const MAX_VOXELS: usize = 1 << 30;
+
+/// A density grid together with the box it was authored to fill, if the file records one.
+#[derive(Debug)]
+pub struct VolumeData {
+    pub grid: DensityGrid,
+    pub bounds: Option<(Point3, Point3)>,
+}
+
+pub fn load(path: impl AsRef<Path>) -> Result<VolumeData> {
+    let path = path.as_ref();
+    let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("vol") => read_vol(&mut reader),
+        Some("bvol") => read_bvol(&mut reader),
+        _ => Err(eyre!(
+            "unsupported volume {}, expected a .vol or .bvol file",
+            path.display()
+        )),
+    }
+}
+
+/// Reads a dense grid in Mitsuba's `.vol` format.
+pub fn read_vol(reader: &mut impl Read) -> Result<VolumeData> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != b"VOL\x03" {
+        return Err(eyre!("not a version 3 .vol file"));
+    }
+
+    let encoding = read_i32(reader)?;
+    let [nx, ny, nz] = read_resolution(reader, |r| {
+        let v = read_i32(r)?;
+        usize::try_from(v).map_err(|_| eyre!("negative .vol resolution {v}"))
+    })?;
+    let voxels = voxel_count([nx, ny, nz])?;
+    let channels = read_i32(reader)?.max(1) as usize;
+    let bounds = read_bounds(reader)?;
+
+    let values = (0..voxels)
+        .map(|_| {
+            let mut sum = 0.0;
+            for _ in 0..channels {
+                sum += match encoding {
+                    1 => read_f32(reader)?,
+                    3 => read_u8(reader)? as f32 / 255.0,
+                    _ => return Err(eyre!("unsupported .vol encoding {encoding}")),
+                };
+            }
+            Ok(sum / channels as f32)
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(VolumeData {
+        grid: DensityGrid::new(nx, ny, nz, values),
+        bounds: Some(bounds),
+    })
+}
+
+/// Reads a sparse grid of bricks in the `.bvol` format. Media only sample dense grids, so the
+/// bricks are expanded into one with empty voxels in between, taking memory for the whole
+/// resolution rather than just the bricks; grids of more than [`MAX_VOXELS`] are rejected.
+pub fn read_bvol(reader: &mut impl Read) -> Result<VolumeData> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    let version = read_u32(reader)?;
+    if &magic != b"BVOL" || version != 1 {
+        return Err(eyre!("not a version 1 .bvol file"));
+    }
+
+    let [nx, ny, nz] = read_resolution(reader, |r| read_u32(r).map(|v| v as usize))?;
+    let voxels = voxel_count([nx, ny, nz])?;
+    let bounds = read_bounds(reader)?;
+    let brick_count = read_u32(reader)?;
+
+    let mut values = vec![0.0; voxels];
+    let mut brick = vec![0.0; BRICK_SIZE * BRICK_SIZE * BRICK_SIZE];
+    for _ in 0..brick_count {
+        let [ox, oy, oz] = read_resolution(reader, |r| read_u32(r).map(|v| v as usize))?;
+        for value in &mut brick {
+            *value = read_f32(reader)?;
+        }
+
+        // bricks may hang over the edge of grids whose size isn't a multiple of the brick size
+        for z in 0..BRICK_SIZE.min(nz.saturating_sub(oz)) {
+            for y in 0..BRICK_SIZE.min(ny.saturating_sub(oy)) {
+                for x in 0..BRICK_SIZE.min(nx.saturating_sub(ox)) {
+                    values[((oz + z) * ny + oy + y) * nx + ox + x] =
+                        brick[(z * BRICK_SIZE + y) * BRICK_SIZE + x];
+                }
+            }
+        }
+    }
+
+    Ok(VolumeData {
+        grid: DensityGrid::new(nx, ny, nz, values),
+        bounds: Some(bounds),
+    })
+}
+
+/// The number of voxels of a grid with the given resolution, which must be positive and
+/// within [`MAX_VOXELS`].
+pub fn voxel_count([nx, ny, nz]: [usize; 3]) -> Result<usize> {
+    if nx == 0 || ny == 0 || nz == 0 {
+        return Err(eyre!("empty {nx}×{ny}×{nz} density grid"));
+    }
+
+    nx.checked_mul(ny)
+        .and_then(|n| n.checked_mul(nz))
+        .filter(|&n| n <= MAX_VOXELS)
+        .ok_or_else(|| eyre!("{nx}×{ny}×{nz} density grid exceeds {MAX_VOXELS} voxels"))
+}
+
+fn read_resolution<R: Read>(
+    reader: &mut R,
+    read: impl Fn(&mut R) -> Result<usize>,
+) -> Result<[usize; 3]> {
+    Ok([read(reader)?, read(reader)?, read(reader)?])
+}
+
+fn read_bounds(reader: &mut impl Read) -> Result<(Point3, Point3)> {
+    let mut read_point = || -> Result<Point3> {
+        Ok(Point3::new(
+            read_f32(reader)?,
+            read_f32(reader)?,
+            read_f32(reader)?,
+        ))
+    };
+    Ok((read_point()?, read_point()?))
+}
+
+fn read_u8(reader: &mut impl Read) -> Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i32(reader: &mut impl Read) -> Result<i32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_bvol, read_vol, BRICK_SIZE, MAX_VOXELS};
+
+    fn header(magic: &[u8], fields: &[[u8; 4]]) -> Vec<u8> {
+        let mut bytes = magic.to_vec();
+        for field in fields {
+            bytes.extend(field);
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_read_grids() {
+        let bounds = [0.0f32, 0.0, 0.0, 1.0, 2.0, 3.0].map(f32::to_le_bytes);
+        let mut vol = header(b"VOL\x03", &[1, 2, 1, 1, 1].map(|v: i32| v.to_le_bytes()));
+        vol.extend(bounds.concat());
+        vol.extend([0.25f32, 0.75].map(f32::to_le_bytes).concat());
+
+        let data = read_vol(&mut vol.as_slice()).unwrap();
+        assert_eq!((data.grid.nx, data.grid.ny, data.grid.nz), (2, 1, 1));
+        assert_eq!(data.grid.values, [0.25, 0.75]);
+        assert_eq!(data.bounds.unwrap().1.z, 3.0);
+
+        // a single brick at the far corner of a grid that only partially covers it
+        let mut bvol = header(b"BVOL", &[1, 10, 10, 10].map(u32::to_le_bytes));
+        bvol.extend(bounds.concat());
+        bvol.extend([1, 8, 8, 8].map(u32::to_le_bytes).concat());
+        bvol.extend(
+            (0..BRICK_SIZE.pow(3))
+                .map(|i| (i as f32).to_le_bytes())
+                .collect::<Vec<_>>()
+                .concat(),
+        );
+
+        let data = read_bvol(&mut bvol.as_slice()).unwrap();
+        let at = |x: usize, y: usize, z: usize| data.grid.values[(z * 10 + y) * 10 + x];
+        assert_eq!(at(0, 0, 0), 0.0);
+        assert_eq!(at(8, 8, 8), 0.0);
+        assert_eq!(at(9, 8, 8), 1.0);
+        assert_eq!(
+            at(9, 9, 9),
+            (BRICK_SIZE * BRICK_SIZE + BRICK_SIZE + 1) as f32
+        );
+    }
+
+    #[test]
+    fn test_reject_bad_resolutions() {
+        let bounds = [0.0f32; 6].map(f32::to_le_bytes).concat();
+        for resolution in [[0, 1, 1], [2, -1, 1]] {
+            let mut vol = header(b"VOL\x03", &[1].map(|v: i32| v.to_le_bytes()));
+            vol.extend(resolution.map(i32::to_le_bytes).concat());
+            vol.extend(1i32.to_le_bytes());
+            vol.extend(&bounds);
+            assert!(read_vol(&mut vol.as_slice()).is_err());
+        }
+
+        let side = (MAX_VOXELS as f64).cbrt() as u32 + 2;
+        for resolution in [[4, 0, 4], [side, side, side]] {
+            let mut bvol = header(b"BVOL", &[1].map(u32::to_le_bytes));
+            bvol.extend(resolution.map(u32::to_le_bytes).concat());
+            bvol.extend(&bounds);
+            bvol.extend(0u32.to_le_bytes());
+            assert!(read_bvol(&mut bvol.as_slice()).is_err());
+        }
+    }
+}